@@ -0,0 +1,870 @@
+// Command-line interface
+// Every render parameter that used to be edited in main.rs can be given as a flag;
+// `cargo run --release -- --help` lists them.
+
+use crate::modules::output::{OutputFormat, OUTPUT_FORMAT_NAMES};
+use crate::modules::camera_multithreading::{AdaptiveSampling, LightSampling, MisHeuristic, LIGHT_SAMPLING_NAMES, MIS_HEURISTIC_NAMES};
+use crate::modules::sampler::{SamplerKind, SAMPLER_NAMES};
+use crate::modules::tile::{CropWindow, TileOrder, TILE_ORDER_NAMES};
+use crate::modules::aov::{Aov, ALL_AOVS, AOV_NAMES};
+use crate::modules::filter::{PixelFilter, FILTER_NAMES};
+use crate::modules::color::TransferFunction;
+use crate::modules::tonemap::{ToneMapOperator, ToneMapping, TONE_MAP_NAMES};
+
+use clap::{value_parser, Arg, ArgAction, ArgMatches, Command};
+use std::io::IsTerminal;
+
+// process exit codes
+pub const EXIT_SCENE_ERROR: i32 = 1;
+pub const EXIT_INVALID_ARGUMENTS: i32 = 2; // as clap exits with
+pub const EXIT_OUTPUT_EXISTS: i32 = 3;
+pub const EXIT_WRITE_FAILED: i32 = 4;
+pub const EXIT_CANCELED: i32 = 5;
+pub const EXIT_CHECKPOINT_ERROR: i32 = 6;
+pub const EXIT_CROP_ERROR: i32 = 7;
+
+pub const OUTPUT_DIR: &str = "output/";
+pub const DEFAULT_FILE_NAME: &str = "test.jpg";
+
+// built-in scenes, selectable by name with --scene
+pub const SCENE_NAMES: [&str; 12] = [
+    "final", // default, test quality
+    "final_release",
+    "bouncing_spheres",
+    "checkered_spheres",
+    "earth",
+    "perlin_spheres",
+    "quads",
+    "simple_light",
+    "cornell_box",
+    "cornell_smoke",
+    "book2_final",
+    "book2_final_test",
+];
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum SceneChoice {
+    Builtin(String),
+    File(String), // *.toml scene description
+}
+
+impl SceneChoice {
+    pub fn name(&self) -> &str {
+        match self {
+            Self::Builtin(name) | Self::File(name) => name,
+        }
+    }
+}
+
+// What to do when the output file already exists
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OverwritePolicy {
+    Ask, // interactive confirmation on stdin (the desktop behaviour)
+    Overwrite,
+    Skip, // leave the existing file alone, exit successfully
+    Fail, // leave the existing file alone, exit with EXIT_OUTPUT_EXISTS
+}
+
+#[derive(Clone, Debug)]
+pub struct RenderOptions {
+    pub scene: SceneChoice,
+    pub output: String,
+    pub default_output: String,
+    pub format: OutputFormat,
+    pub quality: u8, // JPEG only
+    pub tone_mapping: ToneMapping, // 8/16-bit formats only
+    pub transfer: TransferFunction, // 8/16-bit formats only
+    pub release: bool,
+    pub interactive: bool, // allowed to read stdin
+    pub on_exists: OverwritePolicy,
+    pub image_width: Option<usize>,
+    pub aspect_ratio: Option<f64>,
+    pub sample_per_pixel: Option<usize>,
+    pub max_ray_depth: Option<usize>,
+    pub russian_roulette_depth: Option<Option<usize>>, // Some(None): --rr-depth off
+    pub light_sampling: Option<LightSampling>,
+    pub mis_heuristic: Option<MisHeuristic>,
+    pub spectral: bool, // false: as the scene says
+    pub threads: Option<usize>,
+    pub tile_size: Option<usize>,
+    pub tile_order: Option<TileOrder>,
+    pub seed: u64,
+    pub checkpoint: Option<String>,
+    pub checkpoint_interval: Option<f64>, // seconds
+    pub samples_per_pass: Option<usize>,
+    pub resume: bool, // start from the samples in the checkpoint file
+    pub adaptive_sampling: Option<AdaptiveSampling>,
+    pub sampler: Option<SamplerKind>,
+    pub filter: Option<PixelFilter>,
+    pub sample_heatmap: Option<String>,
+    pub crop: Option<CropWindow>,
+    pub crop_into: Option<String>, // earlier full render to paste the crop into
+    pub aovs: Vec<Aov>, // extra layers, each written next to the output
+    pub denoise: bool,
+    pub denoise_compare: Option<String>, // image of the noisy and the denoised render side by side
+}
+
+fn build_command() -> Command<'static> {
+    Command::new("ray_tracer")
+        .about("Ray Tracing in One Weekend / The Next Week renderer")
+        .arg(
+            Arg::new("file")
+                .value_name("FILE")
+                .help("Output file name inside output/ (kept for compatibility; see --output)")
+                .conflicts_with("output"),
+        )
+        .arg(
+            Arg::new("scene")
+                .short('s')
+                .long("scene")
+                .value_name("NAME|FILE")
+                .takes_value(true)
+                .help("Built-in scene name or a .toml scene file [default: final]")
+                .long_help(
+                    "Built-in scene name or a .toml scene file [default: final]\n\
+                     Built-in scenes: final, final_release, bouncing_spheres, checkered_spheres, earth, \
+                     perlin_spheres, quads, simple_light, cornell_box, cornell_smoke, book2_final, book2_final_test",
+                )
+                .value_parser(parse_scene_choice),
+        )
+        .arg(
+            Arg::new("width")
+                .short('w')
+                .long("width")
+                .value_name("PIXELS")
+                .takes_value(true)
+                .help("Image width, overrides the scene's camera")
+                .value_parser(value_parser!(u64).range(1..)),
+        )
+        .arg(
+            Arg::new("aspect-ratio")
+                .short('a')
+                .long("aspect-ratio")
+                .value_name("RATIO")
+                .takes_value(true)
+                .help("Width over height, as a number (1.5) or W:H (16:9)")
+                .value_parser(parse_aspect_ratio),
+        )
+        .arg(
+            Arg::new("spp")
+                .long("spp")
+                .value_name("N")
+                .takes_value(true)
+                .help("Samples per pixel")
+                .value_parser(value_parser!(u64).range(1..)),
+        )
+        .arg(
+            Arg::new("max-depth")
+                .short('d')
+                .long("max-depth")
+                .value_name("N")
+                .takes_value(true)
+                .help("Maximum number of ray bounces (a cap; Russian roulette ends most paths earlier)")
+                .value_parser(value_parser!(u64).range(1..)),
+        )
+        .arg(
+            Arg::new("rr-depth")
+                .long("rr-depth")
+                .value_name("N|off")
+                .takes_value(true)
+                .help("Bounces before Russian roulette may end a path, or off to follow every path to --max-depth [default: 3]")
+                .value_parser(parse_rr_depth),
+        )
+        .arg(
+            Arg::new("light-sampling")
+                .long("light-sampling")
+                .value_name("MODE")
+                .takes_value(true)
+                .help("How direct light is found [default: the scene's, or mis]")
+                .long_help(
+                    "How direct light is found [default: the scene's, or mis]\n\
+                     bsdf: only by paths that scatter into a light\n\
+                     light: next-event estimation, a shadow ray towards a point sampled on the lights at every bounce\n\
+                     mis: both, combined by multiple importance sampling",
+                )
+                .value_parser(LIGHT_SAMPLING_NAMES),
+        )
+        .arg(
+            Arg::new("mis-heuristic")
+                .long("mis-heuristic")
+                .value_name("NAME")
+                .takes_value(true)
+                .help("How --light-sampling mis weights the two strategies: balance or power [default: the scene's, or power]")
+                .value_parser(MIS_HEURISTIC_NAMES),
+        )
+        .arg(
+            Arg::new("spectral")
+                .long("spectral")
+                .action(ArgAction::SetTrue)
+                .help("Trace wavelengths instead of RGB, for dispersion in glass (slower) [default: the scene's, or RGB]"),
+        )
+        .arg(
+            Arg::new("threads")
+                .short('j')
+                .long("threads")
+                .value_name("N")
+                .takes_value(true)
+                .help("Number of render threads [default: number of CPUs]")
+                .value_parser(value_parser!(u64).range(1..)),
+        )
+        .arg(
+            Arg::new("tile-size")
+                .long("tile-size")
+                .value_name("PIXELS")
+                .takes_value(true)
+                .help("Side of the square tiles the threads render [default: 32]")
+                .value_parser(value_parser!(u64).range(1..)),
+        )
+        .arg(
+            Arg::new("tile-order")
+                .long("tile-order")
+                .value_name("ORDER")
+                .takes_value(true)
+                .help("Order the tiles are rendered in: scanline, spiral (from the centre) or hilbert [default: scanline]")
+                .value_parser(TILE_ORDER_NAMES),
+        )
+        .arg(
+            Arg::new("output")
+                .short('o')
+                .long("output")
+                .value_name("PATH")
+                .takes_value(true)
+                .help("Output image path [default: output/test.jpg]"),
+        )
+        .arg(
+            Arg::new("format")
+                .short('f')
+                .long("format")
+                .value_name("FORMAT")
+                .takes_value(true)
+                .help("Output image format [default: from the output extension]")
+                .long_help(
+                    "Output image format [default: from the output extension]\n\
+                     jpeg, png (8-bit), png16, ppm (plain ASCII): tone mapped, encoded with --transfer and clamped\n\
+                     hdr (Radiance), exr (OpenEXR float): linear radiance, unclamped",
+                )
+                .value_parser(OUTPUT_FORMAT_NAMES),
+        )
+        .arg(
+            Arg::new("quality")
+                .long("quality")
+                .value_name("1-100")
+                .takes_value(true)
+                .help("JPEG quality")
+                .value_parser(value_parser!(u8).range(1..=100))
+                .default_value("60"),
+        )
+        .arg(
+            Arg::new("tonemap")
+                .long("tonemap")
+                .value_name("OPERATOR")
+                .takes_value(true)
+                .help("Tone mapping operator for 8/16-bit output")
+                .long_help(
+                    "Tone mapping operator for 8/16-bit output\n\
+                     clamp: values above 1 turn white (the old behaviour)\n\
+                     reinhard: L / (1 + L) on luminance\n\
+                     reinhard-extended: like reinhard, but luminance --white maps to 1\n\
+                     aces: filmic curve (Narkowicz's ACES approximation)",
+                )
+                .value_parser(TONE_MAP_NAMES)
+                .default_value("clamp"),
+        )
+        .arg(
+            Arg::new("white")
+                .long("white")
+                .value_name("LUMINANCE")
+                .takes_value(true)
+                .requires("tonemap")
+                .help("White point of reinhard-extended, after exposure [default: brightest pixel]")
+                .value_parser(parse_positive),
+        )
+        .arg(
+            Arg::new("exposure")
+                .long("exposure")
+                .value_name("STOPS")
+                .takes_value(true)
+                .allow_hyphen_values(true)
+                .help("Exposure adjustment in stops (-1 halves the brightness), applied before tone mapping")
+                .value_parser(value_parser!(f64))
+                .default_value("0"),
+        )
+        .arg(
+            Arg::new("auto-exposure")
+                .long("auto-exposure")
+                .action(ArgAction::SetTrue)
+                .help("Expose the image's log-average luminance to middle grey; --exposure adjusts on top"),
+        )
+        .arg(
+            Arg::new("transfer")
+                .long("transfer")
+                .value_name("FUNCTION")
+                .takes_value(true)
+                .help("Transfer function of 8/16-bit output: srgb, linear or gamma<N> (e.g. gamma2.2)")
+                .value_parser(parse_transfer_function)
+                .default_value("srgb"),
+        )
+        .arg(
+            Arg::new("seed")
+                .long("seed")
+                .value_name("N")
+                .takes_value(true)
+                .help("Seed for the random parts of the scene (sphere layout, perlin noise) and of the render; the same seed gives the same image for any thread count")
+                .value_parser(value_parser!(u64))
+                .default_value("0"),
+        )
+        .arg(
+            Arg::new("checkpoint")
+                .long("checkpoint")
+                .value_name("FILE")
+                .takes_value(true)
+                .help("Save the accumulated samples to FILE every --checkpoint-interval seconds and at the end"),
+        )
+        .arg(
+            Arg::new("checkpoint-interval")
+                .long("checkpoint-interval")
+                .value_name("SECONDS")
+                .takes_value(true)
+                .requires("checkpoint")
+                .help("Minimum time between two checkpoints [default: 300]")
+                .value_parser(parse_positive),
+        )
+        .arg(
+            Arg::new("pass-spp")
+                .long("pass-spp")
+                .value_name("N")
+                .takes_value(true)
+                .help("With --checkpoint or --adaptive: samples per pixel in each pass [default: 16]")
+                .long_help(
+                    "With --checkpoint or --adaptive: samples per pixel in each pass [default: 16]\n\
+                     Checkpoints are only taken, and convergence only checked, between passes.",
+                )
+                .value_parser(value_parser!(u64).range(1..)),
+        )
+        .arg(
+            Arg::new("resume")
+                .long("resume")
+                .action(ArgAction::SetTrue)
+                .requires("checkpoint")
+                .help("Continue from the --checkpoint file; with a higher --spp, adds samples to a finished render")
+                .long_help(
+                    "Continue from the --checkpoint file; with a higher --spp, adds samples to a finished render.\n\
+                     The scene and camera options must be the same as in the run that wrote the checkpoint;\n\
+                     a checkpoint of another scene, size, --seed, --sampler, --filter or --spectral is refused.",
+                ),
+        )
+        .arg(
+            Arg::new("adaptive")
+                .long("adaptive")
+                .action(ArgAction::SetTrue)
+                .help("Adaptive sampling: stop sampling a pixel once its noise is below --noise-threshold; --spp is the maximum"),
+        )
+        .arg(
+            Arg::new("min-spp")
+                .long("min-spp")
+                .value_name("N")
+                .takes_value(true)
+                .requires("adaptive")
+                .help("Samples every pixel gets before its noise is estimated")
+                .value_parser(value_parser!(u64).range(2..))
+                .default_value("16"),
+        )
+        .arg(
+            Arg::new("noise-threshold")
+                .long("noise-threshold")
+                .value_name("T")
+                .takes_value(true)
+                .requires("adaptive")
+                .help("Standard error of a pixel's mean luminance, relative to that luminance, that counts as converged")
+                .value_parser(parse_positive)
+                .default_value("0.01"),
+        )
+        .arg(
+            Arg::new("sampler")
+                .long("sampler")
+                .value_name("NAME")
+                .takes_value(true)
+                .help("Sample generator [default: sobol]")
+                .long_help(
+                    "Sample generator [default: sobol]\n\
+                     independent: white noise\n\
+                     stratified: jittered strata\n\
+                     halton: Halton sequence, randomized per pixel\n\
+                     sobol: Owen-scrambled Sobol' sequence",
+                )
+                .value_parser(SAMPLER_NAMES),
+        )
+        .arg(
+            Arg::new("filter")
+                .long("filter")
+                .value_name("NAME")
+                .takes_value(true)
+                .help("Pixel reconstruction filter [default: box]")
+                .long_help(
+                    "Pixel reconstruction filter [default: box]\n\
+                     box (radius 0.5): the mean of the pixel's samples\n\
+                     tent (radius 1), gaussian (1.5), mitchell (Mitchell-Netravali, B = C = 1/3, radius 2), \
+                     lanczos (radius 3): every sample also counts towards the neighbouring pixels",
+                )
+                .value_parser(FILTER_NAMES),
+        )
+        .arg(
+            Arg::new("filter-radius")
+                .long("filter-radius")
+                .value_name("PIXELS")
+                .takes_value(true)
+                .requires("filter")
+                .help("Radius of the --filter, at least 0.5")
+                .value_parser(parse_filter_radius),
+        )
+        .arg(
+            Arg::new("sample-heatmap")
+                .long("sample-heatmap")
+                .value_name("PATH")
+                .takes_value(true)
+                .help("Also write an image of the samples taken per pixel (black: fewest, white: most)"),
+        )
+        .arg(
+            Arg::new("aov")
+                .long("aov")
+                .value_name("LIST")
+                .takes_value(true)
+                .help("Also write these per-pixel layers, comma-separated, or all")
+                .long_help(
+                    "Also write these per-pixel layers, comma-separated, or all\n\
+                     albedo, normal, depth, position, uv, object_id, material_id: what each pixel sees first. \
+                     Each goes next to the output, e.g. output/test.albedo.jpg. .hdr/.exr get the raw values; \
+                     8/16-bit images a viewable version (depth and position stretched over their range, IDs in false colour).",
+                )
+                .value_parser(parse_aov_list),
+        )
+        .arg(
+            Arg::new("denoise")
+                .long("denoise")
+                .action(ArgAction::SetTrue)
+                .help("Denoise the render, guided by its albedo, normal and depth")
+                .long_help(
+                    "Denoise the render, guided by its albedo, normal and depth\n\
+                     Edge-avoiding a-trous wavelet filter; the guide layers are rendered along with the image \
+                     (they are only written with --aov). Checkpoints keep the noisy samples.",
+                ),
+        )
+        .arg(
+            Arg::new("denoise-compare")
+                .long("denoise-compare")
+                .value_name("PATH")
+                .takes_value(true)
+                .requires("denoise")
+                .help("Also write the noisy (left) and the denoised (right) render side by side"),
+        )
+        .arg(
+            Arg::new("crop")
+                .long("crop")
+                .value_name("X0,Y0,X1,Y1")
+                .takes_value(true)
+                .help("Only render this rectangle of the frame and write just the crop")
+                .long_help(
+                    "Only render this rectangle of the frame and write just the crop\n\
+                     Corners in pixels (e.g. 100,50,300,200; X1,Y1 excluded) or as fractions of the frame \
+                     (e.g. 0.5,0,1.0,0.5). The camera keeps the full-frame geometry.",
+                )
+                .value_parser(parse_crop_window),
+        )
+        .arg(
+            Arg::new("crop-into")
+                .long("crop-into")
+                .value_name("FILE")
+                .takes_value(true)
+                .requires("crop")
+                .help("Paste the crop into this earlier full render and write the whole frame")
+                .long_help(
+                    "Paste the crop into this earlier full render and write the whole frame\n\
+                     8/16-bit images are decoded with --transfer and their pixels are written back unchanged; \
+                     the crop is tone mapped on its own, so --auto-exposure and reinhard-extended without --white \
+                     only see the crop. Use .hdr/.exr renders for .hdr/.exr output.",
+                ),
+        )
+        .arg(
+            Arg::new("release")
+                .long("release")
+                .action(ArgAction::SetTrue)
+                .help("Release render: final scene at full quality, no confirmation before writing"),
+        )
+        .arg(
+            Arg::new("batch")
+                .long("batch")
+                .action(ArgAction::SetTrue)
+                .help("Never read stdin; implied when stdin is not a terminal"),
+        )
+        .arg(
+            Arg::new("on-exists")
+                .long("on-exists")
+                .value_name("POLICY")
+                .takes_value(true)
+                .help("What to do if the output file exists [default: ask, or fail in batch mode]")
+                .value_parser(["ask", "overwrite", "skip", "fail"]),
+        )
+}
+
+fn parse_scene_choice(s: &str) -> Result<SceneChoice, String> {
+    if s.ends_with(".toml") {
+        Ok(SceneChoice::File(s.to_string()))
+    } else if SCENE_NAMES.contains(&s) {
+        Ok(SceneChoice::Builtin(s.to_string()))
+    } else {
+        Err(format!("unknown scene (expected a .toml file or one of: {})", SCENE_NAMES.join(", ")))
+    }
+}
+
+fn parse_aspect_ratio(s: &str) -> Result<f64, String> {
+    let ratio = match s.split_once(':') {
+        Some((w, h)) => {
+            let w: f64 = w.trim().parse().map_err(|_| format!("invalid width in \"{}\"", s))?;
+            let h: f64 = h.trim().parse().map_err(|_| format!("invalid height in \"{}\"", s))?;
+            w / h
+        }
+        None => s.trim().parse().map_err(|_| format!("\"{}\" is not a number or W:H", s))?,
+    };
+    if ratio.is_finite() && ratio > 0.0 {
+        Ok(ratio)
+    } else {
+        Err(format!("aspect ratio must be positive, got \"{}\"", s))
+    }
+}
+
+fn parse_positive(s: &str) -> Result<f64, String> {
+    match s.trim().parse::<f64>() {
+        Ok(v) if v.is_finite() && v > 0.0 => Ok(v),
+        _ => Err(format!("expected a positive number, got \"{}\"", s)),
+    }
+}
+
+fn parse_transfer_function(s: &str) -> Result<TransferFunction, String> {
+    TransferFunction::from_name(s).ok_or_else(|| format!("expected srgb, linear or gamma<N>, got \"{}\"", s))
+}
+
+fn parse_rr_depth(s: &str) -> Result<Option<usize>, String> {
+    match s.trim() {
+        "off" => Ok(None),
+        n => n.parse::<usize>().map(Some).map_err(|_| format!("expected a number of bounces or off, got \"{}\"", s)),
+    }
+}
+
+fn parse_filter_radius(s: &str) -> Result<f64, String> {
+    match s.trim().parse::<f64>() {
+        Ok(v) if v.is_finite() && v >= 0.5 => Ok(v),
+        _ => Err(format!("expected a number of pixels >= 0.5, got \"{}\"", s)),
+    }
+}
+
+fn parse_crop_window(s: &str) -> Result<CropWindow, String> {
+    CropWindow::parse(s)
+}
+
+fn parse_aov_list(s: &str) -> Result<Vec<Aov>, String> {
+    if s.trim() == "all" {
+        return Ok(ALL_AOVS.to_vec());
+    }
+    let mut aovs = Vec::new();
+    for name in s.split(',').map(|name| name.trim()) {
+        let aov = Aov::from_name(name).ok_or_else(|| format!("unknown AOV \"{}\" (expected all or some of: {})", name, AOV_NAMES.join(", ")))?;
+        if !aovs.contains(&aov) {
+            aovs.push(aov);
+        }
+    }
+    Ok(aovs)
+}
+
+fn get_usize(matches: &ArgMatches, name: &str) -> Option<usize> {
+    matches.get_one::<u64>(name).map(|v| *v as usize)
+}
+
+fn from_matches(matches: &ArgMatches, stdin_is_terminal: bool) -> Result<RenderOptions, String> {
+    let release = matches.get_flag("release");
+    let interactive = stdin_is_terminal && !matches.get_flag("batch");
+    let on_exists = match matches.get_one::<String>("on-exists").map(|s| s.as_str()) {
+        Some("ask") if !interactive => return Err("--on-exists ask needs an interactive terminal".to_string()),
+        Some("ask") => OverwritePolicy::Ask,
+        Some("overwrite") => OverwritePolicy::Overwrite,
+        Some("skip") => OverwritePolicy::Skip,
+        Some("fail") => OverwritePolicy::Fail,
+        _ => if interactive { OverwritePolicy::Ask } else { OverwritePolicy::Fail },
+    };
+    let scene = matches.get_one::<SceneChoice>("scene").cloned().unwrap_or_else(|| {
+        SceneChoice::Builtin(if release { "final_release" } else { "final" }.to_string())
+    });
+
+    let default_output = format!("{}{}", OUTPUT_DIR, DEFAULT_FILE_NAME);
+    let output = if let Some(path) = matches.get_one::<String>("output") {
+        path.clone()
+    } else if let Some(file) = matches.get_one::<String>("file") {
+        format!("{}{}", OUTPUT_DIR, file)
+    } else {
+        default_output.clone()
+    };
+
+    let format = match matches.get_one::<String>("format") {
+        Some(name) => OutputFormat::from_name(name).unwrap(),
+        None => OutputFormat::from_extension(&output).ok_or_else(|| {
+            format!("cannot tell the format of \"{}\" from its extension; use --format", output)
+        })?,
+    };
+
+    let image_width = get_usize(matches, "width");
+    let aspect_ratio = matches.get_one::<f64>("aspect-ratio").copied();
+    if let (Some(width), Some(ratio)) = (image_width, aspect_ratio) {
+        if ((width as f64 / ratio) as usize) < 1 {
+            return Err(format!("--width {} at --aspect-ratio {} is less than a pixel high", width, ratio));
+        }
+    }
+
+    let adaptive = matches.get_flag("adaptive");
+    if matches.contains_id("pass-spp") && !matches.contains_id("checkpoint") && !adaptive {
+        return Err("--pass-spp needs --checkpoint or --adaptive".to_string());
+    }
+    let adaptive_sampling = if adaptive {
+        Some(AdaptiveSampling {
+            min_samples: get_usize(matches, "min-spp").unwrap(),
+            threshold: *matches.get_one::<f64>("noise-threshold").unwrap(),
+        })
+    } else {
+        None
+    };
+
+    let white = matches.get_one::<f64>("white").copied();
+    let operator = matches.get_one::<String>("tonemap").unwrap();
+    if white.is_some() && operator != "reinhard-extended" {
+        return Err("--white only applies to --tonemap reinhard-extended".to_string());
+    }
+    let exposure = *matches.get_one::<f64>("exposure").unwrap();
+    if !exposure.is_finite() {
+        return Err(format!("invalid exposure {}", exposure));
+    }
+    let tone_mapping = ToneMapping {
+        operator: ToneMapOperator::from_name(operator, white).unwrap(),
+        exposure,
+        auto_exposure: matches.get_flag("auto-exposure"),
+    };
+
+    Ok(RenderOptions {
+        scene,
+        output,
+        default_output,
+        format,
+        quality: *matches.get_one::<u8>("quality").unwrap(),
+        tone_mapping,
+        transfer: *matches.get_one::<TransferFunction>("transfer").unwrap(),
+        release,
+        interactive,
+        on_exists,
+        image_width,
+        aspect_ratio,
+        sample_per_pixel: get_usize(matches, "spp"),
+        max_ray_depth: get_usize(matches, "max-depth"),
+        russian_roulette_depth: matches.get_one::<Option<usize>>("rr-depth").copied(),
+        light_sampling: matches.get_one::<String>("light-sampling").map(|name| LightSampling::from_name(name).unwrap()),
+        mis_heuristic: matches.get_one::<String>("mis-heuristic").map(|name| MisHeuristic::from_name(name).unwrap()),
+        spectral: matches.get_flag("spectral"),
+        threads: get_usize(matches, "threads"),
+        tile_size: get_usize(matches, "tile-size"),
+        tile_order: matches.get_one::<String>("tile-order").map(|name| TileOrder::from_name(name).unwrap()),
+        seed: *matches.get_one::<u64>("seed").unwrap(),
+        checkpoint: matches.get_one::<String>("checkpoint").cloned(),
+        checkpoint_interval: matches.get_one::<f64>("checkpoint-interval").copied(),
+        samples_per_pass: get_usize(matches, "pass-spp"),
+        resume: matches.get_flag("resume"),
+        adaptive_sampling,
+        sampler: matches.get_one::<String>("sampler").map(|name| SamplerKind::from_name(name).unwrap()),
+        filter: matches.get_one::<String>("filter")
+            .map(|name| PixelFilter::from_name(name, matches.get_one::<f64>("filter-radius").copied()).unwrap()),
+        sample_heatmap: matches.get_one::<String>("sample-heatmap").cloned(),
+        crop: matches.get_one::<CropWindow>("crop").copied(),
+        crop_into: matches.get_one::<String>("crop-into").cloned(),
+        aovs: matches.get_one::<Vec<Aov>>("aov").cloned().unwrap_or_default(),
+        denoise: matches.get_flag("denoise"),
+        denoise_compare: matches.get_one::<String>("denoise-compare").cloned(),
+    })
+}
+
+// Parse the process arguments; prints usage and exits on --help or invalid input.
+pub fn parse_args() -> RenderOptions {
+    let mut command = build_command();
+    let matches = command.get_matches_mut();
+    match from_matches(&matches, std::io::stdin().is_terminal()) {
+        Ok(opts) => opts,
+        Err(msg) => command.error(clap::ErrorKind::ArgumentConflict, msg).exit(),
+    }
+}
+
+pub fn try_parse_args_from<I, T>(args: I, stdin_is_terminal: bool) -> Result<RenderOptions, String>
+where
+    I: IntoIterator<Item = T>,
+    T: Into<std::ffi::OsString> + Clone,
+{
+    let matches = build_command().try_get_matches_from(args).map_err(|err| err.to_string())?;
+    from_matches(&matches, stdin_is_terminal)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_defaults() {
+        let opts = try_parse_args_from(["ray_tracer"], true).unwrap();
+        assert_eq!(opts.scene, SceneChoice::Builtin("final".to_string()));
+        assert_eq!(opts.output, "output/test.jpg");
+        assert_eq!(opts.format, OutputFormat::Jpeg);
+        assert_eq!(opts.quality, 60);
+        assert_eq!(opts.image_width, None);
+        assert_eq!(opts.tone_mapping, ToneMapping::default());
+        assert_eq!(opts.transfer, TransferFunction::Srgb);
+        assert_eq!(opts.seed, 0);
+    }
+
+    #[test]
+    fn test_flags() {
+        let opts = try_parse_args_from([
+            "ray_tracer", "-s", "cornell_box", "-w", "300", "-a", "16:9", "--spp", "8",
+            "-d", "5", "-j", "4", "-o", "out.png", "-f", "png", "--seed", "7", "--tile-size", "16", "--tile-order", "hilbert",
+        ], true).unwrap();
+        assert_eq!(opts.scene, SceneChoice::Builtin("cornell_box".to_string()));
+        assert_eq!(opts.image_width, Some(300));
+        assert!((opts.aspect_ratio.unwrap() - 16.0 / 9.0).abs() < 1e-12);
+        assert_eq!(opts.sample_per_pixel, Some(8));
+        assert_eq!(opts.max_ray_depth, Some(5));
+        assert_eq!(opts.russian_roulette_depth, None);
+        assert_eq!(opts.threads, Some(4));
+        assert_eq!(opts.tile_size, Some(16));
+        assert_eq!(opts.tile_order, Some(TileOrder::Hilbert));
+        assert_eq!(opts.crop, None);
+        assert_eq!(opts.output, "out.png");
+        assert_eq!(opts.format, OutputFormat::Png);
+        assert_eq!(opts.seed, 7);
+
+        for (file, format) in [("a.PNG", OutputFormat::Png), ("a.ppm", OutputFormat::Ppm), ("a.hdr", OutputFormat::Hdr), ("a.exr", OutputFormat::Exr)] {
+            let opts = try_parse_args_from(["ray_tracer", "-o", file], true).unwrap();
+            assert_eq!(opts.format, format);
+        }
+        let opts = try_parse_args_from(["ray_tracer", "-o", "a.png", "-f", "png16"], true).unwrap();
+        assert_eq!(opts.format, OutputFormat::Png16);
+
+        let opts = try_parse_args_from(["ray_tracer", "--crop", "0.25,0.25,0.75,0.5", "--crop-into", "full.png"], true).unwrap();
+        assert_eq!(opts.crop, Some(CropWindow::Fraction { x_min: 0.25, y_min: 0.25, x_max: 0.75, y_max: 0.5 }));
+        assert_eq!(opts.crop_into.as_deref(), Some("full.png"));
+
+        let opts = try_parse_args_from(["ray_tracer", "--aov", "normal, albedo,normal"], true).unwrap();
+        assert_eq!(opts.aovs, vec![Aov::Normal, Aov::Albedo]);
+        let opts = try_parse_args_from(["ray_tracer", "--aov", "all"], true).unwrap();
+        assert_eq!(opts.aovs.len(), AOV_NAMES.len());
+        assert!(!opts.denoise);
+
+        let opts = try_parse_args_from(["ray_tracer", "--denoise", "--denoise-compare", "compare.png"], true).unwrap();
+        assert!(opts.denoise);
+        assert_eq!(opts.denoise_compare.as_deref(), Some("compare.png"));
+
+        let opts = try_parse_args_from(["ray_tracer", "release.jpg", "--release"], true).unwrap();
+        assert_eq!(opts.scene, SceneChoice::Builtin("final_release".to_string()));
+        assert_eq!(opts.output, "output/release.jpg");
+
+        let opts = try_parse_args_from([
+            "ray_tracer", "--tonemap", "reinhard-extended", "--white", "4", "--exposure", "-1.5", "--auto-exposure",
+        ], true).unwrap();
+        assert_eq!(opts.tone_mapping, ToneMapping {
+            operator: ToneMapOperator::ReinhardExtended { white: Some(4.0) },
+            exposure: -1.5,
+            auto_exposure: true,
+        });
+
+        let opts = try_parse_args_from(["ray_tracer", "--transfer", "gamma2"], true).unwrap();
+        assert_eq!(opts.transfer, TransferFunction::Gamma(2.0));
+
+        let opts = try_parse_args_from([
+            "ray_tracer", "--checkpoint", "render.ckpt", "--checkpoint-interval", "60", "--pass-spp", "8", "--resume",
+        ], true).unwrap();
+        assert_eq!(opts.checkpoint.as_deref(), Some("render.ckpt"));
+        assert_eq!(opts.checkpoint_interval, Some(60.0));
+        assert_eq!(opts.samples_per_pass, Some(8));
+        assert!(opts.resume);
+        assert_eq!(opts.adaptive_sampling, None);
+
+        let opts = try_parse_args_from([
+            "ray_tracer", "--adaptive", "--min-spp", "8", "--pass-spp", "4", "--sample-heatmap", "spp.png",
+        ], true).unwrap();
+        assert_eq!(opts.adaptive_sampling, Some(AdaptiveSampling { min_samples: 8, threshold: 0.01 }));
+        assert_eq!(opts.samples_per_pass, Some(4));
+        assert_eq!(opts.sample_heatmap.as_deref(), Some("spp.png"));
+
+        let opts = try_parse_args_from(["ray_tracer", "--sampler", "halton"], true).unwrap();
+        assert_eq!(opts.sampler, Some(SamplerKind::Halton));
+        assert_eq!(opts.filter, None);
+
+        let opts = try_parse_args_from(["ray_tracer", "--rr-depth", "off"], true).unwrap();
+        assert_eq!(opts.russian_roulette_depth, Some(None));
+        let opts = try_parse_args_from(["ray_tracer", "--rr-depth", "5"], true).unwrap();
+        assert_eq!(opts.russian_roulette_depth, Some(Some(5)));
+        assert_eq!(opts.light_sampling, None);
+        let opts = try_parse_args_from(["ray_tracer", "--light-sampling", "bsdf"], true).unwrap();
+        assert_eq!(opts.light_sampling, Some(LightSampling::Bsdf));
+        let opts = try_parse_args_from(["ray_tracer", "--light-sampling", "mis", "--mis-heuristic", "balance"], true).unwrap();
+        assert_eq!((opts.light_sampling, opts.mis_heuristic), (Some(LightSampling::Mis), Some(MisHeuristic::Balance)));
+        assert!(!opts.spectral);
+        assert!(try_parse_args_from(["ray_tracer", "--spectral"], true).unwrap().spectral);
+
+        let opts = try_parse_args_from(["ray_tracer", "--filter", "mitchell", "--filter-radius", "1.5"], true).unwrap();
+        assert_eq!(opts.filter, Some(PixelFilter::Mitchell { radius: 1.5, b: 1.0 / 3.0, c: 1.0 / 3.0 }));
+    }
+
+    #[test]
+    fn test_validation() {
+        for args in [
+            vec!["ray_tracer", "--width", "0"],
+            vec!["ray_tracer", "--spp", "-3"],
+            vec!["ray_tracer", "--aspect-ratio", "16:0"],
+            vec!["ray_tracer", "--width", "1", "--aspect-ratio", "16:9"],
+            vec!["ray_tracer", "--scene", "nonexistent"],
+            vec!["ray_tracer", "--format", "bmp"],
+            vec!["ray_tracer", "-o", "image.bmp"],
+            vec!["ray_tracer", "a.jpg", "--output", "b.jpg"],
+            vec!["ray_tracer", "--tonemap", "filmic"],
+            vec!["ray_tracer", "--transfer", "gamma-1"],
+            vec!["ray_tracer", "--resume"],
+            vec!["ray_tracer", "--pass-spp", "4"],
+            vec!["ray_tracer", "--min-spp", "4"],
+            vec!["ray_tracer", "--adaptive", "--min-spp", "1"],
+            vec!["ray_tracer", "--sampler", "random"],
+            vec!["ray_tracer", "--tonemap", "aces", "--white", "2"],
+            vec!["ray_tracer", "--tonemap", "reinhard-extended", "--white", "0"],
+            vec!["ray_tracer", "--crop", "0,0,10"],
+            vec!["ray_tracer", "--crop-into", "full.png"],
+            vec!["ray_tracer", "--aov", "albedo,speed"],
+            vec!["ray_tracer", "--denoise-compare", "compare.png"],
+            vec!["ray_tracer", "--filter", "sinc"],
+            vec!["ray_tracer", "--rr-depth", "-1"],
+            vec!["ray_tracer", "--light-sampling", "area"],
+            vec!["ray_tracer", "--mis-heuristic", "maximum"],
+            vec!["ray_tracer", "--filter", "tent", "--filter-radius", "0.25"],
+            vec!["ray_tracer", "--filter-radius", "2"],
+        ] {
+            assert!(try_parse_args_from(&args, true).is_err(), "{:?} should be rejected", args);
+        }
+    }
+
+    #[test]
+    fn test_batch_mode() {
+        let opts = try_parse_args_from(["ray_tracer"], true).unwrap();
+        assert!(opts.interactive);
+        assert_eq!(opts.on_exists, OverwritePolicy::Ask);
+
+        for (args, terminal) in [(vec!["ray_tracer", "--batch"], true), (vec!["ray_tracer"], false)] {
+            let opts = try_parse_args_from(&args, terminal).unwrap();
+            assert!(!opts.interactive);
+            assert_eq!(opts.on_exists, OverwritePolicy::Fail);
+        }
+
+        let opts = try_parse_args_from(["ray_tracer", "--on-exists", "skip"], false).unwrap();
+        assert_eq!(opts.on_exists, OverwritePolicy::Skip);
+        assert!(try_parse_args_from(["ray_tracer", "--batch", "--on-exists", "ask"], true).is_err());
+    }
+}
@@ -4,75 +4,83 @@
 mod modules;
 use modules::*;
 
+mod cli;
+use cli::*;
+
 use color::ColorType;
 
 // standard library
-use image::{ImageBuffer, RgbImage}; //接收render传回来的图片，在main中文件输出
+use image::{ImageBuffer, RgbImage};
 use indicatif::{ProgressBar, ProgressStyle};
 use nalgebra::center;
 use nalgebra::Point;
 use std::fs::File;
-use std::env;
 use std::io;
+use std::path::Path;
 
 
 // anxilliary part
 
-
-// return (path, file_name, default_file_name, quality)
-fn init_prompt() -> (String, String, String, u8, bool) {
-    let path: String = "output/".into();
-    let default_file_name: String = "test.jpg".into();
-
-    let mut file_name: String = default_file_name.clone();
-
-    let quality = 60 as u8;
-
-    let mut release_flag = false;
-
-
-
-    let args: Vec<String> = env::args().collect();
-
-    println!("[Ray Tracer]");
-    if args.len() < 2 {
-        println!("Info: No output file specified, using default file path: \"{}{}\"", path, file_name);
-    } else {
-        file_name = args[1].clone();
-        println!("Info: Output file specified as \"{}{}\"", path, file_name);
-        if args.len() > 2 && args[2].clone() == "--release" {
-            release_flag = true
+// Decide, before spending time on the render, whether the output may be written. Returns the exit code to stop with.
+fn check_output_target(opts: &RenderOptions) -> Option<i32> {
+    if !Path::new(&opts.output).exists() {
+        return None;
+    }
+    match opts.on_exists {
+        OverwritePolicy::Skip => {
+            println!("Info: \"{}\" exists; skipped.", opts.output);
+            Some(0)
+        }
+        OverwritePolicy::Fail => {
+            eprintln!("Error: \"{}\" exists (use --on-exists overwrite or skip).", opts.output);
+            Some(EXIT_OUTPUT_EXISTS)
         }
+        OverwritePolicy::Ask | OverwritePolicy::Overwrite => None,
     }
-    (path, file_name, default_file_name, quality, release_flag)
 }
 
-fn tail_process(img: RgbImage, parameters: (String, String, String, u8, bool), author: &str) {
-    let (path, mut file_name, default_file_name, quality, release_flag) = parameters;
+// Returns the process exit code.
+fn tail_process(img: FrameBuffer, opts: &RenderOptions, author: &str) -> i32 {
+    let mut file_name = opts.output.clone();
     
-    println!("Ouput image as \"{}\"\n Author: {}\n Is release? {}", path.clone() + &file_name, author, release_flag);
-    let output_image: image::DynamicImage = image::DynamicImage::ImageRgb8(img);
+    println!("Ouput image as \"{}\" ({:?})\n Author: {}\n Is release? {}", file_name, opts.format, author, opts.release);
 
+    if opts.on_exists != OverwritePolicy::Ask {
+        if let Some(code) = check_output_target(opts) { // the file may have appeared while rendering
+            return code;
+        }
+        return match write_image(&img, &file_name, opts.format, opts.quality, &opts.tone_mapping, opts.transfer) {
+            Ok(_) => {
+                println!("Render finished with success.");
+                0
+            }
+            Err(err) => {
+                eprintln!("Error: {}", err);
+                EXIT_WRITE_FAILED
+            }
+        };
+    }
 
-    let confirmation_flag = get_output_confirmation(&mut file_name, &default_file_name, release_flag);
+    let confirmation_flag = get_output_confirmation(&mut file_name, &opts.default_output, opts.release);
 
     if confirmation_flag == false {
         println!("Canceled");
-        return ();
+        return EXIT_CANCELED;
     }
 
-    let mut output_file: File = File::create(path.clone() + &file_name).unwrap();
-    while true {
-        match output_image.write_to(&mut output_file, image::ImageOutputFormat::Jpeg(quality)) {
+    loop {
+        match write_image(&img, &file_name, opts.format, opts.quality, &opts.tone_mapping, opts.transfer) {
             Ok(_) => { 
                 println!("Render finished with success.");
-                break; 
+                return 0;
             }
-            Err(_) => {
-                println!("Outputting image fails. \n Please enter another file name.");
+            Err(err) => {
+                println!("Outputting image fails ({}). \n Please enter another file name.", err);
                 let mut input: String = String::default();
-                let _ = io::stdin().read_line(&mut input);
-                output_file = File::create(path.clone() + &input).unwrap();
+                if io::stdin().read_line(&mut input).unwrap_or(0) == 0 { // stdin closed
+                    return EXIT_WRITE_FAILED;
+                }
+                file_name = format!("{}{}", OUTPUT_DIR, input.trim());
             }
         }
     }
@@ -1130,45 +1138,214 @@ fn build_final_world() -> Object {
 
 
 
+fn build_builtin_scene(name: &str) -> (Camera, Object) {
+    match name {
+        "final" => (build_final_camera(400, 2000, 10), build_final_world()),
+        "final_release" => (build_final_camera(1200, 5000, 40), build_final_world()),
+        "bouncing_spheres" => (build_camera_1(), build_world_1()),
+        "checkered_spheres" => (build_camera_2(), build_world_2()),
+        "earth" => (build_camera_3(), build_world_3()),
+        "perlin_spheres" => (build_camera_4(), build_world_4()),
+        "quads" => (build_camera_5(), build_world_5()),
+        "simple_light" => (build_camera_6(), build_world_6()),
+        "cornell_box" => (build_camera_7(), build_world_7()),
+        "cornell_smoke" => (build_camera_8(), build_world_8()),
+        "book2_final" => (build_camera_9(800, 10000, 40), build_world_9()),
+        "book2_final_test" => (build_camera_9(400, 2000, 10), build_world_9()),
+        _ => panic!("Not matched"),
+    }
+}
+
+// command-line overrides of the scene's camera
+fn apply_overrides(cam: Camera, opts: &RenderOptions) -> Camera {
+    let (light_sampling, mis_heuristic, spectral) = (cam.light_sampling, cam.mis_heuristic, cam.spectral);
+    let mut cam = Camera::new(
+        opts.aspect_ratio.unwrap_or(cam.aspect_ratio),
+        opts.image_width.unwrap_or(cam.image_width),
+        opts.sample_per_pixel.unwrap_or(cam.sample_per_pixel),
+        opts.max_ray_depth.unwrap_or(cam.max_ray_depth),
+        cam.vfov,
+        cam.lookfrom,
+        cam.lookat,
+        cam.vup,
+        cam.defocus_angle,
+        cam.focus_dist,
+        cam.background
+    );
+    if let Some(rr_depth) = opts.russian_roulette_depth {
+        cam.russian_roulette_depth = rr_depth;
+    }
+    cam.light_sampling = opts.light_sampling.unwrap_or(light_sampling);
+    cam.mis_heuristic = opts.mis_heuristic.unwrap_or(mis_heuristic);
+    cam.spectral = opts.spectral || spectral;
+    if let Some(threads) = opts.threads {
+        cam.thread_limit = threads;
+    }
+    if let Some(tile_size) = opts.tile_size {
+        cam.tile_size = tile_size;
+    }
+    if let Some(tile_order) = opts.tile_order {
+        cam.tile_order = tile_order;
+    }
+    cam.checkpoint_file = opts.checkpoint.clone();
+    cam.scene_name = opts.scene.name().to_string();
+    if let Some(interval) = opts.checkpoint_interval {
+        cam.checkpoint_interval = interval;
+    }
+    if let Some(samples_per_pass) = opts.samples_per_pass {
+        cam.samples_per_pass = samples_per_pass;
+    }
+    cam.adaptive_sampling = opts.adaptive_sampling;
+    if let Some(sampler) = opts.sampler {
+        cam.sampler = sampler;
+    }
+    if let Some(filter) = opts.filter {
+        cam.filter = filter;
+    }
+    cam.seed = opts.seed;
+    cam.aovs = opts.aovs.clone();
+    if opts.denoise {
+        for aov in DENOISE_AOVS {
+            if !cam.aovs.contains(&aov) {
+                cam.aovs.push(aov);
+            }
+        }
+    }
+    cam
+}
+
+// samples of an earlier run to continue from (--resume)
+fn load_checkpoint(cam: &Camera, opts: &RenderOptions) -> Result<FrameBuffer, String> {
+    FrameBuffer::load(opts.checkpoint.as_ref().unwrap(), &cam.checkpoint_header())
+}
+
+// --crop: restrict the camera to the crop window; with --crop-into, also read the render to paste the crop into
+fn prepare_crop(cam: &mut Camera, opts: &RenderOptions) -> Result<Option<FrameBuffer>, String> {
+    let crop = match opts.crop {
+        Some(crop) => crop,
+        None => return Ok(None),
+    };
+    cam.crop = Some(crop.resolve(cam.image_width, cam.image_height())?);
+    let base_file = match &opts.crop_into {
+        Some(base_file) => base_file,
+        None => return Ok(None),
+    };
+    let base = read_image(base_file, opts.transfer)?;
+    if base.width != cam.image_width || base.height != cam.image_height() {
+        return Err(format!(
+            "\"{}\" is {}x{}, but the camera is {}x{}",
+            base_file, base.width, base.height, cam.image_width, cam.image_height()
+        ));
+    }
+    Ok(Some(base))
+}
+
+// Paste the rendered crop into the earlier render. For 8/16-bit output the base holds display values, so the
+// crop is tone mapped now and the composite is written without tone mapping.
+fn paste_crop(mut base: FrameBuffer, region: &Tile, crop: &FrameBuffer, opts: &mut RenderOptions) -> FrameBuffer {
+    if opts.format.is_hdr() {
+        base.paste(region.x_min, region.y_min, crop);
+    } else {
+        base.paste(region.x_min, region.y_min, &opts.tone_mapping.apply(crop));
+        opts.tone_mapping = ToneMapping::default();
+    }
+    base
+}
+
 fn main() {
+    let mut opts = parse_args();
 
-    let parameters = init_prompt();
-
-    let mut TYPE = if parameters.4 {0} else {-1};
-    let cam = match TYPE {
-        -1 => build_final_camera(400, 2000, 10),
-        0 => build_final_camera(1200, 5000, 40),
-        1 => build_camera_1(), // bouncing_spheres
-        2 => build_camera_2(), // checkered_spheres
-        3 => build_camera_3(), // earth
-        4 => build_camera_4(), // perlin_spheres
-        5 => build_camera_5(), // quads
-        6 => build_camera_6(), // simple_light
-        7 => build_camera_7(), // cornell_box
-        8 => build_camera_8(), // cornell_smoke
-        9 => build_camera_9(800, 10000, 40), // final scene
-        10 => build_camera_9(400, 2000, 10), // final scene test
-        _ => panic!("Not matched"),
+    println!("[Ray Tracer]");
+    println!("Info: Output file \"{}\"", opts.output);
+
+    if let Some(code) = check_output_target(&opts) {
+        std::process::exit(code);
+    }
+
+    seed_rng(opts.seed);
+
+    let (cam, world) = match &opts.scene {
+        SceneChoice::File(scene_file) => match load_scene(scene_file) {
+            Ok(scene) => (scene.camera, scene.world),
+            Err(err) => {
+                eprintln!("Error: {}", err);
+                std::process::exit(EXIT_SCENE_ERROR);
+            }
+        },
+        SceneChoice::Builtin(name) => build_builtin_scene(name),
     };
-    let world = match TYPE {
-        -1 => build_final_world(),
-        0 => build_final_world(), 
-        1 => build_world_1(),
-        2 => build_world_2(),
-        3 => build_world_3(),
-        4 => build_world_4(),
-        5 => build_world_5(),
-        6 => build_world_6(),
-        7 => build_world_7(),
-        8 => build_world_8(),
-        9 => build_world_9(),
-        10 => build_world_9(), // final scene test
-        _ => panic!("Not matched"),
+    let mut cam = apply_overrides(cam, &opts);
+    if cam.image_height() == 0 { // --width or --aspect-ratio against the scene's camera
+        eprintln!("Error: a {} pixel wide image at aspect ratio {} is less than a pixel high", cam.image_width, cam.aspect_ratio);
+        std::process::exit(EXIT_INVALID_ARGUMENTS);
+    }
+    let crop_base = match prepare_crop(&mut cam, &opts) {
+        Ok(base) => base,
+        Err(err) => {
+            eprintln!("Error: {}", err);
+            std::process::exit(EXIT_CROP_ERROR);
+        }
     };
 
-    let img = cam.render(&world);
+    let img = if opts.resume {
+        match load_checkpoint(&cam, &opts) {
+            Ok(img) => cam.render_from(&world, img),
+            Err(err) => {
+                eprintln!("Error: {}", err);
+                std::process::exit(EXIT_CHECKPOINT_ERROR);
+            }
+        }
+    } else {
+        cam.render(&world)
+    };
+    let img = match cam.crop {
+        Some(region) => img.crop(&region),
+        None => img,
+    };
 
-    tail_process(img, parameters, "fAKe");
-}
+    let mut extra_output_failed = false;
+    if let Some(heatmap_file) = &opts.sample_heatmap {
+        match write_sample_heatmap(&img, heatmap_file) {
+            Ok((min, max)) => println!("Info: sample heatmap \"{}\": black = {} samples, white = {} samples", heatmap_file, min, max),
+            Err(err) => {
+                eprintln!("Error: {}", err);
+                extra_output_failed = true;
+            }
+        }
+    }
+    for &aov in &opts.aovs { // of the crop only, with --crop-into as well
+        let aov_file = aov_file_name(&opts.output, aov);
+        match write_aov(&img, aov, &aov_file, opts.format, opts.quality, opts.transfer) {
+            Ok(_) => println!("Info: {} layer \"{}\"", aov.name(), aov_file),
+            Err(err) => {
+                eprintln!("Error: {}", err);
+                extra_output_failed = true;
+            }
+        }
+    }
 
+    let img = if opts.denoise {
+        let denoised = denoise(&img, &DenoiseSettings::default());
+        if let Some(compare_file) = &opts.denoise_compare {
+            let format = OutputFormat::from_extension(compare_file).unwrap_or(opts.format);
+            match write_image(&side_by_side(&img, &denoised), compare_file, format, opts.quality, &opts.tone_mapping, opts.transfer) {
+                Ok(_) => println!("Info: noisy | denoised \"{}\"", compare_file),
+                Err(err) => {
+                    eprintln!("Error: {}", err);
+                    extra_output_failed = true;
+                }
+            }
+        }
+        denoised
+    } else {
+        img
+    };
+
+    let img = match (crop_base, cam.crop) {
+        (Some(base), Some(region)) => paste_crop(base, &region, &img, &mut opts),
+        _ => img,
+    };
 
+    let code = tail_process(img, &opts, "fAKe");
+    std::process::exit(if code == 0 && extra_output_failed { EXIT_WRITE_FAILED } else { code });
+}
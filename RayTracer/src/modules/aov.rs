@@ -0,0 +1,170 @@
+// Arbitrary output variables (AOVs): per-pixel data about the first surface seen through each pixel,
+// rendered alongside the beauty pass for compositing and denoising.
+// Every camera sample that hits something adds its value to the pixel, and the pixel is the mean over those samples;
+// the ID layers keep the first ID seen instead, since averaging IDs means nothing.
+
+use crate::color::{*};
+use crate::hittable::{*};
+use crate::tile::{*};
+use crate::vec3::{*};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Aov {
+  Albedo, // surface colour, from the material's texture; the background where nothing was hit
+  Normal, // world-space shading normal, facing the camera
+  Depth, // distance along the view axis
+  Position, // world space
+  Uv, // texture coordinates, in the first two channels
+  ObjectId, // in the first channel; 0: background
+  MaterialId, // in the first channel; 0: background
+}
+
+pub const AOV_NAMES: [&str; 7] = ["albedo", "normal", "depth", "position", "uv", "object_id", "material_id"];
+
+pub const ALL_AOVS: [Aov; 7] = [Aov::Albedo, Aov::Normal, Aov::Depth, Aov::Position, Aov::Uv, Aov::ObjectId, Aov::MaterialId];
+
+impl Aov {
+  pub fn from_name(name: &str) -> Option<Self> {
+    AOV_NAMES.iter().position(|&n| n == name).map(|k| ALL_AOVS[k])
+  }
+
+  pub fn name(&self) -> &'static str {
+    AOV_NAMES[ALL_AOVS.iter().position(|aov| aov == self).unwrap()]
+  }
+
+  pub fn is_id(&self) -> bool {
+    matches!(self, Self::ObjectId | Self::MaterialId)
+  }
+
+  // value of one camera sample; None if it adds nothing to the pixel
+  pub fn value(&self, first_hit: &Option<FirstHit>, background: ColorType) -> Option<ColorType> {
+    let hit = match first_hit {
+      Some(hit) => hit,
+      None => return if *self == Self::Albedo { Some(background) } else { None },
+    };
+    Some(match self {
+      Self::Albedo => hit.albedo,
+      Self::Normal => hit.normal,
+      Self::Depth => ColorType::ones() * hit.depth,
+      Self::Position => hit.p,
+      Self::Uv => ColorType::new(hit.u, hit.v, 0.0),
+      Self::ObjectId => ColorType::new(hit.ids.object as f64, 0.0, 0.0),
+      Self::MaterialId => ColorType::new(hit.ids.material as f64, 0.0, 0.0),
+    })
+  }
+}
+
+// What a camera ray hit first.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FirstHit {
+  pub albedo: ColorType,
+  pub normal: Vec3,
+  pub depth: f64,
+  pub p: Point3,
+  pub u: f64,
+  pub v: f64,
+  pub ids: HitIds,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct AovBuffer {
+  pub aov: Aov,
+  pub width: usize,
+  pub height: usize,
+  sum: Vec<ColorType>, // the ID itself for ID layers
+  count: Vec<u32>, // samples that added a value
+}
+
+impl AovBuffer {
+  pub fn new(aov: Aov, width: usize, height: usize) -> Self {
+    Self {
+      aov,
+      width,
+      height,
+      sum: vec![ColorType::zero(); width * height],
+      count: vec![0; width * height],
+    }
+  }
+
+  fn index(&self, i: usize, j: usize) -> usize {
+    debug_assert!(i < self.width && j < self.height);
+    j * self.width + i
+  }
+
+  pub fn add(&mut self, i: usize, j: usize, value: Option<ColorType>) {
+    if let Some(value) = value {
+      self.add_samples(i, j, value, 1);
+    }
+  }
+
+  // `count` values summing up to `sum`
+  pub fn add_samples(&mut self, i: usize, j: usize, sum: ColorType, count: u32) {
+    let idx = self.index(i, j);
+    if count == 0 {
+      return;
+    }
+    if !self.aov.is_id() {
+      self.sum[idx] += sum;
+      self.count[idx] += count;
+    } else if self.count[idx] == 0 { // first ID wins
+      self.sum[idx] = sum;
+      self.count[idx] = 1;
+    }
+  }
+
+  // None where no sample added a value (e.g. the depth of the background)
+  pub fn get(&self, i: usize, j: usize) -> Option<ColorType> {
+    let idx = self.index(i, j);
+    match self.count[idx] {
+      0 => None,
+      count => Some(self.sum[idx] / count as f64),
+    }
+  }
+
+  pub fn accumulate(&mut self, x0: usize, y0: usize, tile: &AovBuffer) {
+    for j in 0..tile.height {
+      for i in 0..tile.width {
+        let src = tile.index(i, j);
+        self.add_samples(x0 + i, y0 + j, tile.sum[src], tile.count[src]);
+      }
+    }
+  }
+
+  pub fn crop(&self, region: &Tile) -> AovBuffer {
+    let mut cropped = AovBuffer::new(self.aov, region.width(), region.height());
+    cropped.paste_from(self, region.x_min, region.y_min);
+    cropped
+  }
+
+  // overwrite the pixels under `other`, whose pixel (0, 0) lands on (x0, y0)
+  pub fn paste(&mut self, x0: usize, y0: usize, other: &AovBuffer) {
+    for j in 0..other.height {
+      for i in 0..other.width {
+        let (src, dst) = (other.index(i, j), self.index(x0 + i, y0 + j));
+        self.sum[dst] = other.sum[src];
+        self.count[dst] = other.count[src];
+      }
+    }
+  }
+
+  // fill self with the pixels of `other` starting at (x0, y0)
+  fn paste_from(&mut self, other: &AovBuffer, x0: usize, y0: usize) {
+    for j in 0..self.height {
+      for i in 0..self.width {
+        let (src, dst) = (other.index(x0 + i, y0 + j), self.index(i, j));
+        self.sum[dst] = other.sum[src];
+        self.count[dst] = other.count[src];
+      }
+    }
+  }
+
+  // raw pixel data for checkpoints
+  pub fn pixels(&self) -> impl Iterator<Item = (ColorType, u32)> + '_ {
+    self.sum.iter().copied().zip(self.count.iter().copied())
+  }
+
+  pub fn set_pixel(&mut self, idx: usize, sum: ColorType, count: u32) {
+    self.sum[idx] = sum;
+    self.count[idx] = count;
+  }
+}
@@ -88,5 +88,12 @@ impl Hittable for BvhNode {
   fn bounding_box(&self) -> Aabb {
     self.bbox
   }
+
+  fn collect_lights(&self, _this: &Object, lights: &mut Vec<Object>) {
+    self.left.collect_lights(&self.left, lights);
+    if !Arc::ptr_eq(&self.left, &self.right) { // a leaf holds its object twice
+      self.right.collect_lights(&self.right, lights);
+    }
+  }
 }
 
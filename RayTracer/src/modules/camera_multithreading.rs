@@ -1,6 +1,8 @@
-const HEIGHT_PARTITION: usize = 50; // multithreading parameters
-const WIDTH_PARTITION: usize = 50;
-const THREAD_LIMIT: usize = 20;
+const TILE_SIZE: usize = 32; // default for Camera::tile_size, in pixels
+const SAMPLES_PER_PASS: usize = 16; // default for Camera::samples_per_pass
+const CHECKPOINT_INTERVAL: f64 = 300.0; // default for Camera::checkpoint_interval, in seconds
+const RUSSIAN_ROULETTE_DEPTH: usize = 3; // default for Camera::russian_roulette_depth
+const ADAPTIVE_MIN_LUMINANCE: f64 = 0.01; // below this mean luminance, the noise threshold is absolute instead of relative
 
 use crate::EPS;
 
@@ -13,17 +15,87 @@ use crate::utility::{*};
 use crate::interval::{*};
 use crate::INFINITY;
 
-use std::sync::{Arc, Mutex, Condvar};
-use crossbeam::thread;
-use std::sync::atomic::{AtomicUsize, Ordering};
-use image::{ImageBuffer, RgbImage}; 
-use indicatif::{ProgressBar, ProgressStyle};
+use std::sync::Arc;
+use std::panic::{self, AssertUnwindSafe};
+use crossbeam::{channel, thread};
+use crate::framebuffer::{*};
+use crate::sampler::{*};
+use crate::tile::{*};
+use crate::observer::{*};
+use crate::aov::{*};
+use crate::filter::{*};
+use crate::light::{*};
+use crate::interior::{*};
+use crate::spectrum::{*};
+use std::time::{Duration, Instant};
+
+// Adaptive sampling: every pixel gets at least min_samples; after that, pixels keep receiving passes of
+// Camera::samples_per_pass samples until the standard error of the mean luminance of the pixel and its
+// neighbours drops below threshold * mean luminance, or they reach Camera::sample_per_pixel (the maximum).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AdaptiveSampling {
+  pub min_samples: usize,
+  pub threshold: f64,
+}
+
+// Where the light reaching a diffuse surface directly from an emissive object comes from.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LightSampling {
+  Bsdf, // only paths that happen to scatter into a light; small lights make this very noisy
+  Light, // next-event estimation: at every bounce, a shadow ray towards a point sampled on the lights; big lights seen in glossy surfaces are noisy
+  Mis, // both, weighted by Camera::mis_heuristic (multiple importance sampling)
+}
+
+pub const LIGHT_SAMPLING_NAMES: [&str; 3] = ["bsdf", "light", "mis"];
+
+impl LightSampling {
+  pub fn from_name(name: &str) -> Option<Self> {
+    match name {
+      "bsdf" => Some(Self::Bsdf),
+      "light" => Some(Self::Light),
+      "mis" => Some(Self::Mis),
+      _ => None,
+    }
+  }
+}
+
+// How MIS splits the light arriving in a direction both strategies can sample (Veach 1997)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MisHeuristic {
+  Balance, // in proportion to the densities
+  Power, // to their squares, which favours the better strategy more
+}
+
+pub const MIS_HEURISTIC_NAMES: [&str; 2] = ["balance", "power"];
+
+impl MisHeuristic {
+  pub fn from_name(name: &str) -> Option<Self> {
+    match name {
+      "balance" => Some(Self::Balance),
+      "power" => Some(Self::Power),
+      _ => None,
+    }
+  }
+
+  // weight of a sample taken with density `pdf`, where the other strategy has density `other_pdf`; the two weights sum to 1
+  pub fn weight(&self, pdf: f64, other_pdf: f64) -> f64 {
+    let (a, b) = match self {
+      Self::Balance => (pdf, other_pdf),
+      Self::Power => (pdf * pdf, other_pdf * other_pdf),
+    };
+    if a + b > 0.0 { a / (a + b) } else { 0.0 }
+  }
+}
 
 pub struct Camera {
   pub aspect_ratio: f64, // Ratio of image width over height
   pub image_width: usize,
   pub sample_per_pixel: usize, // Count of random samples for each pixel
   pub max_ray_depth: usize, // Maximum number of ray bounces into scene
+  pub russian_roulette_depth: Option<usize>, // Bounces after which paths may be terminated early (unbiased); None: never
+  pub light_sampling: LightSampling, // Lights found by scattering, by sampling them explicitly, or both
+  pub mis_heuristic: MisHeuristic, // With LightSampling::Mis
+  pub spectral: bool, // Trace wavelengths instead of RGB, for dispersion (slower), see spectrum.rs
   pub vfov: f64, // Vertical view angle (field of view) in degree
   pub lookfrom: Point3,
   pub lookat: Point3,
@@ -31,6 +103,19 @@ pub struct Camera {
   pub defocus_angle: f64, // Variation angle of rays through each pixel
   pub focus_dist: f64,  // Distance from camera lookfrom point to plane of perfect focus
   pub background: ColorType,
+  pub thread_limit: usize, // Number of render worker threads (default: one per CPU)
+  pub tile_size: usize, // Workers render square tiles of this many pixels a side
+  pub tile_order: TileOrder, // Order the tiles are handed out in
+  pub checkpoint_file: Option<String>, // Where to save progress; None disables checkpoints
+  pub scene_name: String, // Recorded in checkpoints, so that only a render of the same scene resumes from them
+  pub checkpoint_interval: f64, // Minimum number of seconds between two checkpoints
+  pub samples_per_pass: usize, // With checkpoints or adaptive sampling, the image is rendered in passes of this many samples per pixel
+  pub adaptive_sampling: Option<AdaptiveSampling>, // None: every pixel gets sample_per_pixel samples
+  pub sampler: SamplerKind, // Source of the random numbers along each path
+  pub seed: u64, // Renders with the same seed are identical, whatever the thread count
+  pub crop: Option<Tile>, // Only render these pixels (of the full-frame camera); the others get no samples
+  pub aovs: Vec<Aov>, // Extra layers rendered into the returned buffer, see aov.rs
+  pub filter: PixelFilter, // Reconstruction filter the samples are splatted into the pixels with
   image_height: usize,
   pixel_samples_scale: f64, // Color scale factor for a sum of pixel samples
   center: Point3, 
@@ -63,6 +148,10 @@ impl Camera {
       image_width,
       sample_per_pixel,
       max_ray_depth, 
+      russian_roulette_depth: Some(RUSSIAN_ROULETTE_DEPTH),
+      light_sampling: LightSampling::Mis,
+      mis_heuristic: MisHeuristic::Power,
+      spectral: false,
       vfov,
       lookfrom,
       lookat,
@@ -70,6 +159,19 @@ impl Camera {
       defocus_angle,
       focus_dist,
       background,
+      thread_limit: std::thread::available_parallelism().map_or(1, |n| n.get()),
+      tile_size: TILE_SIZE,
+      tile_order: TileOrder::Scanline,
+      checkpoint_file: None,
+      scene_name: String::new(),
+      checkpoint_interval: CHECKPOINT_INTERVAL,
+      samples_per_pass: SAMPLES_PER_PASS,
+      adaptive_sampling: None,
+      sampler: SamplerKind::Sobol,
+      seed: 0,
+      crop: None,
+      aovs: Vec::new(),
+      filter: PixelFilter::default(),
       image_height: 0,
       pixel_samples_scale: 0.0,
       center: Point3::zero(),
@@ -135,9 +237,8 @@ impl Camera {
     self.defocus_disk_v = self.v * defocus_radius;
   }
 
-  // Return a ray pointing from camera to pixel (i, j) where exact coordinates is randomly sampled.
-  fn get_ray(&self, i: usize, j: usize) -> Ray {
-    let offset = Self::sample_square();
+  // Return a ray pointing from camera through `offset` (in pixels) from the centre of pixel (i, j).
+  fn get_ray(&self, i: usize, j: usize, offset: Vec3, sampler: &mut dyn Sampler) -> Ray {
     let pixel_sample_coord = self.pixel00_loc + 
       (i as f64 + offset.x) * self.pixel_delta_u + 
       (j as f64 + offset.y) * self.pixel_delta_v;
@@ -145,137 +246,418 @@ impl Camera {
     let ray_origin = if self.defocus_angle <= 0.0 {
         self.center 
       } else {
-        self.defocus_disk_sample()
+        self.defocus_disk_sample(sampler)
       };
     let ray_direction = pixel_sample_coord - ray_origin;
-    let ray_time = rand_01();
+    let ray_time = sampler.get_1d();
     Ray::new(ray_origin, ray_direction, ray_time)
   }
  
 
   // Return a random ([-0.5, 0.5], [-0.5, 0.5], 0) Vec3
-  fn sample_square() -> Vec3 {
+  fn sample_square(sampler: &mut dyn Sampler) -> Vec3 {
     static deviation: f64 = 0.5;
-    Vec3::new(rand_range(-deviation, deviation), rand_range(-deviation, deviation), 0.0)
+    let (x, y) = sampler.get_2d();
+    Vec3::new(x * 2.0 * deviation - deviation, y * 2.0 * deviation - deviation, 0.0)
   }
 
-  fn defocus_disk_sample(&self) -> Point3 {
-    let p = Vec3::rand_in_unit_disk();
+  fn defocus_disk_sample(&self, sampler: &mut dyn Sampler) -> Point3 {
+    let p = sample_unit_disk(sampler.get_2d());
     self.center + p.x * self.defocus_disk_u + p.y * self.defocus_disk_v
   }
 
-  fn ray_color(&self, ray: &Ray, depth: usize, world: &Object) -> ColorType {
-    if depth >= self.max_ray_depth { // ray tracing depth exceeds limit // note that my depth is incremental, which is different from the textbook
-      return ColorType::zero();
+  // Radiance arriving along `ray`, following one path through the scene. The path carries the product of the
+  // attenuations so far (its throughput); after russian_roulette_depth bounces it survives each further bounce
+  // with a probability that follows the throughput, and survivors are scaled up to keep the estimate unbiased.
+  // max_ray_depth only caps the path length.
+  // With next-event estimation, light from `lights` also reaches each non-delta bounce through a shadow ray. A light the
+  // path then scatters into only counts in directions the lights cannot be sampled in; with MIS, both count, weighted.
+  // With AOVs enabled, what the camera ray hits is stored in `first_hit`.
+  // In spectral mode, the radiance at `wavelengths` (still to be converted to RGB).
+  fn ray_color(&self, mut ray: Ray, world: &Object, lights: &LightList, wavelengths: Option<&Wavelengths>, sampler: &mut dyn Sampler, first_hit: &mut Option<FirstHit>) -> ColorType {
+    let spectrum = |rgb: ColorType| wavelengths.map_or(rgb, |wl| wl.upsample(rgb));
+    let mut hero_only = false; // the other wavelengths were dropped at a dispersive surface
+    let next_event = self.light_sampling != LightSampling::Bsdf && !lights.is_empty();
+    let mut radiance = ColorType::zero();
+    let mut throughput = ColorType::ones();
+    let mut sampled_lights_from = None; // the previous bounce and the density it scattered with, if it sampled the lights
+    let mut media = InteriorStack::new();
+    for depth in 0..self.max_ray_depth {
+      let mut rec = HitRecord::default();
+      let mut hit = world.hit(&ray, Interval::new(EPS /* fix shadow acne */, INFINITY), &mut rec);
+      while hit && media.is_false_hit(&rec) { // go on through the surface, the medium changes
+        throughput = throughput.elemul(&media.transmittance(rec.t * ray.dir.norm(), wavelengths));
+        media.cross(&rec);
+        ray = Ray::new(rec.p, ray.dir, ray.tm);
+        rec = HitRecord::default();
+        hit = world.hit(&ray, Interval::new(EPS, INFINITY), &mut rec);
+      }
+      if !hit {
+        radiance += throughput.elemul(&spectrum(self.background)); // missed
+        break;
+      }
+      throughput = throughput.elemul(&media.transmittance(rec.t * ray.dir.norm(), wavelengths)); // absorbed on the way
+      rec.exterior_index = media.exterior_index(&rec);
+      rec.wavelength = wavelengths.map(|wl| wl.lambda[0]);
+      if depth == 0 && !self.aovs.is_empty() {
+        *first_hit = Some(FirstHit {
+          albedo: rec.mat.albedo(rec.u, rec.v, rec.p),
+          normal: rec.normal,
+          depth: Vec3::dot(&(rec.p - self.center), &-self.w),
+          p: rec.p,
+          u: rec.u,
+          v: rec.v,
+          ids: rec.ids,
+        });
+      }
+
+      if rec.mat.is_emissive() {
+        let weight = match sampled_lights_from {
+          Some((origin, bsdf_pdf)) => {
+            let light_pdf = lights.pdf_value(origin, ray.dir);
+            match self.light_sampling {
+              LightSampling::Mis => self.mis_heuristic.weight(bsdf_pdf, light_pdf),
+              _ => if light_pdf == 0.0 { 1.0 } else { 0.0 },
+            }
+          }
+          None => 1.0,
+        };
+        radiance += throughput.elemul(&spectrum(rec.mat.emitted(rec.u, rec.v, rec.p))) * weight;
+      }
+      if wavelengths.is_some() && !hero_only && (rec.mat.is_dispersive() || media.exterior_is_dispersive(&rec)) {
+        throughput = ColorType::new(3.0 * throughput.x, 0.0, 0.0);
+        hero_only = true;
+      }
+      let scattered = match rec.mat.sample(&ray, &rec, sampler) {
+        Some(scattered) => scattered,
+        None => break,
+      };
+      sampled_lights_from = None;
+      if next_event && !scattered.is_delta && depth + 1 < self.max_ray_depth {
+        radiance += throughput.elemul(&self.direct_light(&ray, &rec, world, lights, &media, wavelengths, sampler));
+        sampled_lights_from = Some((rec.p, scattered.pdf));
+      }
+      throughput = throughput.elemul(&spectrum(scattered.weight()));
+      if scattered.ray.dir.dot(&rec.normal) < 0.0 {
+        media.cross(&rec); // refracted
+      }
+
+      if self.russian_roulette_depth.map_or(false, |rr_depth| depth + 1 >= rr_depth) {
+        let termination = (1.0 - throughput.x.max(throughput.y).max(throughput.z)).max(0.05);
+        if sampler.get_1d() < termination {
+          break;
+        }
+        throughput /= 1.0 - termination;
+      }
+      ray = scattered.ray;
     }
+    radiance
+  }
 
-    let mut rec = HitRecord::default();
-    if world.hit(ray, Interval::new(EPS /* fix shadow acne */, INFINITY), &mut rec) {
-      let mut scattered = Ray::default();
-      let mut attenuation = ColorType::zero();
-      let color_from_emission = rec.mat.emitted(rec.u, rec.v, rec.p);
-      if rec.mat.scatter(ray, &rec, &mut attenuation, &mut scattered) {
-        let color_from_scatter = attenuation.elemul(&self.ray_color(&scattered, depth + 1, world));
-        color_from_emission + color_from_scatter
-      } else { 
-        color_from_emission
+  // Light reaching `rec` straight from a point sampled on the lights, times the BSDF, over the density of the direction
+  // (and the MIS weight).
+  // The shadow ray counts whatever it hits first, so occluders (and media it scatters in) block the light.
+  fn direct_light(&self, ray_in: &Ray, rec: &HitRecord, world: &Object, lights: &LightList, media: &InteriorStack, wavelengths: Option<&Wavelengths>, sampler: &mut dyn Sampler) -> ColorType {
+    let direction = lights.random(rec.p, sampler);
+    let pdf = lights.pdf_value(rec.p, direction);
+    let f = rec.mat.eval(ray_in, rec, direction);
+    if pdf <= 0.0 || f == ColorType::zero() {
+      return ColorType::zero();
+    }
+    let mut media = media.clone();
+    if direction.dot(&rec.normal) < 0.0 {
+      media.cross(rec); // through the surface
+    }
+    // through the false hits as in ray_color, absorbed on the way
+    let mut shadow_ray = Ray::new(rec.p, direction, ray_in.tm);
+    let mut transmittance = ColorType::ones();
+    let mut light_rec = HitRecord::default();
+    loop {
+      if !world.hit(&shadow_ray, Interval::new(EPS, INFINITY), &mut light_rec) {
+        return ColorType::zero();
       }
-    } else {
-      self.background // missed
+      transmittance = transmittance.elemul(&media.transmittance(light_rec.t * direction.norm(), wavelengths));
+      if !media.is_false_hit(&light_rec) {
+        break;
+      }
+      media.cross(&light_rec);
+      shadow_ray = Ray::new(light_rec.p, direction, ray_in.tm);
+      light_rec = HitRecord::default();
+    }
+    let weight = match self.light_sampling {
+      LightSampling::Mis => self.mis_heuristic.weight(pdf, rec.mat.pdf(ray_in, rec, direction)),
+      _ => 1.0,
+    };
+    let emitted = light_rec.mat.emitted(light_rec.u, light_rec.v, light_rec.p);
+    let (f, emitted) = match wavelengths {
+      Some(wl) => (wl.upsample(f), wl.upsample(emitted)),
+      None => (f, emitted),
+    };
+    f.elemul(&emitted).elemul(&transmittance) * (weight / pdf)
+  }
+
+  pub fn image_height(&self) -> usize {
+    self.image_height
+  }
+
+  // what checkpoints of this render record, and what a render resuming from one must match
+  pub fn checkpoint_header(&self) -> CheckpointHeader {
+    CheckpointHeader {
+      width: self.image_width,
+      height: self.image_height,
+      scene: self.scene_name.clone(),
+      sampler: format!("{:?}", self.sampler),
+      filter: format!("{:?}", self.filter),
+      seed: self.seed,
+      spectral: self.spectral,
     }
   }
 
+  // the pixels render() takes samples for: the crop window, or the whole image
+  pub fn render_region(&self) -> Tile {
+    let full = Tile::full(self.image_width, self.image_height);
+    self.crop.map_or(full, |crop| crop.intersect(&full))
+  }
 
+  // The returned buffer holds linear radiance and per-pixel sample counts; converting it for display is up to the caller.
+  pub fn render(&self, world: &Object) -> FrameBuffer {
+    self.render_from(world, FrameBuffer::new(self.image_width, self.image_height))
+  }
 
+  // Continue a render whose samples so far are in `img` (e.g. loaded from a checkpoint) until every pixel
+  // of render_region() has sample_per_pixel samples (or has converged, with adaptive sampling); raising sample_per_pixel
+  // adds samples to a finished render.
+  // With checkpoint_file set, the samples are taken in passes and the buffer is saved between passes.
+  pub fn render_from(&self, world: &Object, img: FrameBuffer) -> FrameBuffer {
+    self.render_with(world, img, &ProgressBarObserver::new(), &CancelToken::new())
+  }
 
+  // render_from, reporting to `observer` instead of the terminal. Once `cancel` is cancelled, the render stops
+  // within a row of pixels per worker and returns the samples taken so far (saving a checkpoint first, if enabled).
+  pub fn render_with(&self, world: &Object, mut img: FrameBuffer, observer: &dyn RenderObserver, cancel: &CancelToken) -> FrameBuffer {
+    assert!(img.width == self.image_width && img.height == self.image_height, "Camera::render_with: buffer size mismatch");
 
+    img.add_aov_layers(&self.aovs);
+    let lights = LightList::new(world);
+    let region = self.render_region();
+    let done = img.crop(&region).min_sample_count() as usize;
+    let min_samples = self.adaptive_sampling.map_or(0, |adaptive| adaptive.min_samples.min(self.sample_per_pixel));
+    let pass_size = if self.checkpoint_file.is_some() || self.adaptive_sampling.is_some() {
+      self.samples_per_pass.max(1)
+    } else {
+      self.sample_per_pixel.saturating_sub(done).max(1)
+    };
+    let mut targets = Vec::new(); // sample count of every pixel after each pass
+    let mut target = done;
+    while target < self.sample_per_pixel {
+      target = if target < min_samples { min_samples } else { (target + pass_size).min(self.sample_per_pixel) };
+      targets.push(target);
+    }
+    let pass_count = targets.len();
 
-  // Multithread mechanism -> Partition into fine granularity (with WIDTH_PARTITION * HEIGHT_PARTITION sub-tasks), and only let THREAD_LIMIT threads run at the same time.
-  pub fn render(&self, world: &Object) -> RgbImage { 
-    let mut img: RgbImage = ImageBuffer::new(self.image_width as u32, self.image_height as u32);
+    // A pool of workers lives for the whole render. Every pass puts all the tiles in a shared queue; whichever
+    // worker is free takes the next one and sends the finished tile back, so only this thread writes to `img`.
+    let tiles = make_tiles(&region, self.tile_size, self.tile_order);
+    let workers = self.thread_limit.clamp(1, tiles.len().max(1));
+    let (job_sender, job_receiver) = channel::unbounded::<(usize, Arc<FrameBuffer>, u32)>(); // index into `tiles`
+    let (result_sender, result_receiver) = channel::unbounded();
 
-    println!("[Render progress]:");
-    let bar = get_ProgressBar(self.image_height, self.image_width);
-    let bar_wrapper = Arc::new(&bar);
+    let start = Instant::now();
+    let planned = (self.sample_per_pixel.saturating_sub(done) * region.width() * region.height()).max(1) as f64; // pixel samples
+    let mut progress = RenderProgress {
+      pass: 0,
+      pass_count,
+      target: targets.first().copied().unwrap_or(done),
+      resumed_at: done,
+      sample_per_pixel: self.sample_per_pixel,
+      tiles_done: 0,
+      tile_count: tiles.len(),
+      fraction: 0.0,
+      elapsed: Duration::ZERO,
+      eta: None,
+    };
+    observer.render_started(&progress, workers);
 
-    let camera_wrapper = Arc::new(self);
-    let world_wrapper = Arc::new(world);
-    let img_mtx = Arc::new(Mutex::new(&mut img));
-    
-    thread::scope(move |thd_spawner|{
-      let thread_count = Arc::new(AtomicUsize::new(0));
-      let thread_number_controller = Arc::new(Condvar::new());
-      
-      let chunk_height = (self.image_height + HEIGHT_PARTITION - 1) / HEIGHT_PARTITION;
-      let chunk_width = (self.image_width + WIDTH_PARTITION - 1) / WIDTH_PARTITION;
-      for j in 0..HEIGHT_PARTITION {
-        for i in 0..WIDTH_PARTITION {
-          let lock_for_condv = Mutex::new(false);
-          while !(thread_count.load(Ordering::SeqCst) < THREAD_LIMIT) { // outstanding thread number control
-            thread_number_controller.wait(lock_for_condv.lock().unwrap()).unwrap();
+    thread::scope(|scope| {
+      for _ in 0..workers {
+        let (job_receiver, result_sender, tiles, lights) = (job_receiver.clone(), result_sender.clone(), &tiles, &lights);
+        scope.spawn(move |_| {
+          for (k, pass_start, target) in job_receiver.iter() {
+            let result = panic::catch_unwind(AssertUnwindSafe(|| self.render_sub(world, lights, &pass_start, &tiles[k], target, cancel)));
+            if result_sender.send((k, result)).is_err() {
+              break;
+            }
           }
-          
-          
-          let bar = Arc::clone(&bar_wrapper);
-
-          let camera = Arc::clone(&camera_wrapper);
-          let world = Arc::clone(&world_wrapper);
-          let img_mtx = Arc::clone(&img_mtx);
-          
-          let thread_count = Arc::clone(&thread_count);
-          let thread_number_controller = Arc::clone(&thread_number_controller);
-
-          thread_count.fetch_add(1, Ordering::SeqCst);
-          bar.set_message(format!("|{} threads outstanding|", thread_count.load(Ordering::SeqCst))); // move out of thread, so that it's sequential with thread number control code
-
-          let _ = thd_spawner.spawn(move |_| {
-            camera.render_sub(&world, &img_mtx, &bar, 
-              i * chunk_width, (i + 1) * chunk_width, 
-              j * chunk_height, (j + 1) * chunk_height);
-            thread_count.fetch_sub(1, Ordering::SeqCst);
-            bar.set_message(format!("|{} threads outstanding|", thread_count.load(Ordering::SeqCst)));
-            thread_number_controller.notify_one();
-          });
+        });
+      }
+
+      let mut last_checkpoint = Instant::now();
+      let mut rendered = 0.0; // pixel samples
+      let mut previous_target = done;
+      for (pass, &target) in targets.iter().enumerate() {
+        (progress.pass, progress.target, progress.tiles_done) = (pass, target, 0);
+        observer.pass_started(&progress);
 
+        let pass_start = Arc::new(img.clone()); // tiles decide what to sample from this, not from whatever neighbouring tiles already wrote
+        for k in 0..tiles.len() {
+          job_sender.send((k, Arc::clone(&pass_start), target as u32)).unwrap();
+        }
+        // Tiles are merged in the order of `tiles`, whichever finishes first: with a filter wider than a pixel,
+        // neighbouring tiles add to the same pixels, and a fixed order keeps the sums identical from run to run.
+        let mut finished_tiles: Vec<Option<FrameBuffer>> = vec![None; tiles.len()];
+        let mut next = 0;
+        for _ in 0..tiles.len() {
+          let (k, result) = result_receiver.recv().unwrap();
+          finished_tiles[k] = Some(result.unwrap_or_else(|err| panic::resume_unwind(err))); // a worker panicked
+          while next < tiles.len() && finished_tiles[next].is_some() {
+            let (tile, buff) = (tiles[next], finished_tiles[next].take().unwrap());
+            next += 1;
+            let splat = self.splat_region(&tile);
+            img.accumulate(splat.x_min, splat.y_min, &buff);
+
+            if !cancel.is_cancelled() { // tiles cut short by cancellation are not done
+              rendered += ((tile.x_max - tile.x_min) * (tile.y_max - tile.y_min) * (target - previous_target)) as f64;
+            }
+            progress.tiles_done += 1;
+            progress.fraction = (rendered / planned).min(1.0);
+            progress.elapsed = start.elapsed();
+            progress.eta = if rendered > 0.0 { Some(progress.elapsed.mul_f64((1.0 - progress.fraction) / progress.fraction)) } else { None };
+            observer.tile_completed(&tile, &img, &progress);
+          }
+        }
+        previous_target = target;
+        observer.pass_completed(&img, &progress);
+        let finished = pass + 1 == pass_count || self.all_converged(&img) || cancel.is_cancelled();
+
+        if let Some(checkpoint_file) = &self.checkpoint_file {
+          if finished || last_checkpoint.elapsed().as_secs_f64() >= self.checkpoint_interval {
+            if let Err(err) = img.save(checkpoint_file, &self.checkpoint_header()) { // keep rendering, the samples are still in memory
+              observer.warning(&format!("checkpoint not saved: {}", err));
+            }
+            last_checkpoint = Instant::now();
+          }
+        }
+        if finished {
+          break;
         }
       }
+      drop(job_sender); // lets the workers leave their loop
     }).unwrap();
 
-    bar.finish();
+    observer.render_finished(&img, cancel.is_cancelled());
     img
   }
-  
-  pub fn render_sub(&self, world: &Object, img_mtx: &Mutex<&mut RgbImage>, bar: &ProgressBar, x_min: usize, x_max: usize, y_min: usize, y_max: usize) {
-    let x_min = x_min.max(0);
-    let y_min = y_min.max(0);
-    let x_max = x_max.min(self.image_width);
-    let y_max = y_max.min(self.image_height);
 
-    if y_max <= y_min || x_max <= x_min {
-      return;
+  // adaptive sampling: whether pixel (i, j) needs no more samples
+  fn converged(&self, img: &FrameBuffer, i: usize, j: usize) -> bool {
+    let adaptive = match self.adaptive_sampling {
+      Some(adaptive) => adaptive,
+      None => return false,
+    };
+    let count = img.sample_count(i, j) as usize;
+    if count >= self.sample_per_pixel {
+      return true;
     }
+    if count < adaptive.min_samples {
+      return false;
+    }
+    // A pixel whose samples all missed the light looks noise-free, so its neighbours must be quiet as well.
+    let region = self.render_region();
+    for y in j.saturating_sub(1).max(region.y_min)..(j + 2).min(region.y_max) {
+      for x in i.saturating_sub(1).max(region.x_min)..(i + 2).min(region.x_max) {
+        let mean = luminance(img.get(x, y));
+        if img.luminance_standard_error(x, y) > adaptive.threshold * mean.max(ADAPTIVE_MIN_LUMINANCE) {
+          return false;
+        }
+      }
+    }
+    true
+  }
 
-    let mut buff: Vec<Vec<ColorType>> = vec![vec![ColorType::zero(); y_max - y_min]; x_max - x_min];
+  fn all_converged(&self, img: &FrameBuffer) -> bool {
+    let region = self.render_region();
+    self.adaptive_sampling.is_some() && (region.y_min..region.y_max).all(|j| (region.x_min..region.x_max).all(|i| self.converged(img, i, j)))
+  }
+
+  // Render the samples of `tile` that bring each pixel that has not converged up to `target` samples.
+  // The returned buffer covers splat_region(tile): samples near the edge of the tile also count towards the neighbouring tiles' pixels.
+  // Every sample starts from an RNG stream derived from (seed, pixel, sample index), so the result does not
+  // depend on which thread renders which tile, or when.
+  pub fn render_sub(&self, world: &Object, lights: &LightList, pass_start: &FrameBuffer, tile: &Tile, target: u32, cancel: &CancelToken) -> FrameBuffer {
+    let Tile { x_min, x_max, y_min, y_max } = *tile;
+
+    let mut sample_ranges = Vec::with_capacity((x_max - x_min) * (y_max - y_min)); // sample indices to take this pass
     for j in y_min..y_max {
+      for i in x_min..x_max {
+        let done = pass_start.sample_count(i, j);
+        sample_ranges.push(if self.converged(pass_start, i, j) { done..done } else { done..target.max(done) });
+      }
+    }
+
+    let mut sampler = self.sampler.create(self.sample_per_pixel, self.seed);
+    let splat = self.splat_region(tile);
+    let mut buff = FrameBuffer::new(splat.width(), splat.height());
+    buff.add_aov_layers(&self.aovs);
+    let (radius, margin) = (self.filter.radius(), self.filter.margin());
+    for j in y_min..y_max {
+        if cancel.is_cancelled() {
+          break;
+        }
         for i in x_min..x_max {
+          let sample_range = sample_ranges[(j - y_min) * (x_max - x_min) + (i - x_min)].clone();
+          let sample_count = sample_range.len() as u32;
           let mut pixel_color = ColorType::zero();
-          for _ in 0..self.sample_per_pixel {
-            let ray = self.get_ray(i, j);
-            pixel_color += self.ray_color(&ray, 0 as usize, &world);
+          let mut luminance_sq = 0.0;
+          for sample_index in sample_range {
+            seed_rng(hash_values(&[self.seed, i as u64, j as u64, sample_index as u64]));
+            sampler.start_pixel_sample(i, j, sample_index as usize);
+            let offset = Self::sample_square(sampler.as_mut());
+            let ray = self.get_ray(i, j, offset, sampler.as_mut());
+            let mut first_hit = None;
+            let sample = if self.spectral {
+              let wavelengths = Wavelengths::sample(sampler.get_1d());
+              wavelengths.to_rgb(self.ray_color(ray, &world, lights, Some(&wavelengths), sampler.as_mut(), &mut first_hit))
+            } else {
+              self.ray_color(ray, &world, lights, None, sampler.as_mut(), &mut first_hit)
+            };
+            for layer in buff.aovs.iter_mut() {
+              layer.add(i - splat.x_min, j - splat.y_min, layer.aov.value(&first_hit, self.background));
+            }
+            luminance_sq += luminance(sample) * luminance(sample);
+            pixel_color += sample;
+
+            // into every pixel of the splat region whose filter covers the sample (always its own)
+            for y in j.saturating_sub(margin).max(splat.y_min)..(j + margin + 1).min(splat.y_max) {
+              let dy = j as f64 + offset.y - y as f64;
+              if y != j && dy.abs() >= radius {
+                continue;
+              }
+              for x in i.saturating_sub(margin).max(splat.x_min)..(i + margin + 1).min(splat.x_max) {
+                let dx = i as f64 + offset.x - x as f64;
+                if x != i && dx.abs() >= radius {
+                  continue;
+                }
+                let weight = self.filter.evaluate(dx, dy);
+                buff.add_weighted(x - splat.x_min, y - splat.y_min, sample * weight, weight);
+              }
+            }
           }
-          pixel_color *= self.pixel_samples_scale;
 
-          buff[i - x_min][j - y_min] = pixel_color;
-          // bar.inc(1); // fact: bar.inc 相当慢，脱了速度
-        }
-        bar.inc((x_max - x_min) as u64);
-      }
-      let mut img = img_mtx.lock().unwrap();
-      for j in y_min..y_max {
-        for i in x_min..x_max {
-          write_color_01(buff[i - x_min][j - y_min], &mut img, i, j);
+          buff.add_sample_stats(i - splat.x_min, j - splat.y_min, pixel_color, luminance_sq, sample_count);
         }
       }
+      buff
+  }
+
+  // the pixels the samples of `tile` can reach with the reconstruction filter: the tile and a margin, within the image
+  pub fn splat_region(&self, tile: &Tile) -> Tile {
+    let margin = self.filter.margin();
+    Tile {
+      x_min: tile.x_min.saturating_sub(margin),
+      x_max: (tile.x_max + margin).min(self.image_width),
+      y_min: tile.y_min.saturating_sub(margin),
+      y_max: (tile.y_max + margin).min(self.image_height),
+    }
   }
 
 
@@ -284,7 +666,387 @@ impl Camera {
 impl Clone for Camera {
   fn clone(&self) -> Self {
     Camera {
+      checkpoint_file: self.checkpoint_file.clone(),
+      scene_name: self.scene_name.clone(),
+      aovs: self.aovs.clone(),
       ..*self
     }
   }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::*;
+
+  // white emitting sphere on black: only pixels on its silhouette are noisy (partial coverage)
+  fn test_camera_and_world() -> (Camera, Object) {
+    let mut world = HittableList::default();
+    world.add(Sphere::new_static(Point3::zero(), 1.0, DiffuseLight::new_by_color(ColorType::ones()).to_material()).to_object());
+    let mut cam = Camera::new(
+      1.0, 24, 64, 10, 20.0,
+      Point3::new(0.0, 0.0, 10.0), Point3::zero(), Vec3::new(0.0, 1.0, 0.0),
+      0.0, 10.0, ColorType::zero(),
+    );
+    cam.thread_limit = 4;
+    (cam, world.to_object())
+  }
+
+  #[test]
+  fn test_adaptive_sampling() {
+    let (mut cam, world) = test_camera_and_world();
+    cam.adaptive_sampling = Some(AdaptiveSampling { min_samples: 8, threshold: 0.01 });
+    cam.samples_per_pass = 8;
+    let img = cam.render(&world);
+
+    assert_eq!(img.sample_count(0, 0), 8); // background
+    assert_eq!(img.get(0, 0), ColorType::zero());
+    assert_eq!(img.sample_count(12, 12), 8); // inside the sphere
+    assert_eq!(img.get(12, 12), ColorType::ones());
+    assert_eq!(img.min_sample_count(), 8);
+    assert_eq!(img.max_sample_count(), 64); // silhouette
+  }
+
+  #[test]
+  fn test_deterministic() {
+    let (mut cam, _) = test_camera_and_world();
+    let mut world = HittableList::default();
+    world.add(Sphere::new_static(Point3::new(0.0, 1.5, 0.0), 0.5, DiffuseLight::new_by_color(ColorType::ones() * 4.0).to_material()).to_object());
+    world.add(Sphere::new_static(Point3::new(0.0, -100.0, 0.0), 100.0, Lambertian::new_by_color(ColorType::ones() * 0.5).to_material()).to_object());
+    let world = world.to_object();
+    cam.sample_per_pixel = 16;
+    cam.adaptive_sampling = Some(AdaptiveSampling { min_samples: 4, threshold: 0.05 });
+    cam.samples_per_pass = 4;
+
+    for sampler in [SamplerKind::Independent, SamplerKind::Sobol] {
+      cam.sampler = sampler;
+      cam.seed = 1;
+      (cam.thread_limit, cam.tile_size, cam.tile_order) = (1, 32, TileOrder::Scanline);
+      let reference = cam.render(&world);
+      (cam.thread_limit, cam.tile_size, cam.tile_order) = (8, 5, TileOrder::Hilbert);
+      assert!(cam.render(&world) == reference);
+      cam.seed = 2;
+      assert!(cam.render(&world) != reference);
+    }
+  }
+
+  #[test]
+  fn test_crop() {
+    let (mut cam, world) = test_camera_and_world();
+    cam.sample_per_pixel = 8;
+    let full = cam.render(&world);
+
+    let region = Tile { x_min: 5, x_max: 17, y_min: 9, y_max: 24 };
+    cam.crop = Some(region);
+    let img = cam.render(&world);
+    assert!(img.crop(&region) == full.crop(&region)); // same pixels as in the full frame
+    assert_eq!(img.sample_count(4, 9), 0);
+    assert_eq!(img.sample_count(5, 8), 0);
+  }
+
+  #[test]
+  fn test_furnace() {
+    // inside a closed sphere that reflects 80% and emits 0.2, every path converges to 0.2 / (1 - 0.8) = 1
+    let mut world = HittableList::default();
+    let wall = LambertianWithLight::new_by_color(ColorType::ones() * 0.8, ColorType::ones() * 0.25).to_material();
+    world.add(Sphere::new_static(Point3::zero(), 10.0, wall).to_object());
+    let world = world.to_object();
+    let mut cam = Camera::new(
+      1.0, 16, 64, 100000, 60.0,
+      Point3::zero(), Point3::new(0.0, 0.0, -1.0), Vec3::new(0.0, 1.0, 0.0),
+      0.0, 1.0, ColorType::zero(),
+    );
+    cam.thread_limit = 4;
+    let mean_luminance = |img: &FrameBuffer| {
+      (0..img.height).flat_map(|j| (0..img.width).map(move |i| (i, j))).map(|(i, j)| luminance(img.get(i, j))).sum::<f64>()
+        / (img.width * img.height) as f64
+    };
+
+    assert!((mean_luminance(&cam.render(&world)) - 1.0).abs() < 0.02);
+
+    cam.russian_roulette_depth = None;
+    cam.max_ray_depth = 5; // cut after 5 segments: 0.2 * (1 + 0.8 + ... + 0.8^4)
+    assert!((mean_luminance(&cam.render(&world)) - (1.0 - 0.8_f64.powi(5))).abs() < 1e-9);
+  }
+
+  #[test]
+  fn test_spectral_rendering() {
+    // inside an emitting sphere, looking at glass that disperses light: the glass only bends the light, and paths
+    // through it keep their hero wavelength only, so the image is the colour of the sphere in both modes
+    let mean_color = |cam: &Camera, world: &Object| {
+      let img = cam.render(world);
+      (0..img.height).flat_map(|j| (0..img.width).map(move |i| (i, j))).map(|(i, j)| img.get(i, j))
+        .fold(ColorType::zero(), |a, b| a + b) / (img.width * img.height) as f64
+    };
+    for emit in [ColorType::ones(), ColorType::new(0.8, 0.4, 0.2)] {
+      let mut world = HittableList::default();
+      world.add(Sphere::new_static(Point3::zero(), 10.0, DiffuseLight::new_by_color(emit).to_material()).to_object());
+      world.add(Sphere::new_static(Point3::new(0.0, 0.0, -3.0), 1.5,
+        Dielectric::new_dispersive(Dispersion::from_name("sf11").unwrap()).to_material()).to_object());
+      let world = world.to_object();
+      let mut cam = Camera::new(
+        1.0, 16, 64, 50, 60.0,
+        Point3::zero(), Point3::new(0.0, 0.0, -1.0), Vec3::new(0.0, 1.0, 0.0),
+        0.0, 1.0, ColorType::zero(),
+      );
+      cam.thread_limit = 4;
+      cam.russian_roulette_depth = None;
+      let color = mean_color(&cam, &world);
+      assert!((color - emit).norm() < 1e-3, "{:?}", color);
+      cam.spectral = true;
+      let color = mean_color(&cam, &world);
+      assert!((color - emit).norm() < 0.03, "{:?}", color);
+    }
+  }
+
+  #[test]
+  fn test_shadow_ray_false_hits() {
+    // a floor point inside absorbing glass, under a light, with clear water of lower priority in between: the
+    // water's surfaces are false hits, and the light arrives as if the water were not there
+    let floor = Lambertian::new_by_color(ColorType::ones() * 0.5).to_material();
+    let glass = Dielectric::new(1.5).with_absorption(ColorType::new(0.2, 0.4, 0.6)).with_priority(1).to_material();
+    let water = Dielectric::new(1.33).to_material();
+    let light = Quad::new(Point3::new(-0.5, 2.0, -0.5), Vec3::new(1.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 1.0),
+      DiffuseLight::new_by_color(ColorType::ones() * 4.0).to_material()).to_object();
+    let mut cam = Camera::new(
+      1.0, 16, 1, 10, 90.0,
+      Point3::new(0.0, 1.0, 1.0), Point3::zero(), Vec3::new(0.0, 1.0, 0.0),
+      0.0, 1.0, ColorType::zero(),
+    );
+    cam.light_sampling = LightSampling::Light;
+    let ray_in = Ray::new(Point3::new(0.0, 1.0, 1.0), Vec3::new(0.0, -1.0, -1.0), 0.0);
+    let rec = HitRecord::new(Point3::zero(), Vec3::new(0.0, 1.0, 0.0), floor, 1.0, true, 0.0, 0.0);
+    let mut glass_rec = HitRecord::new(Point3::zero(), Vec3::new(0.0, 1.0, 0.0), glass.clone(), 1.0, true, 0.0, 0.0);
+    glass_rec.ids = HitIds::new(&glass);
+    let mut media = InteriorStack::new();
+    media.cross(&glass_rec);
+
+    let direct = |world: Object| {
+      let lights = LightList::new(&world);
+      let mut sampler = SamplerKind::Independent.create(1, 0);
+      seed_rng(7);
+      (0..100).fold(ColorType::zero(), |sum, _| sum + cam.direct_light(&ray_in, &rec, &world, &lights, &media, None, sampler.as_mut()))
+    };
+    let without_water = direct(HittableList::new(vec![light.clone()]).to_object());
+    let with_water = direct(HittableList::new(vec![
+      light.clone(), Sphere::new_static(Point3::new(0.0, 1.0, 0.0), 0.5, water).to_object()]).to_object());
+    assert!(without_water.x > 0.0 && without_water.z < without_water.x); // absorbed in the glass
+    assert!((with_water - without_water).norm() < 1e-9 * without_water.norm(), "{:?} {:?}", with_water, without_water);
+  }
+
+  #[test]
+  fn test_next_event_estimation() {
+    // a diffuse floor under a small light: the same image either way, with much less noise from sampling the light
+    let mut world = HittableList::default();
+    world.add(Quad::new(Point3::new(-5.0, 0.0, -5.0), Vec3::new(10.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 10.0),
+      Lambertian::new_by_color(ColorType::ones() * 0.5).to_material()).to_object());
+    world.add(Translate::new(Quad::new(Point3::new(-0.25, 0.0, -0.25), Vec3::new(0.5, 0.0, 0.0), Vec3::new(0.0, 0.0, 0.5),
+      DiffuseLight::new_by_color(ColorType::ones() * 10.0).to_material()).to_object(), Vec3::new(0.0, 1.0, 0.0)).to_object());
+    let world = world.to_object();
+    let mut cam = Camera::new(
+      1.0, 16, 64, 10, 90.0,
+      Point3::new(0.0, 0.5, 2.0), Point3::zero(), Vec3::new(0.0, 1.0, 0.0),
+      0.0, 1.0, ColorType::zero(),
+    );
+    cam.thread_limit = 4;
+    let stats = |img: &FrameBuffer| {
+      let pixels: Vec<(usize, usize)> = (0..img.height).flat_map(|j| (0..img.width).map(move |i| (i, j))).collect();
+      let n = pixels.len() as f64;
+      (pixels.iter().map(|&(i, j)| luminance(img.get(i, j))).sum::<f64>() / n,
+       pixels.iter().map(|&(i, j)| img.luminance_standard_error(i, j)).sum::<f64>() / n)
+    };
+
+    cam.light_sampling = LightSampling::Bsdf;
+    let (bsdf_mean, bsdf_error) = stats(&cam.render(&world));
+    cam.light_sampling = LightSampling::Light;
+    let (light_mean, light_error) = stats(&cam.render(&world));
+    assert!((light_mean - bsdf_mean).abs() < 0.05 * bsdf_mean);
+    assert!(light_error < bsdf_error / 3.0);
+
+    cam.light_sampling = LightSampling::Mis;
+    for heuristic in [MisHeuristic::Balance, MisHeuristic::Power] {
+      cam.mis_heuristic = heuristic;
+      let (mis_mean, mis_error) = stats(&cam.render(&world));
+      assert!((mis_mean - light_mean).abs() < 0.02 * light_mean);
+      assert!(mis_error < bsdf_error / 3.0);
+    }
+    assert_eq!(MisHeuristic::Power.weight(3.0, 1.0), 0.9);
+    assert_eq!(MisHeuristic::Balance.weight(3.0, 1.0) + MisHeuristic::Balance.weight(1.0, 3.0), 1.0);
+    assert_eq!(MisHeuristic::Power.weight(2.0, 0.0), 1.0);
+  }
+
+  #[test]
+  fn test_filters_across_tiles() {
+    let (mut cam, world) = test_camera_and_world();
+    cam.sample_per_pixel = 8;
+    cam.background = ColorType::new(0.25, 0.5, 1.0);
+    for name in FILTER_NAMES {
+      cam.filter = PixelFilter::from_name(name, None).unwrap();
+      (cam.thread_limit, cam.tile_size) = (1, 32);
+      let reference = cam.render(&world);
+      assert!((reference.get(0, 0) - cam.background).norm() < 1e-12); // weights normalised, also at the image border
+      assert!((reference.get(12, 12) - ColorType::ones()).norm() < 1e-12);
+
+      (cam.thread_limit, cam.tile_size) = (8, 5);
+      let img = cam.render(&world);
+      assert!(cam.render(&world) == img); // tiles merged in a fixed order
+      assert!(img.mean_squared_error(&reference) < 1e-24); // samples splatted across tile boundaries
+    }
+  }
+
+  #[test]
+  fn test_aovs() {
+    let (mut cam, _) = test_camera_and_world();
+    let mut world = HittableList::default();
+    world.add(Sphere::new_static(Point3::zero(), 1.0, Lambertian::new_by_color(ColorType::new(0.2, 0.4, 0.6)).to_material()).to_object());
+    let world = world.to_object();
+    cam.sample_per_pixel = 8;
+    cam.background = ColorType::new(0.7, 0.8, 1.0);
+    let beauty = cam.render(&world);
+    cam.aovs = ALL_AOVS.to_vec();
+    let img = cam.render(&world);
+    assert_eq!(img.aovs.len(), ALL_AOVS.len());
+    for j in 0..img.height {
+      for i in 0..img.width {
+        assert_eq!(img.get(i, j), beauty.get(i, j)); // the AOVs do not change the render
+      }
+    }
+
+    let layer = |aov| img.aov(aov).unwrap();
+    assert!((layer(Aov::Albedo).get(12, 12).unwrap() - ColorType::new(0.2, 0.4, 0.6)).norm() < 1e-9);
+    let p = layer(Aov::Position).get(12, 12).unwrap();
+    assert!(p.z > 0.99);
+    assert!((layer(Aov::Normal).get(12, 12).unwrap() - p).norm() < 1e-9); // unit sphere at the origin
+    assert!((layer(Aov::Depth).get(12, 12).unwrap().x - (10.0 - p.z)).abs() < 1e-9);
+    assert!(layer(Aov::ObjectId).get(12, 12).unwrap().x >= 1.0);
+    assert!(layer(Aov::MaterialId).get(12, 12).unwrap().x >= 1.0);
+
+    assert!((layer(Aov::Albedo).get(0, 0).unwrap() - cam.background).norm() < 1e-9); // missed
+    assert_eq!(layer(Aov::Depth).get(0, 0), None);
+    assert_eq!(layer(Aov::ObjectId).get(0, 0), None);
+  }
+
+  // records the events and cancels the render after `cancel_after` tiles
+  struct TestObserver {
+    cancel: CancelToken,
+    cancel_after: usize,
+    events: std::cell::RefCell<Vec<String>>,
+    last_progress: std::cell::Cell<Option<RenderProgress>>,
+  }
+
+  impl RenderObserver for TestObserver {
+    fn pass_started(&self, progress: &RenderProgress) {
+      self.events.borrow_mut().push(format!("pass {}", progress.pass));
+    }
+
+    fn tile_completed(&self, tile: &Tile, img: &FrameBuffer, progress: &RenderProgress) {
+      assert!(img.sample_count(tile.x_min, tile.y_min) > 0 || self.cancel.is_cancelled());
+      self.events.borrow_mut().push("tile".to_string());
+      self.last_progress.set(Some(*progress));
+      if progress.tiles_done == self.cancel_after {
+        self.cancel.cancel();
+      }
+    }
+
+    fn render_finished(&self, _img: &FrameBuffer, cancelled: bool) {
+      self.events.borrow_mut().push(format!("finished {}", cancelled));
+    }
+  }
+
+  #[test]
+  fn test_observer_and_cancel() {
+    let (mut cam, world) = test_camera_and_world();
+    (cam.thread_limit, cam.tile_size, cam.samples_per_pass) = (1, 8, 16);
+    let mut observer = TestObserver {
+      cancel: CancelToken::new(),
+      cancel_after: usize::MAX,
+      events: Default::default(),
+      last_progress: Default::default(),
+    };
+
+    let img = cam.render_with(&world, FrameBuffer::new(24, 24), &observer, &observer.cancel.clone());
+    assert_eq!(img.min_sample_count(), 64);
+    let events = observer.events.take();
+    assert_eq!(events.len(), 1 + 9 + 1);
+    assert_eq!(events.last().unwrap(), "finished false");
+    let progress = observer.last_progress.get().unwrap();
+    assert_eq!((progress.tiles_done, progress.fraction, progress.eta), (9, 1.0, Some(std::time::Duration::ZERO)));
+
+    observer.cancel_after = 3; // of 9 tiles, scanline order
+    let img = cam.render_with(&world, FrameBuffer::new(24, 24), &observer, &observer.cancel.clone());
+    assert_eq!(img.sample_count(0, 0), 64);
+    assert_eq!(img.sample_count(23, 23), 0);
+    assert_eq!(observer.events.take().last().unwrap(), "finished true");
+    assert!(observer.last_progress.get().unwrap().fraction < 1.0);
+  }
+
+  // The scheduler the worker pool replaced: a scoped thread per tile of a 50x50 grid, at most 20 of them
+  // at a time, each writing its tile back under a Mutex. Kept for bench_tile_scheduler.
+  fn render_spawn_per_tile(cam: &Camera, world: &Object) -> FrameBuffer {
+    use std::sync::{Condvar, Mutex};
+    let (width, height) = (cam.image_width, cam.image_height());
+    let pass_start = FrameBuffer::new(width, height);
+    let mut img = pass_start.clone();
+    let cancel = CancelToken::new();
+    let lights = LightList::new(world);
+    let img_mtx = Mutex::new(&mut img);
+    let running = (Mutex::new(0), Condvar::new());
+    let (chunk_width, chunk_height) = ((width + 49) / 50, (height + 49) / 50);
+    crossbeam::thread::scope(|scope| {
+      for j in 0..50 {
+        for i in 0..50 {
+          let tile = Tile {
+            x_min: (i * chunk_width).min(width), x_max: ((i + 1) * chunk_width).min(width),
+            y_min: (j * chunk_height).min(height), y_max: ((j + 1) * chunk_height).min(height),
+          };
+          if tile.x_min == tile.x_max || tile.y_min == tile.y_max {
+            continue;
+          }
+          let mut count = running.0.lock().unwrap();
+          while *count >= 20 {
+            count = running.1.wait(count).unwrap();
+          }
+          *count += 1;
+          drop(count);
+          let (img_mtx, running, pass_start, cancel, lights) = (&img_mtx, &running, &pass_start, &cancel, &lights);
+          scope.spawn(move |_| {
+            let buff = cam.render_sub(world, lights, pass_start, &tile, cam.sample_per_pixel as u32, cancel);
+            img_mtx.lock().unwrap().accumulate(tile.x_min, tile.y_min, &buff);
+            *running.0.lock().unwrap() -= 1;
+            running.1.notify_one();
+          });
+        }
+      }
+    }).unwrap();
+    img
+  }
+
+  // cargo test --release bench_tile_scheduler -- --ignored --nocapture
+  #[test]
+  #[ignore]
+  fn bench_tile_scheduler() {
+    use std::time::Instant;
+    let scene = load_scene("scenes/cornell_box.toml").unwrap();
+    let mut cam = scene.camera;
+    cam.sample_per_pixel = 4;
+    let time = |render: &dyn Fn() -> FrameBuffer| {
+      let start = Instant::now();
+      let img = render();
+      (start.elapsed().as_secs_f64(), img)
+    };
+
+    let (baseline, reference) = time(&|| render_spawn_per_tile(&cam, &scene.world));
+    println!("spawn per tile, 20 threads: {:.3}s", baseline);
+    let threads = cam.thread_limit;
+    let configs = [
+      (20, 32, TileOrder::Scanline),
+      (threads, 32, TileOrder::Scanline), (threads, 32, TileOrder::Spiral), (threads, 32, TileOrder::Hilbert),
+      (threads, 16, TileOrder::Scanline), (threads, 64, TileOrder::Scanline),
+    ];
+    for (thread_limit, tile_size, tile_order) in configs {
+      (cam.thread_limit, cam.tile_size, cam.tile_order) = (thread_limit, tile_size, tile_order);
+      let (seconds, img) = time(&|| cam.render(&scene.world));
+      assert!(img == reference);
+      println!("pool of {} threads, {:?} {}px tiles: {:.3}s ({:.2}x)", thread_limit, tile_order, tile_size, seconds, baseline / seconds);
+    }
+  }
+}
@@ -10,6 +10,81 @@ use image::{RgbImage};
 // color type
 pub type ColorType = Vec3;
 
+// Rec. 709 / sRGB luminance of a linear color
+pub fn luminance(color: ColorType) -> f64 {
+    0.2126 * color.x + 0.7152 * color.y + 0.0722 * color.z
+}
+
+// Transfer functions between linear values and encoded (8/16-bit image) values.
+// Srgb is the exact piecewise curve of IEC 61966-2-1; Gamma(g) is a pure power law
+// (Gamma(GAMMA_COEFFICIENT) is what linear_to_gamma / gamma_to_linear below approximate sRGB with).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TransferFunction {
+    Srgb,
+    Gamma(f64),
+    Linear,
+}
+
+// sRGB OETF: linear -> encoded
+pub fn srgb_encode(linear: f64) -> f64 {
+    if linear <= 0.0 {
+        0.0
+    } else if linear <= 0.0031308 {
+        12.92 * linear
+    } else {
+        1.055 * linear.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+// sRGB EOTF: encoded -> linear
+pub fn srgb_decode(encoded: f64) -> f64 {
+    if encoded <= 0.0 {
+        0.0
+    } else if encoded <= 0.04045 {
+        encoded / 12.92
+    } else {
+        ((encoded + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+impl TransferFunction {
+    // accepts "srgb", "linear" and "gamma<exponent>", e.g. "gamma2.2"
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "srgb" => Some(Self::Srgb),
+            "linear" => Some(Self::Linear),
+            _ => {
+                let exponent: f64 = name.strip_prefix("gamma")?.parse().ok()?;
+                if exponent.is_finite() && exponent > 0.0 { Some(Self::Gamma(exponent)) } else { None }
+            }
+        }
+    }
+
+    pub fn encode(&self, linear: f64) -> f64 {
+        match *self {
+            Self::Srgb => srgb_encode(linear),
+            Self::Gamma(g) => if linear > 0.0 { linear.powf(1.0 / g) } else { 0.0 },
+            Self::Linear => linear,
+        }
+    }
+
+    pub fn decode(&self, encoded: f64) -> f64 {
+        match *self {
+            Self::Srgb => srgb_decode(encoded),
+            Self::Gamma(g) => if encoded > 0.0 { encoded.powf(g) } else { 0.0 },
+            Self::Linear => encoded,
+        }
+    }
+
+    pub fn encode_ColorType(&self, color: ColorType) -> ColorType {
+        ColorType::new(self.encode(color.x), self.encode(color.y), self.encode(color.z))
+    }
+
+    pub fn decode_ColorType(&self, color: ColorType) -> ColorType {
+        ColorType::new(self.decode(color.x), self.decode(color.y), self.decode(color.z))
+    }
+}
+
 pub fn linear_to_gamma(linear_component: f64) -> f64 {
     if linear_component > 0.0 {
         linear_component.powf(1.0 / GAMMA_COEFFICIENT)
@@ -61,10 +136,45 @@ pub fn convert_ColorType_to_u8Array(pixel_color: ColorType) -> [u8; 3] {
 
 // write color in ColorType (range [0, 1)) with gamma correction
 pub fn write_color_01(pixel_color: ColorType, img: &mut RgbImage, i: usize, j: usize) {
+    write_color_01_with(pixel_color, img, i, j, TransferFunction::Gamma(GAMMA_COEFFICIENT));
+}
+
+// write color in ColorType (range [0, 1)), encoded with the given transfer function
+pub fn write_color_01_with(pixel_color: ColorType, img: &mut RgbImage, i: usize, j: usize, transfer: TransferFunction) {
     let pixel = img.get_pixel_mut(i.try_into().unwrap(), j.try_into().unwrap());
 
-    let pixel_color = linear_to_gamma_ColorType(pixel_color);
+    let pixel_color = transfer.encode_ColorType(pixel_color);
 
     *pixel = image::Rgb(convert_ColorType_to_u8Array(pixel_color));
     // Write the translated [0,255] value of each color component.
-}
\ No newline at end of file
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    #[test]
+    fn test_srgb() {
+        // reference values of the sRGB curve
+        assert!((srgb_encode(0.5) - 0.735357).abs() < 1e-5);
+        assert!((srgb_decode(0.5) - 0.214041).abs() < 1e-5);
+        assert!((srgb_encode(0.002) - 0.02584).abs() < 1e-5); // linear segment
+        assert!((srgb_encode(1.0) - 1.0).abs() < 1e-12);
+
+        for k in 0..=255 {
+            let v = k as f64 / 255.0;
+            assert!((srgb_encode(srgb_decode(v)) - v).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn test_transfer_function_names() {
+        assert_eq!(TransferFunction::from_name("srgb"), Some(TransferFunction::Srgb));
+        assert_eq!(TransferFunction::from_name("gamma2.2"), Some(TransferFunction::Gamma(2.2)));
+        assert_eq!(TransferFunction::from_name("linear"), Some(TransferFunction::Linear));
+        assert_eq!(TransferFunction::from_name("gamma0"), None);
+        assert_eq!(TransferFunction::from_name("rec709"), None);
+        assert!((TransferFunction::Gamma(2.0).encode(0.25) - linear_to_gamma(0.25)).abs() < 1e-12);
+    }
+}
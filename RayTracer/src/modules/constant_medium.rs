@@ -14,14 +14,17 @@ pub struct ConstantMedium {
   boundary: Object,
   neg_inv_density: f64,
   phase_function: Material,
+  ids: HitIds,
 }
 
 impl ConstantMedium {
   pub fn new(boundary: Object, density: f64, tex: Texture) -> Self {
+    let phase_function = Isotropic::new(tex).to_material();
     Self {
       boundary,
       neg_inv_density: -1.0 / density,
-      phase_function: Isotropic::new(tex).to_material(),
+      ids: HitIds::new(&phase_function),
+      phase_function,
     }
   }
 
@@ -68,6 +71,7 @@ impl Hittable for ConstantMedium {
 
 
     rec.mat = self.phase_function.clone();
+    rec.ids = self.ids;
     // rec.normal and front_face is aribitrary
     true
   }
@@ -0,0 +1,269 @@
+// Post-render denoiser: edge-avoiding à-trous wavelet filter (Dammertz et al. 2010) with the variance-guided
+// luminance weight of SVGF (Schied et al. 2017).
+// Each iteration blurs with a 5x5 B3-spline kernel whose taps are 2^iteration pixels apart, and lowers the weight of
+// taps that differ from the centre pixel in normal, depth or (relative to the pixel's noise) luminance.
+// The colour is divided by the albedo before filtering and multiplied back after, so textures stay sharp.
+// The guides come from the Albedo, Normal and Depth AOV layers of the buffer; missing ones are not used.
+
+use crate::aov::{*};
+use crate::color::{*};
+use crate::framebuffer::{*};
+
+pub const DENOISE_AOVS: [Aov; 3] = [Aov::Albedo, Aov::Normal, Aov::Depth];
+
+const KERNEL: [f64; 5] = [1.0 / 16.0, 1.0 / 4.0, 3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0];
+const ALBEDO_MIN: f64 = 0.01; // below this, the albedo channel is not divided out
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DenoiseSettings {
+  pub iterations: usize, // the filter reaches 2^(iterations + 1) pixels from the centre
+  pub sigma_luminance: f64, // in standard deviations of the pixel's luminance
+  pub sigma_normal: f64, // exponent on the cosine between the normals
+  pub sigma_depth: f64, // in units of the depth change across the tap offset
+}
+
+impl DenoiseSettings {
+  pub fn default() -> Self {
+    Self {
+      iterations: 5,
+      sigma_luminance: 4.0,
+      sigma_normal: 128.0,
+      sigma_depth: 1.0,
+    }
+  }
+}
+
+// per-pixel filter state
+#[derive(Clone, Copy)]
+struct Pixel {
+  color: ColorType, // albedo divided out
+  variance: f64, // of the luminance of `color`
+}
+
+// Per-pixel guides, None where the layer is missing or the pixel saw only background.
+struct Guides {
+  albedo: Vec<Option<ColorType>>,
+  normal: Vec<Option<ColorType>>,
+  depth: Vec<Option<f64>>,
+  depth_gradient: Vec<f64>, // per pixel
+}
+
+impl Guides {
+  fn new(img: &FrameBuffer) -> Self {
+    let layer = |aov| -> Vec<Option<ColorType>> {
+      match img.aov(aov) {
+        Some(layer) => (0..img.height).flat_map(|j| (0..img.width).map(move |i| layer.get(i, j))).collect(),
+        None => vec![None; img.width * img.height],
+      }
+    };
+    let depth: Vec<Option<f64>> = layer(Aov::Depth).iter().map(|d| d.map(|d| d.x)).collect();
+    let mut depth_gradient = vec![0.0; depth.len()];
+    for j in 0..img.height {
+      for i in 0..img.width {
+        let idx = j * img.width + i;
+        if let Some(d) = depth[idx] {
+          let mut gradient: f64 = 0.0;
+          for (di, dj) in [(-1, 0), (1, 0), (0, -1), (0, 1)] {
+            let (x, y) = (i as i64 + di, j as i64 + dj);
+            if x >= 0 && y >= 0 && (x as usize) < img.width && (y as usize) < img.height {
+              if let Some(dq) = depth[y as usize * img.width + x as usize] {
+                gradient = gradient.max((dq - d).abs());
+              }
+            }
+          }
+          depth_gradient[idx] = gradient;
+        }
+      }
+    }
+    Self { albedo: layer(Aov::Albedo), normal: layer(Aov::Normal), depth, depth_gradient }
+  }
+
+  // edge-stopping weight of the normal and depth guides between pixels p and q, `distance` pixels apart
+  fn weight(&self, settings: &DenoiseSettings, p: usize, q: usize, distance: f64) -> f64 {
+    let mut weight = 1.0;
+    if let (Some(np), Some(nq)) = (self.normal[p], self.normal[q]) {
+      weight *= np.dot(&nq).max(0.0).powf(settings.sigma_normal);
+    }
+    match (self.depth[p], self.depth[q]) {
+      (Some(dp), Some(dq)) => weight *= (-(dp - dq).abs() / (settings.sigma_depth * self.depth_gradient[p] * distance + 1e-6)).exp(),
+      (None, None) => {}
+      _ => weight = 0.0, // surface and background
+    }
+    weight
+  }
+}
+
+fn demodulate(color: ColorType, albedo: Option<ColorType>) -> ColorType {
+  let albedo = albedo.unwrap_or(ColorType::ones());
+  let divide = |c: f64, a: f64| if a > ALBEDO_MIN { c / a } else { c };
+  ColorType::new(divide(color.x, albedo.x), divide(color.y, albedo.y), divide(color.z, albedo.z))
+}
+
+fn remodulate(color: ColorType, albedo: Option<ColorType>) -> ColorType {
+  let albedo = albedo.unwrap_or(ColorType::ones());
+  let multiply = |c: f64, a: f64| if a > ALBEDO_MIN { c * a } else { c };
+  ColorType::new(multiply(color.x, albedo.x), multiply(color.y, albedo.y), multiply(color.z, albedo.z))
+}
+
+// variance of the luminance over the 7x7 neighbourhood, weighted by the guides
+fn spatial_variance(pixels: &[Pixel], width: usize, height: usize, guides: &Guides, settings: &DenoiseSettings) -> Vec<f64> {
+  let mut variance = Vec::with_capacity(pixels.len());
+  for j in 0..height {
+    for i in 0..width {
+      let p = j * width + i;
+      let (mut sum, mut sum_sq, mut weight_sum) = (0.0, 0.0, 0.0);
+      for y in j.saturating_sub(3)..(j + 4).min(height) {
+        for x in i.saturating_sub(3)..(i + 4).min(width) {
+          let q = y * width + x;
+          let distance = ((x as f64 - i as f64).powi(2) + (y as f64 - j as f64).powi(2)).sqrt();
+          let weight = if p == q { 1.0 } else { guides.weight(settings, p, q, distance) };
+          let l = luminance(pixels[q].color);
+          sum += weight * l;
+          sum_sq += weight * l * l;
+          weight_sum += weight;
+        }
+      }
+      let mean = sum / weight_sum;
+      variance.push((sum_sq / weight_sum - mean * mean).max(0.0));
+    }
+  }
+  variance
+}
+
+// One à-trous iteration with taps `step` pixels apart.
+fn filter_step(pixels: &[Pixel], width: usize, height: usize, guides: &Guides, settings: &DenoiseSettings, step: usize) -> Vec<Pixel> {
+  // the luminance weight uses the variance blurred over 3x3 pixels, which is less noisy itself
+  let mut std_dev = vec![0.0; pixels.len()];
+  for j in 0..height {
+    for i in 0..width {
+      let (mut sum, mut weight_sum) = (0.0, 0.0);
+      for dj in -1..=1_i64 {
+        for di in -1..=1_i64 {
+          let (x, y) = (i as i64 + di, j as i64 + dj);
+          if x >= 0 && y >= 0 && (x as usize) < width && (y as usize) < height {
+            let weight = KERNEL[(di + 2) as usize] * KERNEL[(dj + 2) as usize];
+            sum += weight * pixels[y as usize * width + x as usize].variance;
+            weight_sum += weight;
+          }
+        }
+      }
+      std_dev[j * width + i] = (sum / weight_sum).sqrt();
+    }
+  }
+
+  let mut filtered = Vec::with_capacity(pixels.len());
+  for j in 0..height {
+    for i in 0..width {
+      let p = j * width + i;
+      let lp = luminance(pixels[p].color);
+      let (mut color, mut variance, mut weight_sum) = (ColorType::zero(), 0.0, 0.0);
+      for (kj, dj) in (-2..=2_i64).enumerate() {
+        for (ki, di) in (-2..=2_i64).enumerate() {
+          let (x, y) = (i as i64 + di * step as i64, j as i64 + dj * step as i64);
+          if x < 0 || y < 0 || x as usize >= width || y as usize >= height {
+            continue;
+          }
+          let q = y as usize * width + x as usize;
+          let distance = ((di * di + dj * dj) as f64).sqrt() * step as f64;
+          let luminance_weight = (-(lp - luminance(pixels[q].color)).abs() / (settings.sigma_luminance * std_dev[p] + 1e-6)).exp();
+          let weight = KERNEL[ki] * KERNEL[kj] * luminance_weight * if p == q { 1.0 } else { guides.weight(settings, p, q, distance) };
+          color += pixels[q].color * weight;
+          variance += weight * weight * pixels[q].variance;
+          weight_sum += weight;
+        }
+      }
+      filtered.push(Pixel { color: color / weight_sum, variance: variance / (weight_sum * weight_sum) });
+    }
+  }
+  filtered
+}
+
+// Denoised copy of `img`. Sample counts and AOV layers are kept; the pixels lose their variance.
+pub fn denoise(img: &FrameBuffer, settings: &DenoiseSettings) -> FrameBuffer {
+  let (width, height) = (img.width, img.height);
+  let guides = Guides::new(img);
+
+  let mut pixels = Vec::with_capacity(width * height);
+  for j in 0..height {
+    for i in 0..width {
+      let albedo = guides.albedo[j * width + i];
+      let color = demodulate(img.get(i, j), albedo);
+      let se = img.luminance_standard_error(i, j); // of the mean luminance
+      let variance = if se.is_finite() {
+        let l_albedo = luminance(albedo.unwrap_or(ColorType::ones())).max(ALBEDO_MIN);
+        (se / l_albedo).powi(2)
+      } else {
+        luminance(color).powi(2) // one sample: as noisy as it is bright
+      };
+      pixels.push(Pixel { color, variance });
+    }
+  }
+
+  // With few samples, a pixel's own variance estimate is as noisy as its colour (zero if every sample missed the light),
+  // so it is raised to the variance of its neighbours on the same surface (SVGF's spatial estimate).
+  let spatial = spatial_variance(&pixels, width, height, &guides, settings);
+  for (pixel, spatial) in pixels.iter_mut().zip(spatial) {
+    pixel.variance = pixel.variance.max(spatial);
+  }
+
+  for iteration in 0..settings.iterations {
+    pixels = filter_step(&pixels, width, height, &guides, settings, 1 << iteration);
+  }
+
+  let mut denoised = FrameBuffer::new(width, height);
+  for j in 0..height {
+    for i in 0..width {
+      let color = remodulate(pixels[j * width + i].color, guides.albedo[j * width + i]);
+      let count = img.sample_count(i, j);
+      let l = luminance(color);
+      denoised.add_samples(i, j, color * count as f64, l * l * count as f64, count);
+    }
+  }
+  denoised.aovs = img.aovs.clone();
+  denoised
+}
+
+// `left` and `right` next to each other, for comparing e.g. the noisy and the denoised render
+pub fn side_by_side(left: &FrameBuffer, right: &FrameBuffer) -> FrameBuffer {
+  let mut img = FrameBuffer::new(left.width + right.width, left.height.max(right.height));
+  img.paste(0, 0, left);
+  img.paste(left.width, 0, right);
+  img
+}
+
+
+#[cfg(test)]
+mod tests {
+  use crate::*;
+
+  #[test]
+  fn test_denoise() {
+    // two walls meeting at x = 16: grey on the left, white on the right, with different normals
+    let (width, height) = (32, 16);
+    let mut noisy = FrameBuffer::new(width, height);
+    noisy.add_aov_layers(&DENOISE_AOVS);
+    let mut clean = FrameBuffer::new(width, height);
+    seed_rng(1);
+    for j in 0..height {
+      for i in 0..width {
+        let (albedo, normal) = if i < 16 { (0.5, Vec3::new(1.0, 0.0, 0.0)) } else { (1.0, Vec3::new(0.0, 0.0, 1.0)) };
+        for _ in 0..4 {
+          noisy.add_sample(i, j, ColorType::ones() * albedo * rand_range(0.0, 2.0));
+          noisy.aov_mut(Aov::Albedo).unwrap().add(i, j, Some(ColorType::ones() * albedo));
+          noisy.aov_mut(Aov::Normal).unwrap().add(i, j, Some(normal));
+          noisy.aov_mut(Aov::Depth).unwrap().add(i, j, Some(ColorType::ones() * 5.0));
+        }
+        clean.add_sample(i, j, ColorType::ones() * albedo);
+      }
+    }
+
+    let denoised = denoise(&noisy, &DenoiseSettings::default());
+    assert!(denoised.mean_squared_error(&clean) < noisy.mean_squared_error(&clean) * 0.1);
+    assert!(luminance(denoised.get(15, 8)) < 0.6 && luminance(denoised.get(16, 8)) > 0.8); // the edge survives
+    assert_eq!(denoised.sample_count(3, 3), 4);
+
+    let both = side_by_side(&noisy, &denoised);
+    assert_eq!((both.width, both.height), (64, 16));
+    assert_eq!(both.get(width + 3, 3), denoised.get(3, 3));
+  }
+}
@@ -0,0 +1,102 @@
+// Pixel reconstruction filters
+// Every camera sample is taken inside its own pixel but counts towards all the pixels whose filter covers it,
+// with the filter's weight at the sample's offset from their centre; a pixel is the weighted mean of those samples.
+// Box with radius 0.5 is the plain per-pixel average; the other filters trade sharpness for less aliasing.
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PixelFilter {
+  Box { radius: f64 },
+  Tent { radius: f64 },
+  Gaussian { radius: f64, alpha: f64 },
+  Mitchell { radius: f64, b: f64, c: f64 }, // Mitchell-Netravali
+  Lanczos { radius: f64, tau: f64 }, // windowed sinc
+}
+
+pub const FILTER_NAMES: [&str; 5] = ["box", "tent", "gaussian", "mitchell", "lanczos"];
+
+fn sinc(x: f64) -> f64 {
+  let x = x.abs();
+  if x < 1e-5 { 1.0 } else { (crate::PI * x).sin() / (crate::PI * x) }
+}
+
+fn mitchell_1d(x: f64, b: f64, c: f64) -> f64 { // x in [-2, 2]
+  let x = x.abs();
+  if x > 2.0 {
+    0.0
+  } else if x > 1.0 {
+    ((-b - 6.0 * c) * x * x * x + (6.0 * b + 30.0 * c) * x * x + (-12.0 * b - 48.0 * c) * x + (8.0 * b + 24.0 * c)) / 6.0
+  } else {
+    ((12.0 - 9.0 * b - 6.0 * c) * x * x * x + (-18.0 + 12.0 * b + 6.0 * c) * x * x + (6.0 - 2.0 * b)) / 6.0
+  }
+}
+
+impl PixelFilter {
+  pub fn default() -> Self {
+    Self::Box { radius: 0.5 }
+  }
+
+  // the named filter with its usual parameters; `radius` overrides the default radius
+  pub fn from_name(name: &str, radius: Option<f64>) -> Option<Self> {
+    match name {
+      "box" => Some(Self::Box { radius: radius.unwrap_or(0.5) }),
+      "tent" => Some(Self::Tent { radius: radius.unwrap_or(1.0) }),
+      "gaussian" => Some(Self::Gaussian { radius: radius.unwrap_or(1.5), alpha: 2.0 }),
+      "mitchell" => Some(Self::Mitchell { radius: radius.unwrap_or(2.0), b: 1.0 / 3.0, c: 1.0 / 3.0 }),
+      "lanczos" => Some(Self::Lanczos { radius: radius.unwrap_or(3.0), tau: 3.0 }),
+      _ => None,
+    }
+  }
+
+  pub fn radius(&self) -> f64 {
+    match *self {
+      Self::Box { radius } | Self::Tent { radius } | Self::Gaussian { radius, .. }
+        | Self::Mitchell { radius, .. } | Self::Lanczos { radius, .. } => radius,
+    }
+  }
+
+  // pixels a sample can reach beyond its own, in each direction
+  pub fn margin(&self) -> usize {
+    (self.radius() - 0.5).max(0.0).ceil() as usize
+  }
+
+  fn evaluate_1d(&self, x: f64) -> f64 {
+    match *self {
+      Self::Box { .. } => 1.0,
+      Self::Tent { radius } => (radius - x.abs()).max(0.0),
+      Self::Gaussian { radius, alpha } => ((-alpha * x * x).exp() - (-alpha * radius * radius).exp()).max(0.0),
+      Self::Mitchell { radius, b, c } => mitchell_1d(2.0 * x / radius, b, c),
+      Self::Lanczos { tau, .. } => sinc(x) * sinc(x / tau),
+    }
+  }
+
+  // weight of a sample at offset (x, y) from a pixel centre, 0 outside the radius; may be negative
+  // (the radius is at least 0.5, so a sample anywhere in the pixel counts towards it)
+  pub fn evaluate(&self, x: f64, y: f64) -> f64 {
+    let radius = self.radius();
+    if x.abs() > radius || y.abs() > radius {
+      return 0.0;
+    }
+    self.evaluate_1d(x) * self.evaluate_1d(y)
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use crate::*;
+
+  #[test]
+  fn test_filters() {
+    for name in FILTER_NAMES {
+      let filter = PixelFilter::from_name(name, None).unwrap();
+      assert!(filter.evaluate(0.0, 0.0) > 0.0);
+      assert_eq!(filter.evaluate(filter.radius() + 1e-9, 0.0), 0.0);
+      assert_eq!(filter.evaluate(0.1, -0.3), filter.evaluate(-0.1, 0.3)); // symmetric
+    }
+    assert_eq!(PixelFilter::default().margin(), 0);
+    assert_eq!(PixelFilter::from_name("gaussian", None).unwrap().margin(), 1);
+    assert_eq!(PixelFilter::from_name("lanczos", Some(2.0)).unwrap().margin(), 2);
+    assert!(PixelFilter::from_name("mitchell", None).unwrap().evaluate(1.5, 0.0) < 0.0); // negative lobe
+    assert_eq!(PixelFilter::default().evaluate(-0.5, 0.0), 1.0);
+  }
+}
@@ -0,0 +1,498 @@
+// Linear floating-point render target
+// Each pixel keeps the sum of its radiance samples and how many there were, so the buffer can be
+// averaged, merged with another render of the same frame, or written out without losing range.
+// The sum of squared sample luminances is kept as well, for the per-pixel variance used by adaptive sampling.
+// The displayed value is a separate weighted mean, which reconstruction filters (filter.rs) splat samples into from
+// neighbouring pixels; with the default box filter it is the plain mean of the pixel's own samples.
+// Converting to 8-bit (transfer function + clamp) is a separate display step, see to_rgb8().
+// save()/load() store the sums and counts exactly, which is what render checkpoints are made of.
+// AOV layers (see aov.rs) ride along: they are merged, cropped, pasted and saved with the pixels.
+
+use crate::aov::{*};
+use crate::color::{*};
+use crate::tile::{*};
+
+use image::{ImageBuffer, Rgb32FImage, RgbImage};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+
+// checkpoint file layout (little endian):
+//   magic, width: u64, height: u64, scene, sampler, filter: each a u32 length and UTF-8 text, seed: u64, spectral: u8,
+//   then per pixel (row major)
+//   sum: 3 x f64, sum of squared luminance: f64, sample count: u32, weighted sum: 3 x f64, weight: f64
+//   then layer count: u32, and per AOV layer its index in ALL_AOVS: u32, then per pixel sum: 3 x f64, count: u32
+const CHECKPOINT_MAGIC: &[u8; 8] = b"RTFBUF05";
+
+// What a checkpoint was rendered with. Resuming with another scene, seed, sampler, filter or mode would mix samples
+// of a different image into it, so load() takes the header the render expects and refuses any other.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CheckpointHeader {
+  pub width: usize,
+  pub height: usize,
+  pub scene: String, // scene file, or name of the built-in scene
+  pub sampler: String,
+  pub filter: String,
+  pub seed: u64,
+  pub spectral: bool,
+}
+
+fn read_u64(reader: &mut impl Read) -> std::io::Result<u64> {
+  let mut buf = [0u8; 8];
+  reader.read_exact(&mut buf)?;
+  Ok(u64::from_le_bytes(buf))
+}
+
+fn read_u32(reader: &mut impl Read) -> std::io::Result<u32> {
+  let mut buf = [0u8; 4];
+  reader.read_exact(&mut buf)?;
+  Ok(u32::from_le_bytes(buf))
+}
+
+impl CheckpointHeader {
+  fn write(&self, writer: &mut impl Write) -> std::io::Result<()> {
+    writer.write_all(CHECKPOINT_MAGIC)?;
+    writer.write_all(&(self.width as u64).to_le_bytes())?;
+    writer.write_all(&(self.height as u64).to_le_bytes())?;
+    for text in [&self.scene, &self.sampler, &self.filter] {
+      writer.write_all(&(text.len() as u32).to_le_bytes())?;
+      writer.write_all(text.as_bytes())?;
+    }
+    writer.write_all(&self.seed.to_le_bytes())?;
+    writer.write_all(&[self.spectral as u8])
+  }
+
+  // None if this is not a checkpoint
+  fn read(reader: &mut impl Read) -> std::io::Result<Option<Self>> {
+    let mut magic = [0u8; 8];
+    reader.read_exact(&mut magic)?;
+    if &magic != CHECKPOINT_MAGIC {
+      return Ok(None);
+    }
+    let (width, height) = match (usize::try_from(read_u64(reader)?), usize::try_from(read_u64(reader)?)) {
+      (Ok(width), Ok(height)) => (width, height),
+      _ => return Ok(None),
+    };
+    let mut texts = Vec::new();
+    for _ in 0..3 {
+      let len = read_u32(reader)? as usize;
+      if len > 4096 {
+        return Ok(None);
+      }
+      let mut bytes = vec![0u8; len];
+      reader.read_exact(&mut bytes)?;
+      match String::from_utf8(bytes) {
+        Ok(text) => texts.push(text),
+        Err(_) => return Ok(None),
+      }
+    }
+    let seed = read_u64(reader)?;
+    let mut spectral = [0u8; 1];
+    reader.read_exact(&mut spectral)?;
+    let filter = texts.pop().unwrap();
+    let sampler = texts.pop().unwrap();
+    let scene = texts.pop().unwrap();
+    Ok(Some(Self { width, height, scene, sampler, filter, seed, spectral: spectral[0] != 0 }))
+  }
+
+  // how the render of this checkpoint differs from `expected`, if it does
+  fn difference(&self, expected: &Self) -> Option<String> {
+    if (self.width, self.height) != (expected.width, expected.height) {
+      Some(format!("is a {}x{} render, but the camera is {}x{}", self.width, self.height, expected.width, expected.height))
+    } else if self.scene != expected.scene {
+      Some(format!("is a render of \"{}\", not \"{}\"", self.scene, expected.scene))
+    } else if self.seed != expected.seed {
+      Some(format!("was rendered with seed {}, not {}", self.seed, expected.seed))
+    } else if self.sampler != expected.sampler {
+      Some(format!("was rendered with the {} sampler, not {}", self.sampler, expected.sampler))
+    } else if self.filter != expected.filter {
+      Some(format!("was rendered with filter {}, not {}", self.filter, expected.filter))
+    } else if self.spectral != expected.spectral {
+      let mode = |spectral: bool| if spectral { "spectral" } else { "RGB" };
+      Some(format!("was rendered in {} mode, not {}", mode(self.spectral), mode(expected.spectral)))
+    } else {
+      None
+    }
+  }
+}
+
+#[derive(Clone, PartialEq)]
+pub struct FrameBuffer {
+  pub width: usize,
+  pub height: usize,
+  sum: Vec<ColorType>,
+  sum_luminance_sq: Vec<f64>,
+  sample_count: Vec<u32>,
+  weighted_sum: Vec<ColorType>, // filter-weighted samples, own and splatted from neighbours
+  weight_sum: Vec<f64>,
+  pub aovs: Vec<AovBuffer>,
+}
+
+impl FrameBuffer {
+  pub fn new(width: usize, height: usize) -> Self {
+    Self {
+      width,
+      height,
+      sum: vec![ColorType::zero(); width * height],
+      sum_luminance_sq: vec![0.0; width * height],
+      sample_count: vec![0; width * height],
+      weighted_sum: vec![ColorType::zero(); width * height],
+      weight_sum: vec![0.0; width * height],
+      aovs: Vec::new(),
+    }
+  }
+
+  // add empty layers for the AOVs in `aovs` that the buffer does not have yet
+  pub fn add_aov_layers(&mut self, aovs: &[Aov]) {
+    for &aov in aovs {
+      if self.aov(aov).is_none() {
+        self.aovs.push(AovBuffer::new(aov, self.width, self.height));
+      }
+    }
+  }
+
+  pub fn aov(&self, aov: Aov) -> Option<&AovBuffer> {
+    self.aovs.iter().find(|layer| layer.aov == aov)
+  }
+
+  pub fn aov_mut(&mut self, aov: Aov) -> Option<&mut AovBuffer> {
+    self.aovs.iter_mut().find(|layer| layer.aov == aov)
+  }
+
+  fn index(&self, i: usize, j: usize) -> usize {
+    debug_assert!(i < self.width && j < self.height);
+    j * self.width + i
+  }
+
+  pub fn add_sample(&mut self, i: usize, j: usize, color: ColorType) {
+    let l = luminance(color);
+    self.add_samples(i, j, color, l * l, 1);
+  }
+
+  // add `count` samples whose radiance sums up to `sum` and whose squared luminances sum up to `sum_luminance_sq`,
+  // with weight 1 each (box filter)
+  pub fn add_samples(&mut self, i: usize, j: usize, sum: ColorType, sum_luminance_sq: f64, count: u32) {
+    self.add_sample_stats(i, j, sum, sum_luminance_sq, count);
+    self.add_weighted(i, j, sum, count as f64);
+  }
+
+  // samples taken in pixel (i, j), for its sample count and variance only
+  pub fn add_sample_stats(&mut self, i: usize, j: usize, sum: ColorType, sum_luminance_sq: f64, count: u32) {
+    let idx = self.index(i, j);
+    self.sum[idx] += sum;
+    self.sum_luminance_sq[idx] += sum_luminance_sq;
+    self.sample_count[idx] += count;
+  }
+
+  // filter-weighted samples for the value of pixel (i, j): `weighted_sum` is the sum of weight x radiance
+  pub fn add_weighted(&mut self, i: usize, j: usize, weighted_sum: ColorType, weight: f64) {
+    let idx = self.index(i, j);
+    self.weighted_sum[idx] += weighted_sum;
+    self.weight_sum[idx] += weight;
+  }
+
+  // merge a smaller buffer (e.g. a rendered tile) whose pixel (0, 0) lands on (x0, y0)
+  pub fn accumulate(&mut self, x0: usize, y0: usize, tile: &FrameBuffer) {
+    for j in 0..tile.height {
+      for i in 0..tile.width {
+        let src = tile.index(i, j);
+        self.add_sample_stats(x0 + i, y0 + j, tile.sum[src], tile.sum_luminance_sq[src], tile.sample_count[src]);
+        self.add_weighted(x0 + i, y0 + j, tile.weighted_sum[src], tile.weight_sum[src]);
+      }
+    }
+    for layer in &tile.aovs {
+      if let Some(own) = self.aov_mut(layer.aov) {
+        own.accumulate(x0, y0, layer);
+      }
+    }
+  }
+
+  // copy of the pixels in `region`
+  pub fn crop(&self, region: &Tile) -> FrameBuffer {
+    let mut cropped = FrameBuffer::new(region.width(), region.height());
+    for j in 0..cropped.height {
+      for i in 0..cropped.width {
+        let (src, dst) = (self.index(region.x_min + i, region.y_min + j), cropped.index(i, j));
+        cropped.sum[dst] = self.sum[src];
+        cropped.sum_luminance_sq[dst] = self.sum_luminance_sq[src];
+        cropped.sample_count[dst] = self.sample_count[src];
+        cropped.weighted_sum[dst] = self.weighted_sum[src];
+        cropped.weight_sum[dst] = self.weight_sum[src];
+      }
+    }
+    cropped.aovs = self.aovs.iter().map(|layer| layer.crop(region)).collect();
+    cropped
+  }
+
+  // overwrite the pixels under `other`, whose pixel (0, 0) lands on (x0, y0)
+  pub fn paste(&mut self, x0: usize, y0: usize, other: &FrameBuffer) {
+    for j in 0..other.height {
+      for i in 0..other.width {
+        let (src, dst) = (other.index(i, j), self.index(x0 + i, y0 + j));
+        self.sum[dst] = other.sum[src];
+        self.sum_luminance_sq[dst] = other.sum_luminance_sq[src];
+        self.sample_count[dst] = other.sample_count[src];
+        self.weighted_sum[dst] = other.weighted_sum[src];
+        self.weight_sum[dst] = other.weight_sum[src];
+      }
+    }
+    for layer in &other.aovs {
+      if let Some(own) = self.aov_mut(layer.aov) {
+        own.paste(x0, y0, layer);
+      }
+    }
+  }
+
+  // one sample per pixel with the image's values
+  pub fn from_rgb32f(img: &Rgb32FImage) -> Self {
+    let mut buffer = Self::new(img.width() as usize, img.height() as usize);
+    for (i, j, pixel) in img.enumerate_pixels() {
+      buffer.add_sample(i as usize, j as usize, ColorType::new(pixel[0] as f64, pixel[1] as f64, pixel[2] as f64));
+    }
+    buffer
+  }
+
+  // filtered radiance of pixel (i, j); black if no sample reached it yet
+  pub fn get(&self, i: usize, j: usize) -> ColorType {
+    let idx = self.index(i, j);
+    if self.weight_sum[idx] == 0.0 {
+      ColorType::zero()
+    } else {
+      self.weighted_sum[idx] / self.weight_sum[idx]
+    }
+  }
+
+  pub fn sample_count(&self, i: usize, j: usize) -> u32 {
+    self.sample_count[self.index(i, j)]
+  }
+
+  pub fn min_sample_count(&self) -> u32 {
+    self.sample_count.iter().copied().min().unwrap_or(0)
+  }
+
+  pub fn max_sample_count(&self) -> u32 {
+    self.sample_count.iter().copied().max().unwrap_or(0)
+  }
+
+  // standard error of the pixel's mean luminance, from the sample variance; infinite below 2 samples
+  pub fn luminance_standard_error(&self, i: usize, j: usize) -> f64 {
+    let idx = self.index(i, j);
+    let n = self.sample_count[idx] as f64;
+    if n < 2.0 {
+      return f64::INFINITY;
+    }
+    let mean = luminance(self.sum[idx]) / n;
+    let variance = ((self.sum_luminance_sq[idx] - n * mean * mean) / (n - 1.0)).max(0.0);
+    (variance / n).sqrt()
+  }
+
+  // Written to `<path>.tmp` first and renamed, so a kill while saving leaves the previous checkpoint intact.
+  pub fn save(&self, path: &str, header: &CheckpointHeader) -> Result<(), String> {
+    debug_assert_eq!((header.width, header.height), (self.width, self.height));
+    let tmp_path = format!("{}.tmp", path);
+    let write = || -> std::io::Result<()> {
+      let mut writer = BufWriter::new(File::create(&tmp_path)?);
+      header.write(&mut writer)?;
+      for idx in 0..self.sum.len() {
+        let sum = &self.sum[idx];
+        for c in [sum.x, sum.y, sum.z, self.sum_luminance_sq[idx]] {
+          writer.write_all(&c.to_le_bytes())?;
+        }
+        writer.write_all(&self.sample_count[idx].to_le_bytes())?;
+        let weighted_sum = &self.weighted_sum[idx];
+        for c in [weighted_sum.x, weighted_sum.y, weighted_sum.z, self.weight_sum[idx]] {
+          writer.write_all(&c.to_le_bytes())?;
+        }
+      }
+      writer.write_all(&(self.aovs.len() as u32).to_le_bytes())?;
+      for layer in &self.aovs {
+        let kind = ALL_AOVS.iter().position(|&aov| aov == layer.aov).unwrap() as u32;
+        writer.write_all(&kind.to_le_bytes())?;
+        for (sum, count) in layer.pixels() {
+          for c in [sum.x, sum.y, sum.z] {
+            writer.write_all(&c.to_le_bytes())?;
+          }
+          writer.write_all(&count.to_le_bytes())?;
+        }
+      }
+      writer.into_inner().map_err(|err| err.into_error())?.sync_all()
+    };
+    write().map_err(|err| format!("cannot write \"{}\": {}", tmp_path, err))?;
+    std::fs::rename(&tmp_path, path).map_err(|err| format!("cannot rename \"{}\" to \"{}\": {}", tmp_path, path, err))
+  }
+
+  // A checkpoint of the render `expected` describes; the header is checked before anything is allocated.
+  pub fn load(path: &str, expected: &CheckpointHeader) -> Result<Self, String> {
+    let not_a_checkpoint = || format!("\"{}\" is not a render checkpoint", path);
+    let read = || -> std::io::Result<Result<Self, String>> {
+      let mut reader = BufReader::new(File::open(path)?);
+      let header = match CheckpointHeader::read(&mut reader)? {
+        Some(header) => header,
+        None => return Ok(Err(not_a_checkpoint())),
+      };
+      if let Some(difference) = header.difference(expected) {
+        return Ok(Err(format!("\"{}\" {}", path, difference)));
+      }
+      let (width, height) = (header.width, header.height);
+      let pixel_count = match width.checked_mul(height) {
+        Some(n) => n,
+        None => return Ok(Err(not_a_checkpoint())),
+      };
+
+      let mut img = Self::new(width, height);
+      let mut u64_buf = [0u8; 8];
+      let mut u32_buf = [0u8; 4];
+      for idx in 0..pixel_count {
+        let mut c = [0.0; 4];
+        for x in c.iter_mut() {
+          reader.read_exact(&mut u64_buf)?;
+          *x = f64::from_le_bytes(u64_buf);
+        }
+        reader.read_exact(&mut u32_buf)?;
+        img.sum[idx] = ColorType::new(c[0], c[1], c[2]);
+        img.sum_luminance_sq[idx] = c[3];
+        img.sample_count[idx] = u32::from_le_bytes(u32_buf);
+        for x in c.iter_mut() {
+          reader.read_exact(&mut u64_buf)?;
+          *x = f64::from_le_bytes(u64_buf);
+        }
+        img.weighted_sum[idx] = ColorType::new(c[0], c[1], c[2]);
+        img.weight_sum[idx] = c[3];
+      }
+      reader.read_exact(&mut u32_buf)?;
+      for _ in 0..u32::from_le_bytes(u32_buf) {
+        reader.read_exact(&mut u32_buf)?;
+        let aov = match ALL_AOVS.get(u32::from_le_bytes(u32_buf) as usize) {
+          Some(&aov) => aov,
+          None => return Ok(Err(not_a_checkpoint())),
+        };
+        let mut layer = AovBuffer::new(aov, width, height);
+        for idx in 0..pixel_count {
+          let mut c = [0.0; 3];
+          for x in c.iter_mut() {
+            reader.read_exact(&mut u64_buf)?;
+            *x = f64::from_le_bytes(u64_buf);
+          }
+          reader.read_exact(&mut u32_buf)?;
+          layer.set_pixel(idx, ColorType::new(c[0], c[1], c[2]), u32::from_le_bytes(u32_buf));
+        }
+        img.aovs.push(layer);
+      }
+      Ok(Ok(img))
+    };
+    read().unwrap_or_else(|err| Err(format!("cannot read \"{}\": {}", path, err)))
+  }
+
+  pub fn to_rgb32f(&self) -> Rgb32FImage {
+    ImageBuffer::from_fn(self.width as u32, self.height as u32, |i, j| {
+      let color = self.get(i as usize, j as usize);
+      image::Rgb([color.x as f32, color.y as f32, color.z as f32])
+    })
+  }
+
+  // display conversion: transfer function (e.g. sRGB) + quantization
+  pub fn to_rgb8(&self, transfer: TransferFunction) -> RgbImage {
+    let mut img = RgbImage::new(self.width as u32, self.height as u32);
+    for j in 0..self.height {
+      for i in 0..self.width {
+        write_color_01_with(self.get(i, j), &mut img, i, j, transfer);
+      }
+    }
+    img
+  }
+
+  // per-channel mean squared difference of the pixel means, for comparing two renders
+  pub fn mean_squared_error(&self, other: &FrameBuffer) -> f64 {
+    assert!(self.width == other.width && self.height == other.height, "FrameBuffer: size mismatch");
+    let mut total = 0.0;
+    for j in 0..self.height {
+      for i in 0..self.width {
+        total += (self.get(i, j) - other.get(i, j)).norm_squared();
+      }
+    }
+    total / (3 * self.width * self.height) as f64
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use crate::*;
+
+  #[test]
+  fn test_framebuffer() {
+    let mut img = FrameBuffer::new(3, 2);
+    img.add_sample(1, 0, ColorType::new(1.0, 2.0, 4.0));
+    img.add_sample(1, 0, ColorType::new(3.0, 0.0, -2.0)); // linear: no clamping
+    assert_eq!(img.get(1, 0), ColorType::new(2.0, 1.0, 1.0));
+    assert_eq!(img.sample_count(1, 0), 2);
+    assert_eq!(img.get(0, 1), ColorType::zero()); // no samples yet
+    assert_eq!(img.sample_count(0, 1), 0);
+
+    // a tile rendered on its own adds to the pixels it lands on
+    let mut tile = FrameBuffer::new(2, 1);
+    tile.add_sample(0, 0, ColorType::ones() * 5.0);
+    tile.add_sample(1, 0, ColorType::ones() * 0.5);
+    img.accumulate(1, 0, &tile);
+    assert_eq!(img.get(1, 0), ColorType::new(3.0, 7.0 / 3.0, 7.0 / 3.0));
+    assert_eq!(img.sample_count(1, 0), 3);
+    assert_eq!(img.get(2, 0), ColorType::ones() * 0.5);
+    assert_eq!((img.min_sample_count(), img.max_sample_count()), (0, 3));
+  }
+
+  #[test]
+  fn test_checkpoint_round_trip() {
+    let mut img = FrameBuffer::new(3, 2);
+    img.add_samples(0, 0, ColorType::new(1.5, -0.25, 1e300), 2.0, 7);
+    img.add_samples(2, 1, ColorType::new(0.1, 0.2, 0.3), 0.5, 3);
+    img.add_aov_layers(&[Aov::Depth, Aov::ObjectId]);
+    img.aov_mut(Aov::Depth).unwrap().add(1, 0, Some(ColorType::ones() * 2.5));
+    img.aov_mut(Aov::ObjectId).unwrap().add(1, 0, Some(ColorType::new(3.0, 0.0, 0.0)));
+    img.aov_mut(Aov::ObjectId).unwrap().add(1, 0, Some(ColorType::new(4.0, 0.0, 0.0))); // first ID wins
+
+    let header = CheckpointHeader {
+      width: 3,
+      height: 2,
+      scene: "scenes/cornell_box.toml".to_string(),
+      sampler: "Sobol".to_string(),
+      filter: "Box { radius: 0.5 }".to_string(),
+      seed: 42,
+      spectral: false,
+    };
+    let path = std::env::temp_dir().join(format!("ray_tracer_checkpoint_{}.bin", std::process::id()));
+    let path = path.to_str().unwrap();
+    img.save(path, &header).unwrap();
+    let loaded = FrameBuffer::load(path, &header).unwrap();
+    // a render with other settings does not resume from it
+    let other_size = FrameBuffer::load(path, &CheckpointHeader { width: 2, height: 3, ..header.clone() }).err().unwrap();
+    let other_seed = FrameBuffer::load(path, &CheckpointHeader { seed: 7, ..header.clone() }).err().unwrap();
+    let spectral = FrameBuffer::load(path, &CheckpointHeader { spectral: true, ..header.clone() }).err().unwrap();
+    std::fs::remove_file(path).unwrap();
+
+    assert_eq!((loaded.width, loaded.height), (3, 2));
+    for j in 0..2 {
+      for i in 0..3 {
+        assert_eq!(loaded.get(i, j), img.get(i, j));
+        assert_eq!(loaded.sample_count(i, j), img.sample_count(i, j));
+        assert_eq!(loaded.luminance_standard_error(i, j), img.luminance_standard_error(i, j));
+      }
+    }
+    assert!(loaded.aovs == img.aovs);
+    assert_eq!(loaded.aov(Aov::ObjectId).unwrap().get(1, 0), Some(ColorType::new(3.0, 0.0, 0.0)));
+    assert_eq!(loaded.min_sample_count(), 0);
+    assert!(other_size.contains("3x2 render, but the camera is 2x3"), "{}", other_size);
+    assert!(other_seed.contains("seed 42, not 7"), "{}", other_seed);
+    assert!(spectral.contains("in RGB mode, not spectral"), "{}", spectral);
+    assert!(FrameBuffer::load("Cargo.toml", &header).is_err());
+  }
+
+  #[test]
+  fn test_standard_error() {
+    let mut img = FrameBuffer::new(2, 1);
+    for k in 0..100 {
+      img.add_sample(0, 0, ColorType::ones() * 0.5);
+      img.add_sample(1, 0, ColorType::ones() * (k % 2) as f64); // mean 0.5, variance ~0.25
+    }
+    assert!(img.luminance_standard_error(0, 0) < 1e-6);
+    assert!((img.luminance_standard_error(1, 0) - (0.25 * 100.0 / 99.0 / 100.0 as f64).sqrt()).abs() < 1e-9);
+  }
+}
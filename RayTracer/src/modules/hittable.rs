@@ -8,6 +8,37 @@ use crate::material::{*};
 use crate::aabb::{*};
 use crate::bvh::{*};
 use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::collections::BTreeMap;
+
+// IDs of the primitive that was hit and of its material, for the ID AOVs; 0 means nothing was hit.
+// They are handed out in construction order, which does not change between runs because scenes are built on one thread.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct HitIds {
+  pub object: u32,
+  pub material: u32,
+}
+
+static NEXT_OBJECT_ID: AtomicU32 = AtomicU32::new(1);
+static MATERIAL_IDS: Mutex<BTreeMap<usize, u32>> = Mutex::new(BTreeMap::new()); // Arc address -> ID
+
+impl HitIds {
+  // a new object ID; primitives sharing the same `mat` Arc share the material ID
+  pub fn new(mat: &Material) -> Self {
+    let mut material_ids = MATERIAL_IDS.lock().unwrap();
+    let next_material_id = material_ids.len() as u32 + 1;
+    let material = *material_ids.entry(Arc::as_ptr(mat) as *const () as usize).or_insert(next_material_id);
+    Self {
+      object: NEXT_OBJECT_ID.fetch_add(1, Ordering::Relaxed),
+      material,
+    }
+  }
+
+  pub fn none() -> Self {
+    Self { object: 0, material: 0 }
+  }
+}
 
 
 pub struct HitRecord {
@@ -17,11 +48,17 @@ pub struct HitRecord {
   pub t: f64,
   pub u: f64, // texture coord
   pub v: f64,
-  pub front_surface: bool
+  pub front_surface: bool,
+  pub ids: HitIds,
+  pub exterior_index: f64, // index of refraction around the object (see interior.rs), set by the integrator
+  pub wavelength: Option<f64>, // hero wavelength (nm) of a spectral path (see spectrum.rs), set by the integrator
 }
 
 
 // Hittable Trait
+// Objects that can be lights also sample points on their surface, seen from `origin`: random() returns the
+// direction from `origin` to such a point, pdf_value() the density of random() over solid angle (0 where the
+// direction misses the object).
 pub trait Hittable: Send + Sync{
   fn hit(&self, ray: &Ray, ray_t: Interval, rec: &mut HitRecord) -> bool;
   
@@ -29,6 +66,26 @@ pub trait Hittable: Send + Sync{
 
   fn bounding_box(&self) -> Aabb;
 
+  fn pdf_value(&self, origin: Point3, direction: Vec3) -> f64 {
+    0.0
+  }
+
+  // `u`: uniform in [0, 1)^2
+  fn random(&self, origin: Point3, u: (f64, f64)) -> Vec3 {
+    Vec3::new(1.0, 0.0, 0.0)
+  }
+
+  // emissive, and able to sample its surface
+  fn is_light(&self) -> bool {
+    false
+  }
+
+  // push the lights in this object (`this` is the Object holding self); containers and instances override it
+  fn collect_lights(&self, this: &Object, lights: &mut Vec<Object>) {
+    if self.is_light() {
+      lights.push(this.clone());
+    }
+  }
 }
 
 // use Arc::new, instead of Object::new btw
@@ -47,6 +104,9 @@ impl HitRecord {
       u,
       v,
       front_surface,
+      ids: HitIds::none(),
+      exterior_index: 1.0,
+      wavelength: None,
     }
   }
 
@@ -62,6 +122,9 @@ impl HitRecord {
       u,
       v,
       front_surface,
+      ids: HitIds::none(),
+      exterior_index: 1.0,
+      wavelength: None,
     }
   }
 
@@ -143,6 +206,11 @@ impl Hittable for HittableList {
   fn bounding_box(&self) -> Aabb {
       self.bbox
   }
+  fn collect_lights(&self, _this: &Object, lights: &mut Vec<Object>) {
+    for object in &self.objects {
+      object.collect_lights(object, lights);
+    }
+  }
 }
 
 
@@ -181,6 +249,18 @@ impl Hittable for Translate {
   fn to_object(self) -> Object {
     Arc::new(self)
   }
+  fn pdf_value(&self, origin: Point3, direction: Vec3) -> f64 {
+    self.object.pdf_value(origin - self.offset, direction)
+  }
+  fn random(&self, origin: Point3, u: (f64, f64)) -> Vec3 {
+    self.object.random(origin - self.offset, u)
+  }
+  // each light inside, moved like the object
+  fn collect_lights(&self, _this: &Object, lights: &mut Vec<Object>) {
+    let mut inner = Vec::new();
+    self.object.collect_lights(&self.object, &mut inner);
+    lights.extend(inner.into_iter().map(|light| Translate::new(light, self.offset).to_object()));
+  }
 }
 
 pub struct RotateY {
@@ -193,8 +273,10 @@ pub struct RotateY {
 impl RotateY {
   pub fn new(object: Object, angle: f64) -> Self {
     let radians = degrees_to_radians(angle);
-    let sin_theta = radians.sin();
-    let cos_theta = radians.cos();
+    Self::new_by_sin_cos(object, radians.sin(), radians.cos())
+  }
+
+  fn new_by_sin_cos(object: Object, sin_theta: f64, cos_theta: f64) -> Self {
     let bbox = object.bounding_box();
 
     let mut rotate_y = Self {
@@ -267,4 +349,19 @@ impl Hittable for RotateY {
   fn bounding_box(&self) -> Aabb {
     self.bbox
   }
+
+  fn pdf_value(&self, origin: Point3, direction: Vec3) -> f64 {
+    self.object.pdf_value(self.rotate_neg(origin), self.rotate_neg(direction))
+  }
+
+  fn random(&self, origin: Point3, u: (f64, f64)) -> Vec3 {
+    self.rotate_pos(self.object.random(self.rotate_neg(origin), u))
+  }
+
+  // each light inside, rotated like the object
+  fn collect_lights(&self, _this: &Object, lights: &mut Vec<Object>) {
+    let mut inner = Vec::new();
+    self.object.collect_lights(&self.object, &mut inner);
+    lights.extend(inner.into_iter().map(|light| RotateY::new_by_sin_cos(light, self.sin_theta, self.cos_theta).to_object()));
+  }
 }
\ No newline at end of file
@@ -0,0 +1,165 @@
+// Dielectric interiors and the stack of media a path is inside of
+// A closed dielectric object (glass, water) fills its inside with a medium: an index of refraction, and an absorption
+// coefficient per unit of distance (Beer-Lambert: light travelling a distance d keeps exp(-absorption d) of itself).
+// Walking a path, each refraction into an object pushes its interior and each refraction out of it removes it, so the
+// index on the far side of a surface is that of the medium the path actually comes from or goes to (glass in water
+// refracts with 1.5 / 1.33, not 1.5).
+// Overlapping objects are resolved by priority: inside a medium, the surfaces of media with lower priority are false
+// hits, crossed without scattering (Schmidt and Budge 2002, "Simple Nested Dielectrics in Ray Traced Images"). A glass
+// of water models the water slightly larger than the inside of the glass, and gives the glass the higher priority.
+// Among equal priorities the innermost (latest entered) medium wins, which is right for objects nested without overlap.
+
+use crate::color::{*};
+use crate::hittable::{*};
+use crate::spectrum::{*};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Interior {
+  pub refraction_index: f64,
+  pub dispersion: Option<Dispersion>, // gives the index in spectral mode, see spectrum.rs
+  pub absorption: ColorType,
+  pub priority: u32,
+}
+
+impl Interior {
+  // at the path's hero wavelength in spectral mode
+  pub fn index_at(&self, wavelength: Option<f64>) -> f64 {
+    match (self.dispersion, wavelength) {
+      (Some(dispersion), Some(lambda)) => dispersion.ior(lambda),
+      _ => self.refraction_index,
+    }
+  }
+}
+
+// the absorption coefficient that leaves `color` of white light after `distance` (> 0)
+pub fn absorption_at_distance(color: ColorType, distance: f64) -> ColorType {
+  let channel = |c: f64| -c.clamp(1e-6, 1.0).ln() / distance;
+  ColorType::new(channel(color.x), channel(color.y), channel(color.z))
+}
+
+// the media a path is inside of, keyed by material ID (every primitive of a closed object shares its material);
+// empty outside everything, where the index is 1 and nothing is absorbed
+#[derive(Clone, Debug)]
+pub struct InteriorStack {
+  entries: Vec<(u32, Interior)>,
+}
+
+impl InteriorStack {
+  pub fn new() -> Self {
+    Self { entries: Vec::new() }
+  }
+
+  // the medium that wins among those not made of `except`
+  fn current_except(&self, except: u32) -> Option<&Interior> {
+    let mut current: Option<&Interior> = None;
+    for (id, interior) in &self.entries {
+      if *id != except && current.map_or(true, |c| interior.priority >= c.priority) {
+        current = Some(interior);
+      }
+    }
+    current
+  }
+
+  // the medium the path is travelling through
+  pub fn current(&self) -> Option<&Interior> {
+    self.current_except(0)
+  }
+
+  // fraction of light kept over `distance` through the current medium, at the path's wavelengths in spectral mode
+  pub fn transmittance(&self, distance: f64, wavelengths: Option<&Wavelengths>) -> ColorType {
+    match self.current() {
+      Some(interior) if interior.absorption != ColorType::zero() => {
+        let absorption = wavelengths.map_or(interior.absorption, |wl| wl.upsample(interior.absorption));
+        let a = absorption * -distance;
+        ColorType::new(a.x.exp(), a.y.exp(), a.z.exp())
+      }
+      _ => ColorType::ones(),
+    }
+  }
+
+  // whether the hit is on a medium overlapped by one of higher priority, to be crossed without scattering
+  pub fn is_false_hit(&self, rec: &HitRecord) -> bool {
+    match (rec.mat.interior(), self.current_except(rec.ids.material)) {
+      (Some(interior), Some(current)) => interior.priority < current.priority,
+      _ => false,
+    }
+  }
+
+  // index of refraction on the other side of the hit surface from its inside, at rec.wavelength
+  pub fn exterior_index(&self, rec: &HitRecord) -> f64 {
+    self.current_except(rec.ids.material).map_or(1.0, |c| c.index_at(rec.wavelength))
+  }
+
+  // whether refraction at the hit surface depends on the wavelength through the medium on its other side
+  pub fn exterior_is_dispersive(&self, rec: &HitRecord) -> bool {
+    rec.mat.interior().is_some() && self.current_except(rec.ids.material).map_or(false, |c| c.dispersion.is_some())
+  }
+
+  // update for a path passing through the hit surface (refracted, or crossing a false hit)
+  pub fn cross(&mut self, rec: &HitRecord) {
+    let interior = match rec.mat.interior() {
+      Some(interior) => interior,
+      None => return,
+    };
+    if rec.front_surface {
+      self.entries.push((rec.ids.material, interior));
+    } else if let Some(k) = self.entries.iter().rposition(|(id, _)| *id == rec.ids.material) {
+      self.entries.remove(k);
+    }
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use crate::*;
+
+  #[test]
+  fn test_interior_stack() {
+    let glass = Dielectric::new(1.5).with_priority(1).to_material();
+    let water = Dielectric::new(1.33).with_absorption(ColorType::new(0.1, 0.2, 0.3)).to_material();
+    let (glass_ids, water_ids) = (HitIds::new(&glass), HitIds::new(&water));
+    let hit = |mat: &Material, ids: HitIds, front_surface: bool| {
+      let mut rec = HitRecord::new(Point3::zero(), Vec3::new(0.0, 0.0, 1.0), mat.clone(), 1.0, front_surface, 0.0, 0.0);
+      rec.ids = ids;
+      rec
+    };
+
+    let mut media = InteriorStack::new();
+    assert_eq!(media.transmittance(5.0, None), ColorType::ones());
+    let into_water = hit(&water, water_ids, true);
+    assert_eq!(media.exterior_index(&into_water), 1.0);
+    media.cross(&into_water);
+    let t = media.transmittance(2.0, None);
+    assert!((t.y - (-0.4f64).exp()).abs() < 1e-12);
+
+    // glass in water refracts against the water
+    let into_glass = hit(&glass, glass_ids, true);
+    assert!(!media.is_false_hit(&into_glass));
+    assert_eq!(media.exterior_index(&into_glass), 1.33);
+    media.cross(&into_glass);
+    assert_eq!(media.transmittance(2.0, None), ColorType::ones()); // clear glass
+    // the water's surface is a false hit inside the glass, which has the higher priority
+    let out_of_water = hit(&water, water_ids, false);
+    assert!(media.is_false_hit(&out_of_water));
+    media.cross(&out_of_water);
+    let out_of_glass = hit(&glass, glass_ids, false);
+    assert_eq!(media.exterior_index(&out_of_glass), 1.0);
+    media.cross(&out_of_glass);
+    assert!(media.current().is_none());
+
+    // glass inside dispersive flint refracts against the flint's index at the path's hero wavelength
+    let sf11 = Dispersion::from_name("sf11").unwrap();
+    let flint = Dielectric::new_dispersive(sf11).to_material();
+    let flint_ids = HitIds::new(&flint);
+    media.cross(&hit(&flint, flint_ids, true));
+    let mut into_glass = hit(&glass, glass_ids, true);
+    assert_eq!(media.exterior_index(&into_glass), sf11.nominal_ior());
+    into_glass.wavelength = Some(450.0);
+    assert_eq!(media.exterior_index(&into_glass), sf11.ior(450.0));
+    assert!(media.exterior_is_dispersive(&into_glass) && !media.exterior_is_dispersive(&hit(&flint, flint_ids, false)));
+
+    let sigma = absorption_at_distance(ColorType::new(0.5, 1.0, 0.25), 2.0);
+    assert!((sigma.x - 2f64.ln() / 2.0).abs() < 1e-12 && sigma.y == 0.0 && (sigma.z - 2f64.ln()).abs() < 1e-12);
+  }
+}
@@ -0,0 +1,91 @@
+// Lights for next-event estimation
+// Every emissive object of the scene that can sample its surface (see Hittable::is_light), behind
+// translations and rotations. A direction is sampled by picking one light uniformly and a point on it,
+// so the density of a direction is the mean of the lights' densities.
+
+use crate::hittable::{*};
+use crate::sampler::{*};
+use crate::vec3::{*};
+
+pub struct LightList {
+  lights: Vec<Object>,
+}
+
+impl LightList {
+  pub fn new(world: &Object) -> Self {
+    let mut lights = Vec::new();
+    world.collect_lights(world, &mut lights);
+    Self { lights }
+  }
+
+  pub fn default() -> Self {
+    Self { lights: Vec::new() }
+  }
+
+  pub fn len(&self) -> usize {
+    self.lights.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.lights.is_empty()
+  }
+
+  // density of random() over solid angle; 0 where the direction misses every light
+  pub fn pdf_value(&self, origin: Point3, direction: Vec3) -> f64 {
+    if self.lights.is_empty() {
+      return 0.0;
+    }
+    self.lights.iter().map(|light| light.pdf_value(origin, direction)).sum::<f64>() / self.lights.len() as f64
+  }
+
+  // direction from `origin` to a point on one of the lights (not normalized); there must be at least one light
+  pub fn random(&self, origin: Point3, sampler: &mut dyn Sampler) -> Vec3 {
+    let n = self.lights.len();
+    let k = ((sampler.get_1d() * n as f64) as usize).min(n - 1);
+    self.lights[k].random(origin, sampler.get_2d())
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use crate::*;
+
+  #[test]
+  fn test_light_sampling() {
+    let light = DiffuseLight::new_by_color(ColorType::ones()).to_material();
+    let origin = Point3::new(0.3, -0.2, 0.1);
+    let shapes = [
+      Quad::new(Point3::new(-1.0, -1.0, 2.0), Vec3::new(2.0, 0.0, 0.0), Vec3::new(0.5, 1.5, 0.5), light.clone()).to_object(),
+      Triangle::new(Point3::new(-1.0, -1.0, 2.0), Vec3::new(2.0, 0.0, 0.0), Vec3::new(0.0, 2.0, 0.5), light.clone()).to_object(),
+      Circle::new(Point3::new(0.0, 0.0, -2.0), Vec3::new(1.5, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.5), light.clone()).to_object(),
+      Ring::new(Point3::new(0.0, 2.0, 0.0), Vec3::new(1.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 1.0), light.clone(), 0.5).to_object(),
+      Sphere::new_static(Point3::new(2.0, 1.0, 1.0), 0.8, light.clone()).to_object(),
+      Sphere::new_static(Point3::zero(), 2.0, light.clone()).to_object(), // around the origin
+      RotateY::new(Translate::new(
+        Quad::new(Point3::zero(), Vec3::new(1.0, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0), light.clone()).to_object(),
+        Vec3::new(0.0, 0.0, 2.0)).to_object(), 30.0).to_object(),
+    ];
+    let mut sampler = SamplerKind::Independent.create(1, 0);
+    seed_rng(1);
+    for shape in &shapes {
+      let mut lights = Vec::new();
+      shape.collect_lights(shape, &mut lights);
+      assert_eq!(lights.len(), 1);
+      for _ in 0..100 { // sampled directions hit the shape
+        let direction = shape.random(origin, sampler.get_2d());
+        assert!(shape.pdf_value(origin, direction) > 0.0);
+      }
+      // over uniform directions, the density integrates to 1
+      let n = 400000;
+      let integral = (0..n).map(|_| shape.pdf_value(origin, sample_unit_sphere(sampler.get_2d()))).sum::<f64>() * 4.0 * PI / n as f64;
+      assert!((integral - 1.0).abs() < 0.03);
+    }
+
+    let mut world = HittableList::new(shapes.to_vec());
+    world.add(Sphere::new_static(Point3::zero(), 10.0, Lambertian::new_by_color(ColorType::ones()).to_material()).to_object());
+    world.add(Sphere::new_moving(Point3::zero(), Point3::new(0.0, 1.0, 0.0), 1.0, light.clone()).to_object());
+    let lights = LightList::new(&world.to_bvh());
+    assert_eq!(lights.len(), shapes.len()); // not the diffuse or the moving sphere
+  }
+}
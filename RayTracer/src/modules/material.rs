@@ -9,16 +9,66 @@ use crate::color::{*};
 use crate::utility::{*};
 use crate::texture::{*};
 use crate::perlin::{*};
+use crate::sampler::{*};
+use crate::onb::{*};
+use crate::microfacet::{*};
+use crate::interior::{*};
+use crate::spectrum::{*};
+use crate::PI;
 
 use std::sync::Arc;
 
+// A direction sampled by MaterialTrait::sample.
+// `f` is the BSDF times |cos| between the direction and the normal (the phase function, for volumes),
+// `pdf` the density the direction was sampled with (solid angle); the path throughput is multiplied by f / pdf.
+// Delta lobes (mirrors, glass) have no density: `f` is then the weight itself and `pdf` is 1.
+pub struct ScatterRecord {
+  pub ray: Ray,
+  pub f: ColorType,
+  pub pdf: f64,
+  pub is_delta: bool,
+}
+
+impl ScatterRecord {
+  pub fn weight(&self) -> ColorType {
+    self.f / self.pdf
+  }
+}
+
+// Scattering: sample() picks the next direction, eval() and pdf() give the BSDF (times |cos|) and the sampling
+// density for any direction, which lights sampled separately need. Directions point away from the surface;
+// delta lobes evaluate to zero everywhere.
+// random decisions in sample() draw from `sampler`, see sampler.rs
 pub trait MaterialTrait {
-  fn scatter(&self, ray_in: &Ray, rec: &HitRecord, attenuation: &mut ColorType, scattered: &mut Ray) -> bool {
-    false
+  // None: the path is absorbed
+  fn sample(&self, ray_in: &Ray, rec: &HitRecord, sampler: &mut dyn Sampler) -> Option<ScatterRecord> {
+    None
+  }
+  fn eval(&self, ray_in: &Ray, rec: &HitRecord, direction: Vec3) -> ColorType {
+    ColorType::zero()
+  }
+  fn pdf(&self, ray_in: &Ray, rec: &HitRecord, direction: Vec3) -> f64 {
+    0.0
   }
   fn emitted(&self, u: f64, v: f64, p: Point3) -> ColorType {
     ColorType::zero()
   }
+  // whether emitted() can be nonzero; objects made of it are sampled as lights
+  fn is_emissive(&self) -> bool {
+    false
+  }
+  // surface colour for the albedo AOV: the reflectance at (u, v, p), white for glass and lights
+  fn albedo(&self, u: f64, v: f64, p: Point3) -> ColorType {
+    ColorType::ones()
+  }
+  // the medium inside closed objects made of it, for refracting materials
+  fn interior(&self) -> Option<Interior> {
+    None
+  }
+  // whether sample() depends on rec.wavelength, so that a spectral path can only go on with its hero wavelength
+  fn is_dispersive(&self) -> bool {
+    false
+  }
   fn to_material(self) -> Material;
 }
 
@@ -37,16 +87,27 @@ impl DefaultMaterial {
 }
 
 impl MaterialTrait for DefaultMaterial {
-  fn scatter(&self, ray_in: &Ray, rec: &HitRecord, attenuation: &mut ColorType, scattered: &mut Ray) -> bool {
-    false
-  }
   fn to_material(self) ->
    Material {
       Arc::new(self)
   }
 }
 
-// Lambertian
+// Lambertian: BSDF albedo / pi, sampled with the cosine (shared with LambertianWithLight)
+// cos / pi is both the density of cosine-weighted sampling and the BSDF times cos over the albedo
+fn lambertian_pdf(rec: &HitRecord, direction: Vec3) -> f64 {
+  (direction.normalize().dot(&rec.normal) / PI).max(0.0)
+}
+
+fn lambertian_sample(albedo: ColorType, ray_in: &Ray, rec: &HitRecord, sampler: &mut dyn Sampler) -> Option<ScatterRecord> {
+  let direction = Onb::new(rec.normal).local(sample_cosine_hemisphere(sampler.get_2d()));
+  let pdf = lambertian_pdf(rec, direction);
+  if pdf <= 0.0 {
+    return None; // grazing, or a normal that does not face the ray
+  }
+  Some(ScatterRecord { ray: Ray::new(rec.p, direction, ray_in.tm), f: albedo * pdf, pdf, is_delta: false })
+}
+
 pub struct Lambertian{
   tex: Texture,
 }
@@ -66,16 +127,17 @@ impl Lambertian {
 }
 
 impl MaterialTrait for Lambertian {
-  fn scatter(&self, ray_in: &Ray, rec: &HitRecord, attenuation: &mut ColorType, scattered: &mut Ray) -> bool {
-    let mut scatter_dircton = rec.normal + Vec3::rand_unit();
-
-    if scatter_dircton.near_zero() { // to handle zero vector error
-      scatter_dircton = rec.normal
-    }
-
-    *scattered = Ray::new(rec.p, scatter_dircton, ray_in.tm);
-    *attenuation = self.tex.value(rec.u, rec.v, rec.p);
-    true
+  fn sample(&self, ray_in: &Ray, rec: &HitRecord, sampler: &mut dyn Sampler) -> Option<ScatterRecord> {
+    lambertian_sample(self.tex.value(rec.u, rec.v, rec.p), ray_in, rec, sampler)
+  }
+  fn eval(&self, ray_in: &Ray, rec: &HitRecord, direction: Vec3) -> ColorType {
+    self.tex.value(rec.u, rec.v, rec.p) * lambertian_pdf(rec, direction)
+  }
+  fn pdf(&self, ray_in: &Ray, rec: &HitRecord, direction: Vec3) -> f64 {
+    lambertian_pdf(rec, direction)
+  }
+  fn albedo(&self, u: f64, v: f64, p: Point3) -> ColorType {
+    self.tex.value(u, v, p)
   }
   fn to_material(self) ->
   Material {
@@ -94,18 +156,53 @@ impl Metal {
   pub fn new(albedo: ColorType, fuzz: f64) -> Self {
     Metal {
       albedo,
-      fuzz: (1.0 as f64).min(fuzz),
+      fuzz: (1.0 as f64).min(fuzz.abs()),
+    }
+  }
+
+  // Fuzzy metal reflects along the mirror direction plus fuzz times a uniform unit vector, a point on the sphere of
+  // radius fuzz around the mirror direction. The density of a direction is that of the points where its ray meets
+  // the sphere, t^2 / (|cos| area) each; it is cos / pi around the mirror direction for fuzz 1.
+  fn fuzz_pdf(&self, ray_in: &Ray, rec: &HitRecord, direction: Vec3) -> f64 {
+    let b = direction.normalize().dot(&Vec3::reflect(ray_in.dir, rec.normal).normalize());
+    let disc = b * b - 1.0 + self.fuzz * self.fuzz;
+    if disc <= 0.0 {
+      return 0.0;
     }
+    let root = disc.sqrt();
+    let t_sq = |t: f64| if t > 0.0 { t * t } else { 0.0 };
+    (t_sq(b - root) + t_sq(b + root)) / (4.0 * PI * self.fuzz * root)
   }
 }
 
 impl MaterialTrait for Metal {
-  fn scatter(&self, ray_in: &Ray, rec: &HitRecord, attenuation: &mut ColorType, scattered: &mut Ray) -> bool {
+  // a mirror without fuzz; fuzzy metal is a glossy lobe that lights can be sampled against
+  fn sample(&self, ray_in: &Ray, rec: &HitRecord, sampler: &mut dyn Sampler) -> Option<ScatterRecord> {
     let mut reflected = Vec3::reflect(ray_in.dir, rec.normal);
-    reflected = reflected.normalize() + (self.fuzz * Vec3::rand_unit());
-    *scattered = Ray::new(rec.p, reflected, ray_in.tm);
-    *attenuation = self.albedo;
-    Vec3::dot(&scattered.dir, &rec.normal) > 0.0
+    if self.fuzz == 0.0 {
+      return Some(ScatterRecord { ray: Ray::new(rec.p, reflected, ray_in.tm), f: self.albedo, pdf: 1.0, is_delta: true });
+    }
+    reflected = reflected.normalize() + (self.fuzz * sample_unit_sphere(sampler.get_2d()));
+    if Vec3::dot(&reflected, &rec.normal) <= 0.0 {
+      return None; // fuzzed into the surface
+    }
+    let pdf = self.fuzz_pdf(ray_in, rec, reflected);
+    if !(pdf > 0.0 && pdf.is_finite()) {
+      return None; // on the rim of the lobe, where the density is infinite
+    }
+    Some(ScatterRecord { ray: Ray::new(rec.p, reflected, ray_in.tm), f: self.albedo * pdf, pdf, is_delta: false })
+  }
+  fn eval(&self, ray_in: &Ray, rec: &HitRecord, direction: Vec3) -> ColorType {
+    if self.fuzz == 0.0 || direction.dot(&rec.normal) <= 0.0 {
+      return ColorType::zero();
+    }
+    self.albedo * self.fuzz_pdf(ray_in, rec, direction)
+  }
+  fn pdf(&self, ray_in: &Ray, rec: &HitRecord, direction: Vec3) -> f64 {
+    if self.fuzz == 0.0 { 0.0 } else { self.fuzz_pdf(ray_in, rec, direction) }
+  }
+  fn albedo(&self, u: f64, v: f64, p: Point3) -> ColorType {
+    self.albedo
   }
   fn to_material(self) ->
   Material {
@@ -115,17 +212,166 @@ impl MaterialTrait for Metal {
 
 
 
+// Conductor: a rough metal made of mirror microfacets (see microfacet.rs), with the Fresnel reflectance of its
+// complex index of refraction eta + i k. Roughness comes from the first channel of its textures, along the
+// surface tangent closest to the world y axis (roughness_u) and across it (roughness_v), so anisotropic
+// metal looks brushed around y. Light scattered more than once between microfacets is lost, which darkens
+// very rough metal slightly.
+pub struct Conductor {
+  distribution: MicrofacetKind,
+  eta: ColorType,
+  k: ColorType,
+  roughness_u: Texture,
+  roughness_v: Texture,
+}
+
+pub const CONDUCTOR_NAMES: [&str; 4] = ["gold", "copper", "silver", "aluminium"];
+
+// (eta, k) of a metal, at the red, green and blue wavelengths (650, 550, 450 nm)
+pub fn conductor_ior(name: &str) -> Option<(ColorType, ColorType)> {
+  match name {
+    "gold" => Some((ColorType::new(0.143119, 0.374957, 1.44248), ColorType::new(3.98316, 2.38572, 1.60322))),
+    "copper" => Some((ColorType::new(0.200438, 0.924033, 1.10221), ColorType::new(3.91295, 2.45285, 2.14219))),
+    "silver" => Some((ColorType::new(0.155265, 0.116723, 0.138342), ColorType::new(4.82835, 3.12225, 2.14696))),
+    "aluminium" => Some((ColorType::new(1.65746, 0.880369, 0.521229), ColorType::new(9.22387, 6.26952, 4.837))),
+    _ => None,
+  }
+}
+
+impl Conductor {
+  pub fn new(distribution: MicrofacetKind, eta: ColorType, k: ColorType, roughness_u: Texture, roughness_v: Texture) -> Self {
+    Self {
+      distribution,
+      eta,
+      k,
+      roughness_u,
+      roughness_v,
+    }
+  }
+
+  // isotropic GGX
+  pub fn new_by_roughness(eta: ColorType, k: ColorType, roughness: f64) -> Self {
+    let roughness = SolidColor::new(ColorType::ones() * roughness).to_texture();
+    Self::new(MicrofacetKind::Ggx, eta, k, roughness.clone(), roughness)
+  }
+
+  // one of CONDUCTOR_NAMES
+  pub fn new_preset(name: &str, roughness: f64) -> Option<Self> {
+    conductor_ior(name).map(|(eta, k)| Self::new_by_roughness(eta, k, roughness))
+  }
+
+  fn frame(rec: &HitRecord) -> Onb {
+    Onb::new_with_tangent(rec.normal, Vec3::new(0.0, 1.0, 0.0).cross(&rec.normal))
+  }
+
+  fn microfacet(&self, rec: &HitRecord) -> Microfacet {
+    Microfacet::new(self.distribution, self.roughness_u.value(rec.u, rec.v, rec.p).x, self.roughness_v.value(rec.u, rec.v, rec.p).x)
+  }
+
+  // BSDF times cos(theta_i), in the local frame
+  fn eval_local(&self, mf: &Microfacet, wo: Vec3, wi: Vec3) -> ColorType {
+    if wo.z <= 0.0 || wi.z <= 0.0 {
+      return ColorType::zero();
+    }
+    let wh = (wo + wi).normalize();
+    fresnel_conductor(wi.dot(&wh), self.eta, self.k) * (mf.d(wh) * mf.g(wo, wi) / (4.0 * wo.z))
+  }
+
+  fn pdf_local(&self, mf: &Microfacet, wo: Vec3, wi: Vec3) -> f64 {
+    if wo.z <= 0.0 || wi.z <= 0.0 {
+      return 0.0;
+    }
+    let wh = (wo + wi).normalize();
+    mf.pdf_wh(wo, wh) / (4.0 * wo.dot(&wh))
+  }
+}
+
+impl MaterialTrait for Conductor {
+  fn sample(&self, ray_in: &Ray, rec: &HitRecord, sampler: &mut dyn Sampler) -> Option<ScatterRecord> {
+    let frame = Self::frame(rec);
+    let wo = frame.to_local(-ray_in.dir.normalize());
+    if wo.z <= 0.0 {
+      return None;
+    }
+    let mf = self.microfacet(rec);
+    if mf.is_smooth() {
+      let f = fresnel_conductor(wo.z, self.eta, self.k);
+      return Some(ScatterRecord { ray: Ray::new(rec.p, frame.local(Vec3::new(-wo.x, -wo.y, wo.z)), ray_in.tm), f, pdf: 1.0, is_delta: true });
+    }
+    let wh = mf.sample_wh(wo, sampler.get_2d());
+    let wi = 2.0 * wo.dot(&wh) * wh - wo;
+    let pdf = self.pdf_local(&mf, wo, wi);
+    if pdf <= 0.0 {
+      return None; // reflected into the surface
+    }
+    Some(ScatterRecord { ray: Ray::new(rec.p, frame.local(wi), ray_in.tm), f: self.eval_local(&mf, wo, wi), pdf, is_delta: false })
+  }
+  fn eval(&self, ray_in: &Ray, rec: &HitRecord, direction: Vec3) -> ColorType {
+    let mf = self.microfacet(rec);
+    if mf.is_smooth() {
+      return ColorType::zero();
+    }
+    let frame = Self::frame(rec);
+    self.eval_local(&mf, frame.to_local(-ray_in.dir.normalize()), frame.to_local(direction.normalize()))
+  }
+  fn pdf(&self, ray_in: &Ray, rec: &HitRecord, direction: Vec3) -> f64 {
+    let mf = self.microfacet(rec);
+    if mf.is_smooth() {
+      return 0.0;
+    }
+    let frame = Self::frame(rec);
+    self.pdf_local(&mf, frame.to_local(-ray_in.dir.normalize()), frame.to_local(direction.normalize()))
+  }
+  // reflectance at normal incidence
+  fn albedo(&self, u: f64, v: f64, p: Point3) -> ColorType {
+    fresnel_conductor(1.0, self.eta, self.k)
+  }
+  fn to_material(self) -> Material {
+    Arc::new(self)
+  }
+}
+
+
+
 // Dielectric
+// Clear unless given an absorption, which tints light by the distance it travels inside (see interior.rs).
+// With a dispersion model, the index depends on the wavelength when rendering spectrally (see spectrum.rs).
 pub struct Dielectric {
   refraction_index: f64,
+  dispersion: Option<Dispersion>,
+  absorption: ColorType,
+  priority: u32,
 }
 
 impl Dielectric {
   pub fn new(refraction_index: f64) -> Self {
     Dielectric {
       refraction_index,
+      dispersion: None,
+      absorption: ColorType::zero(),
+      priority: 0,
     }
   }
+  // the glass's nominal index when rendering in RGB
+  pub fn new_dispersive(dispersion: Dispersion) -> Self {
+    Dielectric {
+      dispersion: Some(dispersion),
+      ..Self::new(dispersion.nominal_ior())
+    }
+  }
+  // tinted glass that leaves `color` of white light after `distance` inside it
+  pub fn new_tinted(refraction_index: f64, color: ColorType, distance: f64) -> Self {
+    Self::new(refraction_index).with_absorption(absorption_at_distance(color, distance))
+  }
+  pub fn with_absorption(mut self, absorption: ColorType) -> Self {
+    self.absorption = absorption;
+    self
+  }
+  // wins over media of lower priority where objects overlap
+  pub fn with_priority(mut self, priority: u32) -> Self {
+    self.priority = priority;
+    self
+  }
   pub fn reflectance(cosine: f64, refraction_index: f64) -> f64 {
     let r0 = (1.0 - refraction_index) / (1.0 + refraction_index);
     let r0 = r0*r0;
@@ -134,9 +380,12 @@ impl Dielectric {
 }
 
 impl MaterialTrait for Dielectric {
-  fn scatter(&self, ray_in: &Ray, rec: &HitRecord, attenuation: &mut ColorType, scattered: &mut Ray) -> bool {
-    *attenuation = ColorType::ones();
-    let ratio = if rec.front_surface { 1.0 / self.refraction_index } else { self.refraction_index };
+  fn sample(&self, ray_in: &Ray, rec: &HitRecord, sampler: &mut dyn Sampler) -> Option<ScatterRecord> {
+    let refraction_index = match (self.dispersion, rec.wavelength) {
+      (Some(dispersion), Some(lambda)) => dispersion.ior(lambda),
+      _ => self.refraction_index,
+    } / rec.exterior_index; // relative to the medium around
+    let ratio = if rec.front_surface { 1.0 / refraction_index } else { refraction_index };
     
     let unit_direction =  ray_in.dir.normalize();
     let cos_theta = -unit_direction.dot(&rec.normal).min(1.0);
@@ -145,14 +394,19 @@ impl MaterialTrait for Dielectric {
     let cannot_refract = ratio * sin_theta > 1.0;
     
     let scattered_direction = 
-      if cannot_refract || Self::reflectance(cos_theta, self.refraction_index) > rand_01() {
+      if cannot_refract || Self::reflectance(cos_theta, refraction_index) > sampler.get_1d() {
         Vec3::reflect(ray_in.dir.normalize(), rec.normal)
       } else {
         Vec3::refract(ray_in.dir.normalize(), rec.normal, ratio)
       };
 
-    *scattered = Ray::new(rec.p, scattered_direction, ray_in.tm);
-    true
+    Some(ScatterRecord { ray: Ray::new(rec.p, scattered_direction, ray_in.tm), f: ColorType::ones(), pdf: 1.0, is_delta: true })
+  }
+  fn interior(&self) -> Option<Interior> {
+    Some(Interior { refraction_index: self.refraction_index, dispersion: self.dispersion, absorption: self.absorption, priority: self.priority })
+  }
+  fn is_dispersive(&self) -> bool {
+    self.dispersion.is_some()
   }
   fn to_material(self) ->
   Material {
@@ -160,6 +414,145 @@ impl MaterialTrait for Dielectric {
   }
 }
 
+// RoughDielectric: frosted glass, a microfacet surface (see microfacet.rs) that reflects or refracts light at each
+// microfacet with the exact Fresnel reflectance. Roughness comes from the first channel of a texture. Like
+// Dielectric, it leaves radiance unscaled when crossing the surface, and may absorb light inside.
+pub struct RoughDielectric {
+  distribution: MicrofacetKind,
+  refraction_index: f64,
+  roughness: Texture,
+  absorption: ColorType,
+  priority: u32,
+}
+
+impl RoughDielectric {
+  pub fn new(distribution: MicrofacetKind, refraction_index: f64, roughness: Texture) -> Self {
+    Self {
+      distribution,
+      refraction_index,
+      roughness,
+      absorption: ColorType::zero(),
+      priority: 0,
+    }
+  }
+  pub fn with_absorption(mut self, absorption: ColorType) -> Self {
+    self.absorption = absorption;
+    self
+  }
+  pub fn with_priority(mut self, priority: u32) -> Self {
+    self.priority = priority;
+    self
+  }
+
+  // GGX
+  pub fn new_by_roughness(refraction_index: f64, roughness: f64) -> Self {
+    Self::new(MicrofacetKind::Ggx, refraction_index, SolidColor::new(ColorType::ones() * roughness).to_texture())
+  }
+
+  // index of refraction beyond the surface over that on the side of the ray
+  fn eta(&self, rec: &HitRecord) -> f64 {
+    if rec.front_surface { self.refraction_index / rec.exterior_index } else { rec.exterior_index / self.refraction_index }
+  }
+
+  fn microfacet(&self, rec: &HitRecord) -> Microfacet {
+    let roughness = self.roughness.value(rec.u, rec.v, rec.p).x;
+    Microfacet::new(self.distribution, roughness, roughness)
+  }
+
+  // The microfacet normal that turns wo into wi (generalized half vector), facing wo, if wi is a
+  // reflection or refraction of wo about it.
+  fn half_vector(wo: Vec3, wi: Vec3, eta: f64) -> Option<Vec3> {
+    let reflect = wi.z > 0.0;
+    let wh = if reflect { wo + wi } else { wo + eta * wi };
+    if wh.near_zero() {
+      return None;
+    }
+    let wh = if wh.z < 0.0 { -wh.normalize() } else { wh.normalize() };
+    if wo.dot(&wh) <= 0.0 || (wi.dot(&wh) > 0.0) != reflect {
+      return None; // on the back of that microfacet
+    }
+    Some(wh)
+  }
+
+  // BSDF times |cos(theta_i)| and the sampling density, in the local frame (wo.z > 0)
+  fn eval_pdf_local(&self, mf: &Microfacet, eta: f64, wo: Vec3, wi: Vec3) -> (f64, f64) {
+    let wh = match Self::half_vector(wo, wi, eta) {
+      Some(wh) => wh,
+      None => return (0.0, 0.0),
+    };
+    let fresnel = fresnel_dielectric(wo.dot(&wh), eta);
+    let (d, g, pdf_wh) = (mf.d(wh), mf.g(wo, wi), mf.pdf_wh(wo, wh));
+    if wi.z > 0.0 {
+      (fresnel * d * g / (4.0 * wo.z), fresnel * pdf_wh / (4.0 * wo.dot(&wh)))
+    } else {
+      // dwh / dwi of the refraction
+      let denom = wo.dot(&wh) + eta * wi.dot(&wh);
+      let jacobian = eta * eta * wi.dot(&wh).abs() / (denom * denom);
+      ((1.0 - fresnel) * d * g * wo.dot(&wh) * jacobian / wo.z, (1.0 - fresnel) * pdf_wh * jacobian)
+    }
+  }
+}
+
+impl MaterialTrait for RoughDielectric {
+  fn sample(&self, ray_in: &Ray, rec: &HitRecord, sampler: &mut dyn Sampler) -> Option<ScatterRecord> {
+    let frame = Onb::new(rec.normal);
+    let wo = frame.to_local(-ray_in.dir.normalize());
+    if wo.z <= 0.0 {
+      return None;
+    }
+    let (mf, eta) = (self.microfacet(rec), self.eta(rec));
+    let u = sampler.get_1d();
+    if mf.is_smooth() {
+      let n = Vec3::new(0.0, 0.0, 1.0);
+      let wi = match refract_direction(wo, n, eta) {
+        Some(wt) if u >= fresnel_dielectric(wo.z, eta) => wt,
+        _ => Vec3::new(-wo.x, -wo.y, wo.z),
+      };
+      return Some(ScatterRecord { ray: Ray::new(rec.p, frame.local(wi), ray_in.tm), f: ColorType::ones(), pdf: 1.0, is_delta: true });
+    }
+    let wh = mf.sample_wh(wo, sampler.get_2d());
+    let (wi, reflected) = match refract_direction(wo, wh, eta) {
+      Some(wt) if u >= fresnel_dielectric(wo.dot(&wh), eta) => (wt, false),
+      _ => (2.0 * wo.dot(&wh) * wh - wo, true),
+    };
+    let (f, pdf) = self.eval_pdf_local(&mf, eta, wo, wi);
+    if (wi.z > 0.0) != reflected || pdf <= 0.0 {
+      return None; // the microfacet sent it to the wrong side of the surface
+    }
+    Some(ScatterRecord { ray: Ray::new(rec.p, frame.local(wi), ray_in.tm), f: ColorType::ones() * f, pdf, is_delta: false })
+  }
+  fn eval(&self, ray_in: &Ray, rec: &HitRecord, direction: Vec3) -> ColorType {
+    let mf = self.microfacet(rec);
+    if mf.is_smooth() {
+      return ColorType::zero();
+    }
+    let frame = Onb::new(rec.normal);
+    let wo = frame.to_local(-ray_in.dir.normalize());
+    if wo.z <= 0.0 {
+      return ColorType::zero();
+    }
+    ColorType::ones() * self.eval_pdf_local(&mf, self.eta(rec), wo, frame.to_local(direction.normalize())).0
+  }
+  fn pdf(&self, ray_in: &Ray, rec: &HitRecord, direction: Vec3) -> f64 {
+    let mf = self.microfacet(rec);
+    if mf.is_smooth() {
+      return 0.0;
+    }
+    let frame = Onb::new(rec.normal);
+    let wo = frame.to_local(-ray_in.dir.normalize());
+    if wo.z <= 0.0 {
+      return 0.0;
+    }
+    self.eval_pdf_local(&mf, self.eta(rec), wo, frame.to_local(direction.normalize())).1
+  }
+  fn interior(&self) -> Option<Interior> {
+    Some(Interior { refraction_index: self.refraction_index, dispersion: None, absorption: self.absorption, priority: self.priority })
+  }
+  fn to_material(self) -> Material {
+    Arc::new(self)
+  }
+}
+
 pub struct DiffuseLight  {
   tex: Texture,
 }
@@ -179,6 +572,9 @@ impl MaterialTrait for DiffuseLight {
   fn emitted(&self, u: f64, v: f64, p: Point3) -> ColorType {
       self.tex.value(u, v, p)
   }
+  fn is_emissive(&self) -> bool {
+    true
+  }
   fn to_material(self) -> Material {
       Arc::new(self)
   }
@@ -201,10 +597,20 @@ impl Isotropic {
 }
 
 impl MaterialTrait for Isotropic {
-  fn scatter(&self, ray_in: &Ray, rec: &HitRecord, attenuation: &mut ColorType, scattered: &mut Ray) -> bool {
-    *scattered = Ray::new(rec.p, Vec3::rand_unit(), ray_in.tm);
-    *attenuation = self.tex.value(rec.u, rec.v, rec.p);
-    true
+  // uniform phase function 1 / (4 pi), sampled exactly
+  fn sample(&self, ray_in: &Ray, rec: &HitRecord, sampler: &mut dyn Sampler) -> Option<ScatterRecord> {
+    let pdf = 1.0 / (4.0 * PI);
+    let f = self.tex.value(rec.u, rec.v, rec.p) * pdf;
+    Some(ScatterRecord { ray: Ray::new(rec.p, sample_unit_sphere(sampler.get_2d()), ray_in.tm), f, pdf, is_delta: false })
+  }
+  fn eval(&self, ray_in: &Ray, rec: &HitRecord, direction: Vec3) -> ColorType {
+    self.tex.value(rec.u, rec.v, rec.p) / (4.0 * PI)
+  }
+  fn pdf(&self, ray_in: &Ray, rec: &HitRecord, direction: Vec3) -> f64 {
+    1.0 / (4.0 * PI)
+  }
+  fn albedo(&self, u: f64, v: f64, p: Point3) -> ColorType {
+    self.tex.value(u, v, p)
   }
   fn to_material(self) -> Material {
       Arc::new(self)
@@ -234,23 +640,165 @@ impl LambertianWithLight {
 }
 
 impl MaterialTrait for LambertianWithLight {
-  fn scatter(&self, ray_in: &Ray, rec: &HitRecord, attenuation: &mut ColorType, scattered: &mut Ray) -> bool {
-    let mut scatter_dircton = rec.normal + Vec3::rand_unit();
-
-    if scatter_dircton.near_zero() { // to handle zero vector error
-      scatter_dircton = rec.normal
-    }
-
-    *scattered = Ray::new(rec.p, scatter_dircton, ray_in.tm);
-    *attenuation = self.tex.value(rec.u, rec.v, rec.p);
-    true
+  fn sample(&self, ray_in: &Ray, rec: &HitRecord, sampler: &mut dyn Sampler) -> Option<ScatterRecord> {
+    lambertian_sample(self.tex.value(rec.u, rec.v, rec.p), ray_in, rec, sampler)
+  }
+  fn eval(&self, ray_in: &Ray, rec: &HitRecord, direction: Vec3) -> ColorType {
+    self.tex.value(rec.u, rec.v, rec.p) * lambertian_pdf(rec, direction)
+  }
+  fn pdf(&self, ray_in: &Ray, rec: &HitRecord, direction: Vec3) -> f64 {
+    lambertian_pdf(rec, direction)
   }
   fn emitted(&self, u: f64, v: f64, p: Point3) -> ColorType {
       self.light.elemul(&self.tex.value(u, v, p))
   }
+  fn is_emissive(&self) -> bool {
+    self.light != ColorType::zero()
+  }
+  fn albedo(&self, u: f64, v: f64, p: Point3) -> ColorType {
+    self.tex.value(u, v, p)
+  }
 
   fn to_material(self) ->
   Material {
      Arc::new(self)
   }
 }
+
+
+#[cfg(test)]
+mod tests {
+  use crate::*;
+
+  #[test]
+  fn test_bsdf_sampling() {
+    let albedo = ColorType::new(0.2, 0.5, 0.8);
+    let ray_in = Ray::new(Point3::new(1.0, 0.0, 1.0), Vec3::new(-1.0, 0.0, -1.0), 0.0);
+    let rec = HitRecord::new(Point3::zero(), Vec3::new(0.0, 0.0, 1.0), DefaultMaterial::new().to_material(), 1.0, true, 0.0, 0.0);
+    let mut sampler = SamplerKind::Independent.create(1, 0);
+    seed_rng(1);
+
+    for mat in [Lambertian::new_by_color(albedo).to_material(), Isotropic::new_by_color(albedo).to_material()] {
+      for _ in 0..100 { // sample() agrees with eval() and pdf()
+        let scattered = mat.sample(&ray_in, &rec, sampler.as_mut()).unwrap();
+        assert!(!scattered.is_delta);
+        assert!((scattered.pdf - mat.pdf(&ray_in, &rec, scattered.ray.dir)).abs() < 1e-9);
+        assert!((scattered.f - mat.eval(&ray_in, &rec, scattered.ray.dir)).norm() < 1e-9);
+      }
+      // over uniform directions: the pdf integrates to 1, eval to the albedo
+      let n = 200000;
+      let (mut pdf_integral, mut f_integral) = (0.0, ColorType::zero());
+      for _ in 0..n {
+        let direction = sample_unit_sphere(sampler.get_2d());
+        pdf_integral += mat.pdf(&ray_in, &rec, direction) * 4.0 * PI / n as f64;
+        f_integral += mat.eval(&ray_in, &rec, direction) * 4.0 * PI / n as f64;
+      }
+      assert!((pdf_integral - 1.0).abs() < 0.01);
+      assert!((f_integral - albedo).norm() < 0.01);
+    }
+
+    // fuzzy metal: directions within asin(fuzz) of the mirror direction, here all above the surface
+    let metal = Metal::new(albedo, 0.3).to_material();
+    for _ in 0..100 {
+      let scattered = metal.sample(&ray_in, &rec, sampler.as_mut()).unwrap();
+      assert!(!scattered.is_delta);
+      assert!((scattered.pdf - metal.pdf(&ray_in, &rec, scattered.ray.dir)).abs() < 1e-9);
+      assert!((scattered.f - metal.eval(&ray_in, &rec, scattered.ray.dir)).norm() < 1e-9);
+      assert!((scattered.weight() - albedo).norm() < 1e-9);
+    }
+    // the pdf integrates to 1: over the cosine b to the mirror direction, with b = b0 + u^2 for the rim at b0
+    let (mirror, across) = (Vec3::new(-1.0, 0.0, 1.0).normalize(), Vec3::new(1.0, 0.0, 1.0).normalize());
+    let b0 = (1.0 - 0.3f64 * 0.3).sqrt();
+    let (n, u_max) = (100000, (1.0 - b0).sqrt());
+    let pdf_integral = (0..n).map(|k| {
+      let u = (k as f64 + 0.5) / n as f64 * u_max;
+      let b = b0 + u * u;
+      let direction = mirror * b + across * (1.0 - b * b).max(0.0).sqrt();
+      metal.pdf(&ray_in, &rec, direction) * 2.0 * PI * 2.0 * u * u_max / n as f64
+    }).sum::<f64>();
+    assert!((pdf_integral - 1.0).abs() < 1e-3, "{}", pdf_integral);
+    assert_eq!(metal.pdf(&ray_in, &rec, mirror * 0.9 - across * 0.5), 0.0); // outside the lobe
+
+    for mat in [Metal::new(albedo, 0.0).to_material(), Dielectric::new(1.5).to_material()] {
+      let scattered = mat.sample(&ray_in, &rec, sampler.as_mut()).unwrap();
+      assert!(scattered.is_delta);
+      assert_eq!(mat.eval(&ray_in, &rec, scattered.ray.dir), ColorType::zero());
+    }
+    assert!(DiffuseLight::new_by_color(albedo).to_material().sample(&ray_in, &rec, sampler.as_mut()).is_none());
+  }
+
+  #[test]
+  fn test_conductor() {
+    let ray_in = Ray::new(Point3::new(1.0, 0.5, 1.0), Vec3::new(-1.0, -0.5, -1.0), 0.0);
+    let rec = HitRecord::new(Point3::zero(), Vec3::new(0.0, 0.0, 1.0), DefaultMaterial::new().to_material(), 1.0, true, 0.0, 0.0);
+    let mut sampler = SamplerKind::Independent.create(1, 0);
+    seed_rng(1);
+    let (eta, k) = conductor_ior("gold").unwrap();
+    let roughness = |r: f64| SolidColor::new(ColorType::ones() * r).to_texture();
+
+    for kind in [MicrofacetKind::Ggx, MicrofacetKind::Beckmann] {
+      let mat = Conductor::new(kind, eta, k, roughness(0.6), roughness(0.3)).to_material();
+      let n = 200000;
+      let mut sampled = ColorType::zero();
+      for i in 0..n {
+        if let Some(scattered) = mat.sample(&ray_in, &rec, sampler.as_mut()) {
+          assert!(!scattered.is_delta);
+          if i < 100 { // sample() agrees with eval() and pdf()
+            assert!((scattered.pdf - mat.pdf(&ray_in, &rec, scattered.ray.dir)).abs() < 1e-9 * scattered.pdf);
+            assert!((scattered.f - mat.eval(&ray_in, &rec, scattered.ray.dir)).norm() < 1e-9 * scattered.f.norm());
+          }
+          sampled += scattered.weight() / n as f64;
+        }
+      }
+      // the reflected energy, estimated by sampling and over uniform directions
+      let mut integral = ColorType::zero();
+      for _ in 0..n {
+        integral += mat.eval(&ray_in, &rec, sample_unit_sphere(sampler.get_2d())) * 4.0 * PI / n as f64;
+      }
+      assert!((sampled - integral).norm() < 0.03, "{:?} {:?} {:?}", kind, sampled, integral);
+      assert!(sampled.x < 1.0 && sampled.x > 0.8); // gold is red
+      assert!(sampled.z < sampled.x);
+    }
+
+    let mirror = Conductor::new_preset("silver", 0.0).unwrap().to_material();
+    let scattered = mirror.sample(&ray_in, &rec, sampler.as_mut()).unwrap();
+    assert!(scattered.is_delta);
+    assert!((scattered.ray.dir - Vec3::new(-1.0, -0.5, 1.0).normalize()).norm() < 1e-9);
+    assert!(Conductor::new_preset("brass", 0.1).is_none());
+  }
+
+  #[test]
+  fn test_rough_dielectric() {
+    let ray_in = Ray::new(Point3::new(1.0, 0.5, 1.0), Vec3::new(-1.0, -0.5, -1.0), 0.0);
+    let mut sampler = SamplerKind::Independent.create(1, 0);
+    seed_rng(1);
+    let roughness = SolidColor::new(ColorType::ones() * 0.4).to_texture();
+
+    for front_surface in [true, false] { // into the glass, and out of it (with total internal reflection)
+      let rec = HitRecord::new(Point3::zero(), Vec3::new(0.0, 0.0, 1.0), DefaultMaterial::new().to_material(), 1.0, front_surface, 0.0, 0.0);
+      for kind in [MicrofacetKind::Ggx, MicrofacetKind::Beckmann] {
+        let mat = RoughDielectric::new(kind, 1.5, roughness.clone()).to_material();
+        let n = 200000;
+        let (mut sampled, mut transmitted) = (0.0, 0);
+        for i in 0..n {
+          if let Some(scattered) = mat.sample(&ray_in, &rec, sampler.as_mut()) {
+            if i < 100 { // sample() agrees with eval() and pdf()
+              assert!((scattered.pdf - mat.pdf(&ray_in, &rec, scattered.ray.dir)).abs() < 1e-9 * scattered.pdf);
+              assert!((scattered.f - mat.eval(&ray_in, &rec, scattered.ray.dir)).norm() < 1e-9 * scattered.f.norm());
+            }
+            sampled += scattered.weight().x / n as f64;
+            transmitted += (scattered.ray.dir.z < 0.0) as usize;
+          }
+        }
+        let integral = (0..n).map(|_| mat.eval(&ray_in, &rec, sample_unit_sphere(sampler.get_2d())).x).sum::<f64>() * 4.0 * PI / n as f64;
+        assert!((sampled - integral).abs() < 0.03, "{} {:?} {} {}", front_surface, kind, sampled, integral);
+        assert!(sampled < 1.01 && sampled > 0.85, "{} {:?} {}", front_surface, kind, sampled); // some light is lost between microfacets
+        assert!(transmitted > 0 && (transmitted > n / 2) == front_surface); // mostly reflected beyond the critical angle
+      }
+    }
+
+    let rec = HitRecord::new(Point3::zero(), Vec3::new(0.0, 0.0, 1.0), DefaultMaterial::new().to_material(), 1.0, true, 0.0, 0.0);
+    let smooth = RoughDielectric::new_by_roughness(1.5, 0.0).to_material();
+    assert!(smooth.sample(&ray_in, &rec, sampler.as_mut()).unwrap().is_delta);
+  }
+}
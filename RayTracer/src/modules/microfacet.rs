@@ -0,0 +1,214 @@
+// Microfacet distributions and Fresnel terms for rough surfaces
+// Directions are in the local frame of the surface (z along the normal), pointing away from it.
+// The surface is a field of tiny mirrors whose normals wh follow D(wh); the Smith term G accounts for
+// microfacets hidden from (masking) or shadowed towards (shadowing) the two directions, height-correlated.
+// Roughness r maps to the distribution width alpha = r^2, which looks roughly linear in r.
+// (Walter et al. 2007, "Microfacet Models for Refraction through Rough Surfaces"; Heitz 2014, "Understanding
+// the Masking-Shadowing Function in Microfacet-Based BRDFs")
+
+use crate::color::{*};
+use crate::vec3::{*};
+use crate::PI;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MicrofacetKind {
+  Ggx, // Trowbridge-Reitz: long tails, the usual choice
+  Beckmann, // Gaussian slopes: sharper falloff around the highlight
+}
+
+pub const MICROFACET_NAMES: [&str; 2] = ["ggx", "beckmann"];
+
+impl MicrofacetKind {
+  pub fn from_name(name: &str) -> Option<Self> {
+    match name {
+      "ggx" => Some(Self::Ggx),
+      "beckmann" => Some(Self::Beckmann),
+      _ => None,
+    }
+  }
+}
+
+// below this alpha, a surface is sampled as a perfect mirror (its lobe is too narrow to evaluate)
+const SMOOTH_ALPHA: f64 = 1e-3;
+
+// A distribution with widths alpha_x, alpha_y along the local x and y axes (equal: isotropic).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Microfacet {
+  pub kind: MicrofacetKind,
+  pub alpha_x: f64,
+  pub alpha_y: f64,
+}
+
+impl Microfacet {
+  pub fn new(kind: MicrofacetKind, roughness_x: f64, roughness_y: f64) -> Self {
+    Self {
+      kind,
+      alpha_x: roughness_x.clamp(0.0, 1.0).powi(2),
+      alpha_y: roughness_y.clamp(0.0, 1.0).powi(2),
+    }
+  }
+
+  pub fn is_smooth(&self) -> bool {
+    self.alpha_x.max(self.alpha_y) < SMOOTH_ALPHA
+  }
+
+  // density of microfacet normals, per unit projected area of the surface
+  pub fn d(&self, wh: Vec3) -> f64 {
+    if wh.z <= 0.0 {
+      return 0.0;
+    }
+    let (ax, ay) = (self.alpha_x.max(SMOOTH_ALPHA), self.alpha_y.max(SMOOTH_ALPHA));
+    let slope = (wh.x / ax).powi(2) + (wh.y / ay).powi(2); // tan^2 of the angle, stretched by alpha
+    match self.kind {
+      MicrofacetKind::Ggx => 1.0 / (PI * ax * ay * (slope + wh.z * wh.z).powi(2)),
+      MicrofacetKind::Beckmann => (-slope / (wh.z * wh.z)).exp() / (PI * ax * ay * wh.z.powi(4)),
+    }
+  }
+
+  // Smith's auxiliary function: G1(w) = 1 / (1 + lambda(w))
+  fn lambda(&self, w: Vec3) -> f64 {
+    let (ax, ay) = (self.alpha_x.max(SMOOTH_ALPHA), self.alpha_y.max(SMOOTH_ALPHA));
+    let alpha_tan_sq = ((ax * w.x).powi(2) + (ay * w.y).powi(2)) / (w.z * w.z);
+    if !alpha_tan_sq.is_finite() {
+      return f64::INFINITY; // grazing
+    }
+    match self.kind {
+      MicrofacetKind::Ggx => ((1.0 + alpha_tan_sq).sqrt() - 1.0) / 2.0,
+      MicrofacetKind::Beckmann => {
+        let a = 1.0 / alpha_tan_sq.sqrt(); // rational approximation of the exact erf form
+        if a >= 1.6 { 0.0 } else { (1.0 - 1.259 * a + 0.396 * a * a) / (3.535 * a + 2.181 * a * a) }
+      }
+    }
+  }
+
+  pub fn g1(&self, w: Vec3) -> f64 {
+    1.0 / (1.0 + self.lambda(w))
+  }
+
+  pub fn g(&self, wo: Vec3, wi: Vec3) -> f64 {
+    1.0 / (1.0 + self.lambda(wo) + self.lambda(wi))
+  }
+
+  // A microfacet normal seen from wo (wo.z > 0). GGX samples only the normals visible from wo
+  // (Heitz 2018, "Sampling the GGX Distribution of Visible Normals"), Beckmann D(wh) cos(theta_h).
+  pub fn sample_wh(&self, wo: Vec3, u: (f64, f64)) -> Vec3 {
+    let (ax, ay) = (self.alpha_x.max(SMOOTH_ALPHA), self.alpha_y.max(SMOOTH_ALPHA));
+    match self.kind {
+      MicrofacetKind::Ggx => {
+        let vh = Vec3::new(ax * wo.x, ay * wo.y, wo.z).normalize(); // to the hemisphere configuration
+        let len_sq = vh.x * vh.x + vh.y * vh.y;
+        let t1 = if len_sq > 0.0 { Vec3::new(-vh.y, vh.x, 0.0) / len_sq.sqrt() } else { Vec3::new(1.0, 0.0, 0.0) };
+        let t2 = vh.cross(&t1);
+        let r = u.0.sqrt();
+        let phi = 2.0 * PI * u.1;
+        let p1 = r * phi.cos();
+        let s = 0.5 * (1.0 + vh.z);
+        let p2 = (1.0 - s) * (1.0 - p1 * p1).sqrt() + s * r * phi.sin();
+        let nh = p1 * t1 + p2 * t2 + (1.0 - p1 * p1 - p2 * p2).max(0.0).sqrt() * vh;
+        Vec3::new(ax * nh.x, ay * nh.y, nh.z.max(0.0)).normalize()
+      }
+      MicrofacetKind::Beckmann => {
+        // the slopes are Gaussian with deviations alpha / sqrt(2)
+        let r = (-(1.0 - u.0).ln()).sqrt();
+        let phi = 2.0 * PI * u.1;
+        Vec3::new(-ax * r * phi.cos(), -ay * r * phi.sin(), 1.0).normalize()
+      }
+    }
+  }
+
+  // density of sample_wh(wo)
+  pub fn pdf_wh(&self, wo: Vec3, wh: Vec3) -> f64 {
+    match self.kind {
+      MicrofacetKind::Ggx => self.g1(wo) * wo.dot(&wh).max(0.0) * self.d(wh) / wo.z,
+      MicrofacetKind::Beckmann => self.d(wh) * wh.z,
+    }
+  }
+}
+
+// Fresnel reflectance of a conductor with complex index of refraction eta + i k (per channel),
+// for light arriving at cos_i to the normal (exact, unpolarized)
+pub fn fresnel_conductor(cos_i: f64, eta: ColorType, k: ColorType) -> ColorType {
+  let cos_i = cos_i.clamp(0.0, 1.0);
+  let (cos2, sin2) = (cos_i * cos_i, 1.0 - cos_i * cos_i);
+  let channel = |eta: f64, k: f64| {
+    let t0 = eta * eta - k * k - sin2;
+    let a2_plus_b2 = (t0 * t0 + 4.0 * eta * eta * k * k).sqrt();
+    let a = (0.5 * (a2_plus_b2 + t0)).max(0.0).sqrt();
+    let (t1, t2) = (a2_plus_b2 + cos2, 2.0 * cos_i * a);
+    let rs = (t1 - t2) / (t1 + t2);
+    let (t3, t4) = (cos2 * a2_plus_b2 + sin2 * sin2, t2 * sin2);
+    let rp = rs * (t3 - t4) / (t3 + t4);
+    0.5 * (rs + rp)
+  };
+  ColorType::new(channel(eta.x, k.x), channel(eta.y, k.y), channel(eta.z, k.z))
+}
+
+// Fresnel reflectance of a dielectric interface for light arriving at cos_i (>= 0) to the normal,
+// eta the index of refraction on the far side over that on the near side (exact, unpolarized); 1 beyond the critical angle
+pub fn fresnel_dielectric(cos_i: f64, eta: f64) -> f64 {
+  let cos_i = cos_i.clamp(0.0, 1.0);
+  let sin2_t = (1.0 - cos_i * cos_i) / (eta * eta);
+  if sin2_t >= 1.0 {
+    return 1.0; // total internal reflection
+  }
+  let cos_t = (1.0 - sin2_t).sqrt();
+  let r_parallel = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+  let r_perpendicular = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+  0.5 * (r_parallel * r_parallel + r_perpendicular * r_perpendicular)
+}
+
+// `w` refracted through a surface with normal `n` (w and n on the same side, unit), eta as in fresnel_dielectric;
+// None on total internal reflection
+pub fn refract_direction(w: Vec3, n: Vec3, eta: f64) -> Option<Vec3> {
+  let cos_i = w.dot(&n);
+  let sin2_t = (1.0 - cos_i * cos_i).max(0.0) / (eta * eta);
+  if sin2_t >= 1.0 {
+    return None;
+  }
+  let cos_t = (1.0 - sin2_t).sqrt();
+  Some(-w / eta + (cos_i / eta - cos_t) * n)
+}
+
+
+#[cfg(test)]
+mod tests {
+  use crate::*;
+
+  #[test]
+  fn test_microfacet() {
+    let mut sampler = SamplerKind::Independent.create(1, 0);
+    seed_rng(1);
+    for kind in [MicrofacetKind::Ggx, MicrofacetKind::Beckmann] {
+      for (rx, ry) in [(0.3, 0.3), (0.7, 0.4)] {
+        let mf = Microfacet::new(kind, rx, ry);
+        // projected normals cover the surface: the integral of D(wh) cos(theta_h) is 1
+        let n = 200000;
+        let integral = (0..n).map(|_| {
+          let wh = sample_cosine_hemisphere(sampler.get_2d());
+          mf.d(wh) * PI // D cos / (cos / pi)
+        }).sum::<f64>() / n as f64;
+        assert!((integral - 1.0).abs() < 0.03, "{:?} {}", kind, integral);
+
+        let wo = Vec3::new(0.3, -0.5, 0.6).normalize();
+        for _ in 0..100 {
+          let wh = mf.sample_wh(wo, sampler.get_2d());
+          assert!(wh.z > 0.0 && mf.pdf_wh(wo, wh) > 0.0);
+        }
+        assert!(mf.g(wo, wo) <= mf.g1(wo) && mf.g1(wo) <= 1.0);
+      }
+    }
+
+    // at normal incidence: ((eta - 1)^2 + k^2) / ((eta + 1)^2 + k^2)
+    let (eta, k) = (ColorType::new(0.2, 1.0, 1.5), ColorType::new(3.9, 2.4, 0.0));
+    let f0 = fresnel_conductor(1.0, eta, k);
+    assert!((f0.x - (0.64 + 3.9 * 3.9) / (1.44 + 3.9 * 3.9)).abs() < 1e-12);
+    assert!((f0.z - 0.04).abs() < 1e-12); // a dielectric with k = 0
+    assert!((fresnel_conductor(0.0, eta, k) - ColorType::ones()).norm() < 1e-9); // grazing
+
+    assert!((fresnel_dielectric(1.0, 1.5) - 0.04).abs() < 1e-12);
+    assert_eq!(fresnel_dielectric(0.5, 1.0 / 1.5), 1.0); // beyond the critical angle
+    let w = Vec3::new(0.6, 0.0, 0.8);
+    let t = refract_direction(w, Vec3::new(0.0, 0.0, 1.0), 1.5).unwrap();
+    assert!((t.norm() - 1.0).abs() < 1e-12 && (t.x + 0.4).abs() < 1e-12); // Snell: sin_t = sin_i / eta
+  }
+}
@@ -25,6 +25,21 @@ pub mod texture;
 pub mod perlin;
 pub mod planar;
 pub mod constant_medium;
+pub mod scene;
+pub mod framebuffer;
+pub mod output;
+pub mod tonemap;
+pub mod sampler;
+pub mod tile;
+pub mod observer;
+pub mod aov;
+pub mod denoise;
+pub mod filter;
+pub mod onb;
+pub mod light;
+pub mod microfacet;
+pub mod interior;
+pub mod spectrum;
 
 pub use utility::{*};
 pub use color::{*};
@@ -42,3 +57,18 @@ pub use texture::{*};
 pub use perlin::{*};
 pub use planar::{*};
 pub use constant_medium::{*};
+pub use scene::{*};
+pub use framebuffer::{*};
+pub use output::{*};
+pub use tonemap::{*};
+pub use sampler::{*};
+pub use tile::{*};
+pub use observer::{*};
+pub use aov::{*};
+pub use denoise::{*};
+pub use filter::{*};
+pub use onb::{*};
+pub use light::{*};
+pub use microfacet::{*};
+pub use interior::{*};
+pub use spectrum::{*};
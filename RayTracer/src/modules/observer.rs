@@ -0,0 +1,103 @@
+// Render events, for tools that embed the renderer: Camera::render_with reports to a RenderObserver
+// and stops early once its CancelToken is cancelled. Every callback runs on the thread that called render_with.
+
+use crate::framebuffer::{*};
+use crate::tile::{*};
+use crate::utility::{*};
+
+use indicatif::ProgressBar;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RenderProgress {
+  pub pass: usize, // current pass, from 0
+  pub pass_count: usize, // planned passes; adaptive sampling may finish earlier
+  pub target: usize, // samples per pixel at the end of the current pass
+  pub resumed_at: usize, // samples per pixel the render started from (0 unless resuming)
+  pub sample_per_pixel: usize,
+  pub tiles_done: usize, // in the current pass
+  pub tile_count: usize,
+  pub fraction: f64, // of the planned samples, in [0, 1]
+  pub elapsed: Duration,
+  pub eta: Option<Duration>, // None until some samples are done
+}
+
+// All methods do nothing by default.
+pub trait RenderObserver {
+  fn render_started(&self, _progress: &RenderProgress, _threads: usize) {}
+  fn pass_started(&self, _progress: &RenderProgress) {}
+  // `img` is the whole image so far; the pixels of `tile` have just received this pass's samples
+  fn tile_completed(&self, _tile: &Tile, _img: &FrameBuffer, _progress: &RenderProgress) {}
+  fn pass_completed(&self, _img: &FrameBuffer, _progress: &RenderProgress) {}
+  fn warning(&self, _message: &str) {}
+  fn render_finished(&self, _img: &FrameBuffer, _cancelled: bool) {}
+}
+
+// Reports nothing.
+pub struct NullObserver;
+
+impl RenderObserver for NullObserver {}
+
+// The terminal progress bar of the command-line renderer.
+pub struct ProgressBarObserver {
+  bar: ProgressBar,
+}
+
+impl ProgressBarObserver {
+  pub fn new() -> Self {
+    Self { bar: get_ProgressBar(0, 0) }
+  }
+}
+
+impl RenderObserver for ProgressBarObserver {
+  fn render_started(&self, progress: &RenderProgress, threads: usize) {
+    println!("[Render progress]:");
+    if progress.resumed_at > 0 {
+      println!("Info: resuming at {}/{} samples per pixel", progress.resumed_at, progress.sample_per_pixel);
+    }
+    self.bar.set_length((progress.tile_count * progress.pass_count) as u64);
+    self.bar.set_message(format!("|{} threads|", threads));
+  }
+
+  fn tile_completed(&self, _tile: &Tile, _img: &FrameBuffer, _progress: &RenderProgress) {
+    self.bar.inc(1);
+  }
+
+  fn warning(&self, message: &str) {
+    self.bar.println(format!("Warning: {}", message));
+  }
+
+  fn render_finished(&self, img: &FrameBuffer, cancelled: bool) {
+    if cancelled {
+      self.bar.abandon();
+      println!("Info: render cancelled");
+    } else {
+      self.bar.finish();
+    }
+    if img.min_sample_count() != img.max_sample_count() {
+      println!("Info: {} to {} samples per pixel", img.min_sample_count(), img.max_sample_count());
+    }
+  }
+}
+
+// Shared flag to stop a render: the workers finish their current row of pixels and the samples taken so far are returned.
+#[derive(Clone)]
+pub struct CancelToken {
+  cancelled: Arc<AtomicBool>,
+}
+
+impl CancelToken {
+  pub fn new() -> Self {
+    Self { cancelled: Arc::new(AtomicBool::new(false)) }
+  }
+
+  pub fn cancel(&self) {
+    self.cancelled.store(true, Ordering::Relaxed);
+  }
+
+  pub fn is_cancelled(&self) -> bool {
+    self.cancelled.load(Ordering::Relaxed)
+  }
+}
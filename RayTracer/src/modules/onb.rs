@@ -0,0 +1,46 @@
+// Orthonormal basis around a direction w, for sampling directions in local coordinates (z along w)
+
+use crate::vec3::{*};
+
+#[derive(Clone, Copy, Debug)]
+pub struct Onb {
+  pub u: Vec3,
+  pub v: Vec3,
+  pub w: Vec3,
+}
+
+impl Onb {
+  // `n` need not be normalized (Duff et al. 2017, "Building an Orthonormal Basis, Revisited")
+  pub fn new(n: Vec3) -> Self {
+    let w = n.normalize();
+    let sign = 1.0_f64.copysign(w.z);
+    let a = -1.0 / (sign + w.z);
+    let b = w.x * w.y * a;
+    Self {
+      u: Vec3::new(1.0 + sign * w.x * w.x * a, sign * b, -sign * w.x),
+      v: Vec3::new(b, sign + w.y * w.y * a, -w.y),
+      w,
+    }
+  }
+
+  // with u along `tangent` projected onto the plane of `n`; any basis around n where that projection vanishes
+  pub fn new_with_tangent(n: Vec3, tangent: Vec3) -> Self {
+    let w = n.normalize();
+    let u = tangent - w * tangent.dot(&w);
+    if u.norm_squared() < 1e-12 {
+      return Self::new(n);
+    }
+    let u = u.normalize();
+    Self { u, v: w.cross(&u), w }
+  }
+
+  // local (x, y, z) -> world
+  pub fn local(&self, a: Vec3) -> Vec3 {
+    a.x * self.u + a.y * self.v + a.z * self.w
+  }
+
+  // world -> local
+  pub fn to_local(&self, a: Vec3) -> Vec3 {
+    Vec3::new(a.dot(&self.u), a.dot(&self.v), a.dot(&self.w))
+  }
+}
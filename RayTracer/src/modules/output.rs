@@ -0,0 +1,281 @@
+// Image output
+// Camera::render returns linear radiance; the 8/16-bit formats are tone mapped, encoded with the
+// output transfer function (sRGB by default) and clamped here, while .hdr/.exr store the linear floating-point values untouched.
+// AOV layers are written next to the beauty pass, one image each.
+
+use crate::aov::{*};
+use crate::color::{*};
+use crate::framebuffer::{*};
+use crate::tonemap::{*};
+use crate::INFINITY;
+use crate::sampler::hash_values;
+
+use image::{DynamicImage, ImageBuffer, ImageOutputFormat, Rgb, Rgb32FImage, RgbImage};
+use image::codecs::hdr::{HdrDecoder, HdrEncoder};
+use image::codecs::pnm::{PnmSubtype, SampleEncoding};
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::Path;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OutputFormat {
+  Jpeg,
+  Png, // 8-bit
+  Png16,
+  Ppm, // plain (ASCII) PPM
+  Hdr, // Radiance RGBE, linear
+  Exr, // OpenEXR 32-bit float, linear
+}
+
+pub const OUTPUT_FORMAT_NAMES: [&str; 6] = ["jpeg", "png", "png16", "ppm", "hdr", "exr"];
+
+impl OutputFormat {
+  pub fn from_name(name: &str) -> Option<Self> {
+    match name {
+      "jpeg" | "jpg" => Some(Self::Jpeg),
+      "png" => Some(Self::Png),
+      "png16" => Some(Self::Png16),
+      "ppm" => Some(Self::Ppm),
+      "hdr" => Some(Self::Hdr),
+      "exr" => Some(Self::Exr),
+      _ => None,
+    }
+  }
+
+  // .png maps to 8-bit PNG; 16-bit PNG has to be asked for with --format png16
+  pub fn from_extension(path: &str) -> Option<Self> {
+    let ext = Path::new(path).extension()?.to_str()?.to_ascii_lowercase();
+    match ext.as_str() {
+      "jpeg" | "jpg" | "png" | "ppm" | "hdr" | "exr" => Self::from_name(&ext),
+      _ => None,
+    }
+  }
+
+  pub fn is_hdr(&self) -> bool {
+    matches!(self, Self::Hdr | Self::Exr)
+  }
+}
+
+fn to_rgb16(img: &FrameBuffer, transfer: TransferFunction) -> ImageBuffer<Rgb<u16>, Vec<u16>> {
+  ImageBuffer::from_fn(img.width as u32, img.height as u32, |i, j| {
+    let color = transfer.encode_ColorType(img.get(i as usize, j as usize));
+    let quantize = |x: f64| (x.clamp(0.0, 1.0) * 65535.0).round() as u16;
+    Rgb([quantize(color.x), quantize(color.y), quantize(color.z)])
+  })
+}
+
+fn create_parent_dir(file_name: &str) -> Result<(), String> {
+  if let Some(parent) = Path::new(file_name).parent() {
+    if !parent.as_os_str().is_empty() {
+      std::fs::create_dir_all(parent).map_err(|err| format!("cannot create \"{}\": {}", parent.display(), err))?;
+    }
+  }
+  Ok(())
+}
+
+pub fn write_image(img: &FrameBuffer, file_name: &str, format: OutputFormat, quality: u8, tone_mapping: &ToneMapping, transfer: TransferFunction) -> Result<(), String> {
+  create_parent_dir(file_name)?;
+  let output_file = File::create(file_name).map_err(|err| format!("cannot create \"{}\": {}", file_name, err))?;
+  let mut writer = BufWriter::new(output_file);
+
+  let tone_mapped = if format.is_hdr() { None } else { Some(tone_mapping.apply(img)) };
+  let display = tone_mapped.as_ref().unwrap_or(img);
+  let result = match format {
+    OutputFormat::Jpeg => DynamicImage::ImageRgb8(display.to_rgb8(transfer)).write_to(&mut writer, ImageOutputFormat::Jpeg(quality)),
+    OutputFormat::Png => DynamicImage::ImageRgb8(display.to_rgb8(transfer)).write_to(&mut writer, ImageOutputFormat::Png),
+    OutputFormat::Png16 => DynamicImage::ImageRgb16(to_rgb16(display, transfer)).write_to(&mut writer, ImageOutputFormat::Png),
+    OutputFormat::Ppm => DynamicImage::ImageRgb8(display.to_rgb8(transfer))
+      .write_to(&mut writer, ImageOutputFormat::Pnm(PnmSubtype::Pixmap(SampleEncoding::Ascii))),
+    OutputFormat::Hdr => {
+      let pixels: Vec<Rgb<f32>> = img.to_rgb32f().pixels().map(|p| Rgb([p[0].max(0.0), p[1].max(0.0), p[2].max(0.0)])).collect();
+      HdrEncoder::new(&mut writer).encode(&pixels, img.width, img.height)
+    }
+    OutputFormat::Exr => DynamicImage::ImageRgb32F(img.to_rgb32f()).write_to(&mut writer, ImageOutputFormat::OpenExr),
+  };
+  result.map_err(|err| format!("cannot write \"{}\": {}", file_name, err))
+}
+
+// Read an earlier render back into a FrameBuffer (one sample per pixel). .hdr/.exr hold linear radiance;
+// 8/16-bit images are decoded with `transfer`, which gives display values (already tone mapped), not radiance.
+pub fn read_image(file_name: &str, transfer: TransferFunction) -> Result<FrameBuffer, String> {
+  let format = OutputFormat::from_extension(file_name);
+  let img = if format == Some(OutputFormat::Hdr) {
+    read_hdr(file_name)
+  } else {
+    image::open(file_name).map(|img| img.into_rgb32f())
+  }.map_err(|err| format!("cannot read \"{}\": {}", file_name, err))?;
+  let mut buffer = FrameBuffer::from_rgb32f(&img);
+  if !format.map_or(false, |format| format.is_hdr()) {
+    let mut decoded = FrameBuffer::new(buffer.width, buffer.height);
+    for j in 0..buffer.height {
+      for i in 0..buffer.width {
+        decoded.add_sample(i, j, transfer.decode_ColorType(buffer.get(i, j)));
+      }
+    }
+    buffer = decoded;
+  }
+  Ok(buffer)
+}
+
+// image::open tone maps Radiance files down to 8 bits; the decoder itself gives the stored floats
+fn read_hdr(file_name: &str) -> image::ImageResult<Rgb32FImage> {
+  let decoder = HdrDecoder::new(BufReader::new(File::open(file_name)?))?;
+  let (width, height) = (decoder.metadata().width, decoder.metadata().height);
+  let pixels: Vec<f32> = decoder.read_image_hdr()?.iter().flat_map(|p| p.0).collect();
+  Ok(Rgb32FImage::from_raw(width, height, pixels).unwrap())
+}
+
+// black -> blue -> red -> yellow -> white for t in [0, 1]
+fn heat_color(t: f64) -> Rgb<u8> {
+  const STOPS: [[f64; 3]; 5] = [[0.0, 0.0, 0.0], [0.1, 0.1, 0.9], [0.9, 0.1, 0.1], [1.0, 0.9, 0.1], [1.0, 1.0, 1.0]];
+  let x = t.clamp(0.0, 1.0) * (STOPS.len() - 1) as f64;
+  let k = (x as usize).min(STOPS.len() - 2);
+  let f = x - k as f64;
+  let channel = |c: usize| ((STOPS[k][c] * (1.0 - f) + STOPS[k + 1][c] * f) * 255.0).round() as u8;
+  Rgb([channel(0), channel(1), channel(2)])
+}
+
+// Sample-count heatmap: black for the fewest samples in the image, white for the most.
+// The format follows the file extension. Returns those two sample counts.
+pub fn write_sample_heatmap(img: &FrameBuffer, file_name: &str) -> Result<(u32, u32), String> {
+  let (min, max) = (img.min_sample_count() as f64, img.max_sample_count() as f64);
+  let heatmap = RgbImage::from_fn(img.width as u32, img.height as u32, |i, j| {
+    let count = img.sample_count(i as usize, j as usize) as f64;
+    heat_color(if max > min { (count - min) / (max - min) } else { 0.0 })
+  });
+  create_parent_dir(file_name)?;
+  heatmap.save(file_name).map_err(|err| format!("cannot write \"{}\": {}", file_name, err))?;
+  Ok((img.min_sample_count(), img.max_sample_count()))
+}
+
+// "output/test.jpg" -> "output/test.albedo.jpg"
+pub fn aov_file_name(file_name: &str, aov: Aov) -> String {
+  let path = Path::new(file_name);
+  let stem = path.file_stem().map_or(String::new(), |stem| stem.to_string_lossy().into_owned());
+  let name = match path.extension() {
+    Some(ext) => format!("{}.{}.{}", stem, aov.name(), ext.to_string_lossy()),
+    None => format!("{}.{}", stem, aov.name()),
+  };
+  path.with_file_name(name).to_string_lossy().into_owned()
+}
+
+// distinct, stable colour per ID; black for 0 (nothing hit)
+fn id_color(id: u64) -> ColorType {
+  if id == 0 {
+    return ColorType::zero();
+  }
+  let h = hash_values(&[id]);
+  let channel = |shift: u32| 0.2 + 0.8 * ((h >> shift) & 0xff) as f64 / 255.0;
+  ColorType::new(channel(0), channel(8), channel(16))
+}
+
+// The layer in [0, 1] for 8/16-bit images: albedo encoded with `transfer`, normals mapped from [-1, 1],
+// depth and position stretched over their range in the image (per channel), IDs in false colour.
+// Pixels without a value are black.
+fn aov_display(layer: &AovBuffer, transfer: TransferFunction) -> FrameBuffer {
+  let mut lo = ColorType::ones() * INFINITY;
+  let mut hi = -lo;
+  for j in 0..layer.height {
+    for i in 0..layer.width {
+      if let Some(v) = layer.get(i, j) {
+        lo = ColorType::new(lo.x.min(v.x), lo.y.min(v.y), lo.z.min(v.z));
+        hi = ColorType::new(hi.x.max(v.x), hi.y.max(v.y), hi.z.max(v.z));
+      }
+    }
+  }
+  let stretch = |x: f64, lo: f64, hi: f64| if hi > lo { (x - lo) / (hi - lo) } else { 0.0 };
+
+  let mut display = FrameBuffer::new(layer.width, layer.height);
+  for j in 0..layer.height {
+    for i in 0..layer.width {
+      let color = layer.get(i, j).map_or(ColorType::zero(), |v| match layer.aov {
+        Aov::Albedo => transfer.encode_ColorType(v),
+        Aov::Normal => (v + ColorType::ones()) * 0.5,
+        Aov::Depth | Aov::Position => ColorType::new(stretch(v.x, lo.x, hi.x), stretch(v.y, lo.y, hi.y), stretch(v.z, lo.z, hi.z)),
+        Aov::Uv => v,
+        Aov::ObjectId | Aov::MaterialId => id_color(v.x as u64),
+      });
+      display.add_sample(i, j, color);
+    }
+  }
+  display
+}
+
+// One AOV layer of `img` as an image. .hdr/.exr get the raw values (.hdr drops negative ones);
+// the other formats a viewable version, see aov_display().
+pub fn write_aov(img: &FrameBuffer, aov: Aov, file_name: &str, format: OutputFormat, quality: u8, transfer: TransferFunction) -> Result<(), String> {
+  let layer = img.aov(aov).ok_or_else(|| format!("the render has no {} layer", aov.name()))?;
+  let values = if format.is_hdr() {
+    let mut values = FrameBuffer::new(layer.width, layer.height);
+    for j in 0..layer.height {
+      for i in 0..layer.width {
+        values.add_sample(i, j, layer.get(i, j).unwrap_or(ColorType::zero()));
+      }
+    }
+    values
+  } else {
+    aov_display(layer, transfer)
+  };
+  write_image(&values, file_name, format, quality, &ToneMapping::default(), TransferFunction::Linear)
+}
+
+
+#[cfg(test)]
+mod tests {
+  use crate::*;
+
+  #[test]
+  fn test_output_formats() {
+    assert_eq!(OutputFormat::from_extension("out/image.PNG"), Some(OutputFormat::Png)); // 8-bit, whatever the case
+    assert_eq!(OutputFormat::from_extension("a.Jpg"), Some(OutputFormat::Jpeg));
+    assert_eq!(OutputFormat::from_extension("a.jpeg"), Some(OutputFormat::Jpeg));
+    assert_eq!(OutputFormat::from_extension("a.EXR"), Some(OutputFormat::Exr));
+    assert_eq!(OutputFormat::from_extension("a.png16"), None);
+    assert_eq!(OutputFormat::from_extension("a.bmp"), None);
+    assert_eq!(OutputFormat::from_extension("image"), None);
+    assert_eq!(OutputFormat::from_name("jpg"), Some(OutputFormat::Jpeg));
+    assert_eq!(OutputFormat::from_name("png16"), Some(OutputFormat::Png16));
+    assert_eq!(OutputFormat::from_name("PNG"), None); // names are lower case, as --format takes them
+    assert!(OUTPUT_FORMAT_NAMES.iter().all(|name| OutputFormat::from_name(name).is_some()));
+    assert!(OutputFormat::Hdr.is_hdr() && !OutputFormat::Png16.is_hdr());
+  }
+
+  #[test]
+  fn test_write_read_round_trip() {
+    let mut img = FrameBuffer::new(2, 2);
+    img.add_sample(0, 0, ColorType::new(0.2, 0.5, 0.8));
+    img.add_sample(1, 0, ColorType::new(0.01, 0.0, 1.0));
+    img.add_sample(0, 1, ColorType::new(4.0, 0.25, 0.05)); // beyond 1: kept by .hdr/.exr only
+    let dir = std::env::temp_dir();
+    let path = |ext: &str| dir.join(format!("ray_tracer_output_{}.{}", std::process::id(), ext)).to_str().unwrap().to_string();
+    // relative to the brightest channel above 1, since RGBE shares one exponent between the channels
+    let max_error = |a: &FrameBuffer, b: &FrameBuffer, pixels: &[(usize, usize)]| {
+      pixels.iter().map(|&(i, j)| {
+        let c = a.get(i, j);
+        (c - b.get(i, j)).norm() / c.x.max(c.y).max(c.z).max(1.0)
+      }).fold(0.0, f64::max)
+    };
+    let in_range = [(0, 0), (1, 0), (1, 1)];
+    let all = [(0, 0), (1, 0), (0, 1), (1, 1)];
+
+    for (ext, format, tolerance) in [("png", OutputFormat::Png, 1e-2), ("png", OutputFormat::Png16, 1e-4)] {
+      let file = path(ext);
+      write_image(&img, &file, format, 90, &ToneMapping::default(), TransferFunction::Srgb).unwrap();
+      let back = read_image(&file, TransferFunction::Srgb).unwrap();
+      assert!(max_error(&img, &back, &in_range) < tolerance, "{:?}", format);
+      assert_eq!(back.get(0, 1).x, 1.0); // clamped
+      // the file holds sRGB-encoded values
+      let encoded = read_image(&file, TransferFunction::Linear).unwrap();
+      assert!((encoded.get(0, 0).x - srgb_encode(0.2)).abs() < 1e-2);
+      std::fs::remove_file(&file).unwrap();
+    }
+
+    for (ext, format, tolerance) in [("hdr", OutputFormat::Hdr, 2e-2), ("exr", OutputFormat::Exr, 1e-6)] {
+      let file = path(ext);
+      write_image(&img, &file, format, 90, &ToneMapping::default(), TransferFunction::Srgb).unwrap();
+      let back = read_image(&file, TransferFunction::Srgb).unwrap(); // linear, the transfer function is not used
+      assert!(max_error(&img, &back, &all) < tolerance, "{:?}", format);
+      std::fs::remove_file(&file).unwrap();
+    }
+  }
+}
@@ -5,7 +5,9 @@ use crate::texture::{*};
 use crate::aabb::{*};
 use crate::hittable::{*};
 use crate::interval::{*};
-use crate::{EPS, PI};
+use crate::ray::{*};
+use crate::sampler::{*};
+use crate::{EPS, INFINITY, PI};
 
 use std::sync::{Arc};
 
@@ -13,6 +15,18 @@ pub trait Planar {
   fn is_interier(&self, a: f64, b: f64, rec: &mut HitRecord) -> bool;
 }
 
+// Solid-angle density at `origin` of points sampled uniformly over the `area` of a planar shape, towards `direction`:
+// distance^2 / (|cos| * area) where the direction hits the shape.
+fn planar_pdf_value(shape: &dyn Hittable, area: f64, origin: Point3, direction: Vec3) -> f64 {
+  let mut rec = HitRecord::default();
+  if !shape.hit(&Ray::new(origin, direction, 0.0), Interval::new(EPS, INFINITY), &mut rec) {
+    return 0.0;
+  }
+  let distance_squared = rec.t * rec.t * direction.norm_squared();
+  let cosine = (direction.dot(&rec.normal) / direction.norm()).abs();
+  distance_squared / (cosine * area)
+}
+
 
 pub struct Quad {
   Q: Point3,
@@ -20,9 +34,11 @@ pub struct Quad {
   v: Vec3,
   w: Vec3,
   mat: Material,
+  ids: HitIds,
   bbox: Aabb,
   normal: Vec3,
   D: f64, // constant for plane equation
+  area: f64,
 }
 
 impl Quad {
@@ -39,10 +55,12 @@ impl Quad {
       u,
       v,
       w,
+      ids: HitIds::new(&mat),
       mat,
       bbox,
       normal,
       D,
+      area: n.norm(),
     }
   }
 }
@@ -99,6 +117,7 @@ impl Hittable for Quad {
       t,
       rec.u, rec.v
     );
+    rec.ids = self.ids;
 
     true
   }
@@ -108,6 +127,16 @@ impl Hittable for Quad {
   fn bounding_box(&self) -> Aabb {
     self.bbox
   }
+  fn pdf_value(&self, origin: Point3, direction: Vec3) -> f64 {
+    planar_pdf_value(self, self.area, origin, direction)
+  }
+  fn random(&self, origin: Point3, u: (f64, f64)) -> Vec3 {
+    let p = self.Q + u.0 * self.u + u.1 * self.v;
+    p - origin
+  }
+  fn is_light(&self) -> bool {
+    self.mat.is_emissive()
+  }
 }
 
 pub fn build_box(a: Point3, b: Point3, mat: Material) -> HittableList {
@@ -174,9 +203,11 @@ pub struct Triangle {
   v: Vec3,
   w: Vec3,
   mat: Material,
+  ids: HitIds,
   bbox: Aabb,
   normal: Vec3,
   D: f64, // constant for plane equation
+  area: f64,
 }
 
 impl Triangle {
@@ -193,10 +224,12 @@ impl Triangle {
       u,
       v,
       w,
+      ids: HitIds::new(&mat),
       mat,
       bbox,
       normal,
       D,
+      area: n.norm() / 2.0,
     }
   }
 }
@@ -253,6 +286,7 @@ impl Hittable for Triangle {
       t,
       rec.u, rec.v
     );
+    rec.ids = self.ids;
 
     true
   }
@@ -262,6 +296,17 @@ impl Hittable for Triangle {
   fn bounding_box(&self) -> Aabb {
     self.bbox
   }
+  fn pdf_value(&self, origin: Point3, direction: Vec3) -> f64 {
+    planar_pdf_value(self, self.area, origin, direction)
+  }
+  fn random(&self, origin: Point3, u: (f64, f64)) -> Vec3 {
+    let s = u.0.sqrt(); // uniform over the triangle (a, b >= 0, a + b <= 1)
+    let p = self.Q + s * (1.0 - u.1) * self.u + s * u.1 * self.v;
+    p - origin
+  }
+  fn is_light(&self) -> bool {
+    self.mat.is_emissive()
+  }
 }
 
 
@@ -277,9 +322,11 @@ pub struct Circle {
   v: Vec3,
   w: Vec3,
   mat: Material,
+  ids: HitIds,
   bbox: Aabb,
   normal: Vec3,
   D: f64, // constant for plane equation
+  area: f64,
 }
 
 impl Circle {
@@ -296,10 +343,12 @@ impl Circle {
       u,
       v,
       w,
+      ids: HitIds::new(&mat),
       mat,
       bbox,
       normal,
       D,
+      area: PI * n.norm(),
     }
   }
 }
@@ -356,6 +405,7 @@ impl Hittable for Circle {
       t,
       rec.u, rec.v
     );
+    rec.ids = self.ids;
 
     true
   }
@@ -365,6 +415,17 @@ impl Hittable for Circle {
   fn bounding_box(&self) -> Aabb {
     self.bbox
   }
+  fn pdf_value(&self, origin: Point3, direction: Vec3) -> f64 {
+    planar_pdf_value(self, self.area, origin, direction)
+  }
+  fn random(&self, origin: Point3, u: (f64, f64)) -> Vec3 {
+    let d = sample_unit_disk(u);
+    let p = self.Q + d.x * self.u + d.y * self.v;
+    p - origin
+  }
+  fn is_light(&self) -> bool {
+    self.mat.is_emissive()
+  }
 }
 
 
@@ -375,9 +436,11 @@ pub struct Ring {
   min_ratio: f64,
   w: Vec3,
   mat: Material,
+  ids: HitIds,
   bbox: Aabb,
   normal: Vec3,
   D: f64, // constant for plane equation
+  area: f64,
 }
 
 impl Ring {
@@ -395,10 +458,12 @@ impl Ring {
       v,
       min_ratio,
       w,
+      ids: HitIds::new(&mat),
       mat,
       bbox,
       normal,
       D,
+      area: PI * n.norm() * (1.0 - min_ratio * min_ratio),
     }
   }
 }
@@ -456,6 +521,7 @@ impl Hittable for Ring {
       t,
       rec.u, rec.v
     );
+    rec.ids = self.ids;
 
     true
   }
@@ -465,4 +531,18 @@ impl Hittable for Ring {
   fn bounding_box(&self) -> Aabb {
     self.bbox
   }
+  fn pdf_value(&self, origin: Point3, direction: Vec3) -> f64 {
+    planar_pdf_value(self, self.area, origin, direction)
+  }
+  fn random(&self, origin: Point3, u: (f64, f64)) -> Vec3 {
+    let d = sample_unit_disk(u);
+    let r = d.norm();
+    // r^2 is uniform in [0, 1]; stretch it over [min_ratio^2, 1]
+    let scale = if r > 0.0 { (self.min_ratio * self.min_ratio + (1.0 - self.min_ratio * self.min_ratio) * r * r).sqrt() / r } else { 0.0 };
+    let p = self.Q + scale * (d.x * self.u + d.y * self.v);
+    p - origin
+  }
+  fn is_light(&self) -> bool {
+    self.mat.is_emissive()
+  }
 }
\ No newline at end of file
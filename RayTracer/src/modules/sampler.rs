@@ -0,0 +1,427 @@
+// Sample generators
+// Everything random along a camera path (pixel offset, time, lens position, scattering directions)
+// is drawn from a Sampler as 1D or 2D values in [0, 1). Each pixel sample restarts the sequence with
+// start_pixel_sample(); the n-th 1D/2D request of a pixel sample is its n-th "dimension", so a sampler
+// can spread the values of one dimension evenly over the samples of a pixel.
+//
+// Independent: white noise (rand_01), the original behaviour
+// Stratified:  jittered strata, shuffled per pixel and dimension
+// Halton:      radical inverse in prime bases, randomized per pixel by Cranley-Patterson rotation
+// Sobol:       Owen-scrambled Sobol' (0,2)-sequence, padded over dimensions by shuffling
+//              (Burley 2020, "Practical Hash-based Owen Scrambling")
+
+use crate::utility::{*};
+use crate::vec3::{*};
+use crate::PI;
+
+pub trait Sampler {
+  fn start_pixel_sample(&mut self, i: usize, j: usize, sample_index: usize);
+  fn get_1d(&mut self) -> f64;
+  fn get_2d(&mut self) -> (f64, f64);
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SamplerKind {
+  Independent,
+  Stratified,
+  Halton,
+  Sobol,
+}
+
+pub const SAMPLER_NAMES: [&str; 4] = ["independent", "stratified", "halton", "sobol"];
+
+impl SamplerKind {
+  pub fn from_name(name: &str) -> Option<Self> {
+    match name {
+      "independent" => Some(Self::Independent),
+      "stratified" => Some(Self::Stratified),
+      "halton" => Some(Self::Halton),
+      "sobol" => Some(Self::Sobol),
+      _ => None,
+    }
+  }
+
+  // one sampler per render thread; samples_per_pixel sizes the strata of the stratified sampler,
+  // seed selects the per-pixel randomization (the independent sampler follows the thread's RNG instead)
+  pub fn create(&self, samples_per_pixel: usize, seed: u64) -> Box<dyn Sampler> {
+    match self {
+      Self::Independent => Box::new(IndependentSampler {}),
+      Self::Stratified => Box::new(StratifiedSampler::new(samples_per_pixel, seed)),
+      Self::Halton => Box::new(HaltonSampler::new(seed)),
+      Self::Sobol => Box::new(SobolSampler::new(seed)),
+    }
+  }
+}
+
+
+// hashing
+
+// splitmix64 finalizer
+pub fn mix_bits(mut v: u64) -> u64 {
+  v ^= v >> 31;
+  v = v.wrapping_mul(0x7fb5d329728ea185);
+  v ^= v >> 27;
+  v = v.wrapping_mul(0x81dadef4bc2dd44d);
+  v ^= v >> 33;
+  v
+}
+
+pub fn hash_values(values: &[u64]) -> u64 {
+  values.iter().fold(0x9e3779b97f4a7c15, |h, &v| mix_bits(h ^ mix_bits(v)))
+}
+
+fn to_unit_f64(bits: u32) -> f64 {
+  bits as f64 / 4294967296.0 // 2^32, so the result stays below 1
+}
+
+// i-th element of a pseudo-random permutation of 0..l selected by p (Kensler 2013)
+fn permutation_element(mut i: u32, l: u32, p: u32) -> u32 {
+  let mut w = l - 1;
+  w |= w >> 1;
+  w |= w >> 2;
+  w |= w >> 4;
+  w |= w >> 8;
+  w |= w >> 16;
+  loop {
+    i ^= p;
+    i = i.wrapping_mul(0xe170893d);
+    i ^= p >> 16;
+    i ^= (i & w) >> 4;
+    i ^= p >> 8;
+    i = i.wrapping_mul(0x0929eb3f);
+    i ^= p >> 23;
+    i ^= (i & w) >> 1;
+    i = i.wrapping_mul(1 | p >> 27);
+    i = i.wrapping_mul(0x6935fa69);
+    i ^= (i & w) >> 11;
+    i = i.wrapping_mul(0x74dcb303);
+    i ^= (i & w) >> 2;
+    i = i.wrapping_mul(0x9e501cc3);
+    i ^= (i & w) >> 2;
+    i = i.wrapping_mul(0xc860a3df);
+    i &= w;
+    i ^= i >> 5;
+    if i < l {
+      break;
+    }
+  }
+  (i.wrapping_add(p)) % l
+}
+
+
+// warping of 2D samples
+
+pub fn sample_unit_sphere(u: (f64, f64)) -> Vec3 {
+  let z = 1.0 - 2.0 * u.0;
+  let r = (1.0 - z * z).max(0.0).sqrt();
+  let phi = 2.0 * PI * u.1;
+  Vec3::new(r * phi.cos(), r * phi.sin(), z)
+}
+
+// concentric mapping (Shirley & Chiu), keeps strata compact unlike rejection sampling
+pub fn sample_unit_disk(u: (f64, f64)) -> Vec3 {
+  let (a, b) = (2.0 * u.0 - 1.0, 2.0 * u.1 - 1.0);
+  if a == 0.0 && b == 0.0 {
+    return Vec3::zero();
+  }
+  let (r, theta) = if a.abs() > b.abs() {
+    (a, PI / 4.0 * (b / a))
+  } else {
+    (b, PI / 2.0 - PI / 4.0 * (a / b))
+  };
+  Vec3::new(r * theta.cos(), r * theta.sin(), 0.0)
+}
+
+// cosine-weighted direction around +z (pdf cos(theta) / pi), by lifting a disk sample (Malley's method)
+pub fn sample_cosine_hemisphere(u: (f64, f64)) -> Vec3 {
+  let d = sample_unit_disk(u);
+  Vec3::new(d.x, d.y, (1.0 - d.x * d.x - d.y * d.y).max(0.0).sqrt())
+}
+
+
+// Independent
+
+pub struct IndependentSampler {}
+
+impl Sampler for IndependentSampler {
+  fn start_pixel_sample(&mut self, _i: usize, _j: usize, _sample_index: usize) {}
+  fn get_1d(&mut self) -> f64 {
+    rand_01()
+  }
+  fn get_2d(&mut self) -> (f64, f64) {
+    (rand_01(), rand_01())
+  }
+}
+
+
+// Stratified
+
+pub struct StratifiedSampler {
+  seed: u64,
+  strata_1d: usize,
+  strata_x: usize,
+  strata_y: usize,
+  pixel_seed: u64,
+  sample_index: usize,
+  dimension: u64,
+}
+
+impl StratifiedSampler {
+  pub fn new(samples_per_pixel: usize, seed: u64) -> Self {
+    let samples_per_pixel = samples_per_pixel.max(1);
+    let strata_x = (samples_per_pixel as f64).sqrt().ceil() as usize;
+    Self {
+      seed,
+      strata_1d: samples_per_pixel,
+      strata_x,
+      strata_y: (samples_per_pixel + strata_x - 1) / strata_x,
+      pixel_seed: 0,
+      sample_index: 0,
+      dimension: 0,
+    }
+  }
+
+  // stratum of the current sample in the current dimension
+  fn stratum(&mut self, count: usize) -> usize {
+    let seed = hash_values(&[self.pixel_seed, self.dimension]) as u32;
+    self.dimension += 1;
+    permutation_element((self.sample_index % count) as u32, count as u32, seed) as usize
+  }
+}
+
+impl Sampler for StratifiedSampler {
+  fn start_pixel_sample(&mut self, i: usize, j: usize, sample_index: usize) {
+    self.pixel_seed = hash_values(&[self.seed, i as u64, j as u64]);
+    self.sample_index = sample_index;
+    self.dimension = 0;
+  }
+  fn get_1d(&mut self) -> f64 {
+    let stratum = self.stratum(self.strata_1d);
+    (stratum as f64 + rand_01()) / self.strata_1d as f64
+  }
+  fn get_2d(&mut self) -> (f64, f64) {
+    let stratum = self.stratum(self.strata_x * self.strata_y);
+    (
+      ((stratum % self.strata_x) as f64 + rand_01()) / self.strata_x as f64,
+      ((stratum / self.strata_x) as f64 + rand_01()) / self.strata_y as f64,
+    )
+  }
+}
+
+
+// Halton
+
+const PRIMES: [u32; 64] = [
+  2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53, 59, 61, 67, 71, 73, 79, 83, 89, 97, 101, 103, 107, 109, 113, 127, 131,
+  137, 139, 149, 151, 157, 163, 167, 173, 179, 181, 191, 193, 197, 199, 211, 223, 227, 229, 233, 239, 241, 251, 257, 263, 269, 271, 277, 281, 283, 293, 307, 311,
+];
+
+pub fn radical_inverse(base: u32, mut index: u64) -> f64 {
+  let inv_base = 1.0 / base as f64;
+  let mut inv_base_m = 1.0;
+  let mut reversed_digits: u64 = 0;
+  while index > 0 {
+    let next = index / base as u64;
+    reversed_digits = reversed_digits * base as u64 + (index - next * base as u64);
+    inv_base_m *= inv_base;
+    index = next;
+  }
+  (reversed_digits as f64 * inv_base_m).min(1.0 - f64::EPSILON)
+}
+
+pub struct HaltonSampler {
+  seed: u64,
+  pixel_seed: u64,
+  sample_index: usize,
+  dimension: usize,
+}
+
+impl HaltonSampler {
+  pub fn new(seed: u64) -> Self {
+    Self { seed, pixel_seed: 0, sample_index: 0, dimension: 0 }
+  }
+}
+
+impl Sampler for HaltonSampler {
+  fn start_pixel_sample(&mut self, i: usize, j: usize, sample_index: usize) {
+    self.pixel_seed = hash_values(&[self.seed, i as u64, j as u64]);
+    self.sample_index = sample_index;
+    self.dimension = 0;
+  }
+  fn get_1d(&mut self) -> f64 {
+    let dimension = self.dimension;
+    self.dimension += 1;
+    if dimension >= PRIMES.len() { // deeper than the prime table
+      return rand_01();
+    }
+    let rotation = to_unit_f64(hash_values(&[self.pixel_seed, dimension as u64]) as u32);
+    (radical_inverse(PRIMES[dimension], self.sample_index as u64) + rotation).fract()
+  }
+  fn get_2d(&mut self) -> (f64, f64) {
+    (self.get_1d(), self.get_1d())
+  }
+}
+
+
+// Sobol
+
+// generator matrices of the first two Sobol' dimensions, as 32 direction numbers each
+fn sobol_directions() -> [[u32; 32]; 2] {
+  let mut directions = [[0u32; 32]; 2];
+  for bit in 0..32 {
+    directions[0][bit] = 1 << (31 - bit); // van der Corput
+  }
+  directions[1][0] = 1 << 31; // primitive polynomial x + 1
+  for bit in 1..32 {
+    directions[1][bit] = directions[1][bit - 1] ^ (directions[1][bit - 1] >> 1);
+  }
+  directions
+}
+
+fn sobol(directions: &[u32; 32], mut index: u32) -> u32 {
+  let mut result = 0;
+  let mut bit = 0;
+  while index != 0 {
+    if index & 1 != 0 {
+      result ^= directions[bit];
+    }
+    index >>= 1;
+    bit += 1;
+  }
+  result
+}
+
+// hash in which every bit only depends on the bits below it (Vegdahl's constants)
+fn laine_karras_permutation(mut x: u32, seed: u32) -> u32 {
+  x = x.wrapping_add(seed);
+  x ^= x.wrapping_mul(0x6c50b47c);
+  x ^= x.wrapping_mul(0xb82f1e52);
+  x ^= x.wrapping_mul(0xc7afe638);
+  x ^= x.wrapping_mul(0x8d22f6e6);
+  x
+}
+
+// Owen scrambling: each bit is flipped depending on all the bits above it
+fn nested_uniform_scramble(x: u32, seed: u32) -> u32 {
+  laine_karras_permutation(x.reverse_bits(), seed).reverse_bits()
+}
+
+pub struct SobolSampler {
+  seed: u64,
+  directions: [[u32; 32]; 2],
+  pixel_seed: u64,
+  sample_index: usize,
+  dimension: u64,
+}
+
+impl SobolSampler {
+  pub fn new(seed: u64) -> Self {
+    Self { seed, directions: sobol_directions(), pixel_seed: 0, sample_index: 0, dimension: 0 }
+  }
+
+  // every 1D/2D request shuffles the sample order with its own seed, which decorrelates the dimensions
+  fn next_seeds(&mut self) -> (u32, u32, u32) {
+    let hash = hash_values(&[self.pixel_seed, self.dimension]);
+    self.dimension += 1;
+    (hash as u32, (hash >> 32) as u32, mix_bits(hash) as u32)
+  }
+
+  fn shuffled_index(&self, seed: u32) -> u32 {
+    nested_uniform_scramble(self.sample_index as u32, seed)
+  }
+}
+
+impl Sampler for SobolSampler {
+  fn start_pixel_sample(&mut self, i: usize, j: usize, sample_index: usize) {
+    self.pixel_seed = hash_values(&[self.seed, i as u64, j as u64]);
+    self.sample_index = sample_index;
+    self.dimension = 0;
+  }
+  fn get_1d(&mut self) -> f64 {
+    let (index_seed, seed_x, _) = self.next_seeds();
+    let index = self.shuffled_index(index_seed);
+    to_unit_f64(nested_uniform_scramble(sobol(&self.directions[0], index), seed_x))
+  }
+  fn get_2d(&mut self) -> (f64, f64) {
+    let (index_seed, seed_x, seed_y) = self.next_seeds();
+    let index = self.shuffled_index(index_seed);
+    (
+      to_unit_f64(nested_uniform_scramble(sobol(&self.directions[0], index), seed_x)),
+      to_unit_f64(nested_uniform_scramble(sobol(&self.directions[1], index), seed_y)),
+    )
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use crate::*;
+
+  fn pixel_samples(sampler: &mut dyn Sampler, n: usize) -> Vec<(f64, f64)> {
+    (0..n).map(|k| {
+      sampler.start_pixel_sample(3, 5, k);
+      sampler.get_1d(); // skip a dimension
+      sampler.get_2d()
+    }).collect()
+  }
+
+  #[test]
+  fn test_stratification() {
+    // 16 samples of a pixel: one in each cell of a 4x4 grid
+    for kind in [SamplerKind::Stratified, SamplerKind::Sobol] {
+      let mut cells = [0; 16];
+      for (x, y) in pixel_samples(kind.create(16, 0).as_mut(), 16) {
+        assert!((0.0..1.0).contains(&x) && (0.0..1.0).contains(&y));
+        cells[(y * 4.0) as usize * 4 + (x * 4.0) as usize] += 1;
+      }
+      assert_eq!(cells, [1; 16], "{:?}", kind);
+    }
+    // Sobol is also stratified in elementary intervals of other shapes (a (0,2)-net)
+    let mut cells = [0; 16];
+    for (x, y) in pixel_samples(SamplerKind::Sobol.create(16, 0).as_mut(), 16) {
+      cells[(y * 2.0) as usize * 8 + (x * 8.0) as usize] += 1;
+    }
+    assert_eq!(cells, [1; 16]);
+  }
+
+  #[test]
+  fn test_halton() {
+    assert_eq!(radical_inverse(2, 1), 0.5);
+    assert_eq!(radical_inverse(2, 6), 0.375);
+    assert!((radical_inverse(3, 5) - 7.0 / 9.0).abs() < 1e-12);
+
+    // bases 2 and 3 in the first two dimensions: 6 samples split evenly into halves and thirds
+    let mut sampler = SamplerKind::Halton.create(6, 0);
+    let (mut halves, mut thirds) = ([0; 2], [0; 3]);
+    for k in 0..6 {
+      sampler.start_pixel_sample(3, 5, k);
+      let (x, y) = sampler.get_2d();
+      halves[(x * 2.0) as usize] += 1;
+      thirds[(y * 3.0) as usize] += 1;
+    }
+    assert_eq!((halves, thirds), ([3; 2], [2; 3]));
+  }
+
+  #[test]
+  fn test_convergence() {
+    // integral of a smooth function over [0,1)^2 from 64 samples; low-discrepancy beats white noise
+    let f = |(x, y): (f64, f64)| (x * PI).sin() * (y * PI).sin() * PI * PI / 4.0; // integrates to 1
+    let mut errors = Vec::new();
+    for kind in [SamplerKind::Independent, SamplerKind::Stratified, SamplerKind::Halton, SamplerKind::Sobol] {
+      let mut squared_error = 0.0;
+      for pixel in 0..64 {
+        let mut sampler = kind.create(64, 0);
+        let mut sum = 0.0;
+        for k in 0..64 {
+          sampler.start_pixel_sample(pixel, 0, k);
+          sum += f(sampler.get_2d());
+        }
+        squared_error += (sum / 64.0 - 1.0).powi(2);
+      }
+      errors.push(squared_error / 64.0);
+    }
+    for k in 1..errors.len() {
+      assert!(errors[k] * 4.0 < errors[0], "{:?}", errors);
+    }
+  }
+}
@@ -0,0 +1,692 @@
+// Scene description files (TOML)
+//
+// A scene file describes the camera, named textures and materials, and a list of objects:
+//
+//   [camera]
+//   aspect_ratio = 1.0
+//   lookfrom = [278.0, 278.0, -800.0]
+//   lookat = [278.0, 278.0, 0.0]
+//
+//   [materials.white]
+//   type = "lambertian"
+//   albedo = [0.73, 0.73, 0.73]
+//
+//   [[objects]]
+//   type = "quad"
+//   q = [0.0, 0.0, 0.0]
+//   u = [555.0, 0.0, 0.0]
+//   v = [0.0, 0.0, 555.0]
+//   material = "white"
+//
+// Wherever a texture is expected (`albedo`, `emit`, `even`, `odd`, `roughness`) either an RGB triple, a single
+// number (gray) or the name of an entry in [textures] may be given. Image paths are relative to the working directory,
+// the same as the hardcoded scenes in main.rs. Images are sRGB decoded unless `linear = true` is set
+// (for non-color data such as normal or height maps). See scenes/*.toml for complete examples.
+//
+// [camera] may also choose how direct light is sampled: `light_sampling = "bsdf" | "light" | "mis"` and, for mis,
+// `mis_heuristic = "balance" | "power"` (see LightSampling); the command line overrides both.
+//
+// Glass can be tinted by the distance light travels inside it, with `absorption = [r, g, b]` per unit of distance
+// or the `color_at_distance` white light takes after `at_distance`; for overlapping media (a glass of water) the
+// one with the higher `priority` wins (see interior.rs).
+//
+// `spectral = true` in [camera] (or --spectral) traces wavelengths instead of RGB; dielectrics then disperse light
+// when given `dispersion = "bk7" | "fused_silica" | "sf11"` or `cauchy = [a, b]` in place of `refraction_index`.
+
+use crate::vec3::{*};
+use crate::color::{*};
+use crate::hittable::{*};
+use crate::material::{*};
+use crate::texture::{*};
+use crate::sphere::{*};
+use crate::planar::{*};
+use crate::constant_medium::{*};
+use crate::camera_multithreading::{*};
+use crate::microfacet::{*};
+use crate::interior::{*};
+use crate::spectrum::{*};
+
+use serde::Deserialize;
+use toml::Spanned;
+use std::collections::BTreeMap;
+use std::ops::Range;
+use std::path::Path;
+
+pub struct Scene {
+  pub camera: Camera,
+  pub world: Object,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SceneDesc {
+  camera: CameraDesc,
+  #[serde(default)]
+  textures: BTreeMap<String, TextureDesc>,
+  #[serde(default)]
+  materials: BTreeMap<String, MaterialDesc>,
+  #[serde(default)]
+  objects: Vec<ObjectDesc>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CameraDesc {
+  aspect_ratio: Option<Spanned<f64>>,
+  image_width: Option<Spanned<usize>>,
+  #[serde(default = "default_sample_per_pixel")]
+  sample_per_pixel: usize,
+  #[serde(default = "default_max_ray_depth")]
+  max_ray_depth: usize,
+  #[serde(default = "default_vfov")]
+  vfov: f64,
+  lookfrom: [f64; 3],
+  lookat: [f64; 3],
+  #[serde(default = "default_vup")]
+  vup: [f64; 3],
+  #[serde(default)]
+  defocus_angle: f64,
+  #[serde(default = "default_focus_dist")]
+  focus_dist: f64,
+  #[serde(default = "default_background")]
+  background: [f64; 3],
+  light_sampling: Option<Spanned<String>>,
+  mis_heuristic: Option<Spanned<String>>,
+  #[serde(default)]
+  spectral: bool,
+}
+
+fn default_aspect_ratio() -> f64 { 16.0 / 9.0 }
+fn default_image_width() -> usize { 400 }
+fn default_sample_per_pixel() -> usize { 100 }
+fn default_max_ray_depth() -> usize { 50 }
+fn default_vfov() -> f64 { 20.0 }
+fn default_vup() -> [f64; 3] { [0.0, 1.0, 0.0] }
+fn default_focus_dist() -> f64 { 10.0 }
+fn default_background() -> [f64; 3] { [0.70, 0.80, 1.00] }
+
+// A texture slot accepts either a literal color or gray value, or the name of a [textures] entry.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum TextureRef {
+  Color([f64; 3]),
+  Gray(f64),
+  Name(String),
+}
+
+// The descriptions below are flat on purpose: serde's tagged enums buffer their content and
+// lose the source spans we need for error messages, so the `type` field is checked by hand.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TextureDesc {
+  #[serde(rename = "type")]
+  kind: Spanned<String>,
+  color: Option<[f64; 3]>, // solid
+  scale: Option<f64>, // checker, noise
+  even: Option<Spanned<TextureRef>>, // checker
+  odd: Option<Spanned<TextureRef>>,
+  path: Option<Spanned<String>>, // image
+  linear: Option<bool>, // image: non-color data (normal / height map), not sRGB decoded
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MaterialDesc {
+  #[serde(rename = "type")]
+  kind: Spanned<String>,
+  albedo: Option<Spanned<TextureRef>>, // lambertian, metal (color only), isotropic, lambertian_with_light
+  fuzz: Option<f64>, // metal
+  refraction_index: Option<f64>, // dielectric, rough_dielectric
+  dispersion: Option<Spanned<String>>, // dielectric, instead of refraction_index: a glass with Sellmeier coefficients
+  cauchy: Option<Spanned<[f64; 2]>>, // or Cauchy's a and b (um^2)
+  emit: Option<Spanned<TextureRef>>, // diffuse_light
+  light: Option<[f64; 3]>, // lambertian_with_light
+  metal: Option<Spanned<String>>, // conductor: a preset for eta and k
+  eta: Option<[f64; 3]>, // conductor, unless `metal` is given
+  k: Option<[f64; 3]>,
+  roughness: Option<Spanned<TextureRef>>, // conductor, rough_dielectric
+  roughness_v: Option<Spanned<TextureRef>>, // conductor, anisotropic; `roughness` is then along the tangent
+  distribution: Option<Spanned<String>>, // conductor, rough_dielectric: ggx (default) or beckmann
+  absorption: Option<Spanned<[f64; 3]>>, // dielectric, rough_dielectric: per unit of distance inside
+  color_at_distance: Option<Spanned<[f64; 3]>>, // or the color white light takes after `at_distance` (default 1) inside
+  at_distance: Option<Spanned<f64>>,
+  priority: Option<u32>, // dielectric, rough_dielectric: where media overlap, the highest priority wins
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ObjectDesc {
+  #[serde(rename = "type")]
+  kind: Spanned<String>,
+  material: Option<Spanned<String>>,
+  center: Option<[f64; 3]>, // sphere
+  center_after_move: Option<[f64; 3]>,
+  radius: Option<f64>,
+  q: Option<[f64; 3]>, // quad, triangle, circle, ring
+  u: Option<[f64; 3]>,
+  v: Option<[f64; 3]>,
+  min_ratio: Option<f64>, // ring
+  a: Option<[f64; 3]>, // box
+  b: Option<[f64; 3]>,
+  density: Option<f64>, // constant_medium
+  albedo: Option<Spanned<TextureRef>>,
+  offset: Option<[f64; 3]>, // translate
+  angle: Option<f64>, // rotate_y
+  object: Option<Box<ObjectDesc>>, // translate, rotate_y, constant_medium
+  objects: Option<Vec<ObjectDesc>>, // group
+}
+
+
+// Read and build a scene file. Errors carry the file name, line and column of the offending field.
+pub fn load_scene(path: &str) -> Result<Scene, String> {
+  let src = std::fs::read_to_string(path)
+    .map_err(|err| format!("{}: cannot read scene file: {}", path, err))?;
+  parse_scene(&src).map_err(|err| {
+    if err.starts_with(|c: char| c.is_ascii_digit()) { format!("{}:{}", path, err) } else { format!("{}: {}", path, err) }
+  })
+}
+
+pub fn parse_scene(src: &str) -> Result<Scene, String> {
+  let desc: SceneDesc = toml::from_str(src).map_err(|err| {
+    match err.span() {
+      Some(span) => format!("{}: {}", location(src, &span), err.message()),
+      None => err.message().to_string(),
+    }
+  })?;
+  SceneBuilder::new(src).build(desc)
+}
+
+// "line:column" (both 1-based) of the start of a span
+fn location(src: &str, span: &Range<usize>) -> String {
+  let before = &src[..span.start.min(src.len())];
+  let line = before.matches('\n').count() + 1;
+  let column = before.len() - before.rfind('\n').map_or(0, |idx| idx + 1) + 1;
+  format!("{}:{}", line, column)
+}
+
+fn to_vec3(v: [f64; 3]) -> Vec3 {
+  Vec3::new(v[0], v[1], v[2])
+}
+
+struct SceneBuilder<'a> {
+  src: &'a str,
+  textures: BTreeMap<String, Texture>,
+  materials: BTreeMap<String, Material>,
+}
+
+impl<'a> SceneBuilder<'a> {
+  fn new(src: &'a str) -> Self {
+    Self {
+      src,
+      textures: BTreeMap::new(),
+      materials: BTreeMap::new(),
+    }
+  }
+
+  fn error(&self, span: &Range<usize>, msg: String) -> String {
+    format!("{}: {}", location(self.src, span), msg)
+  }
+
+  fn require<T>(&self, field: Option<T>, name: &str, kind: &Spanned<String>) -> Result<T, String> {
+    field.ok_or_else(|| self.error(&kind.span(), format!("{} is missing field `{}`", kind.get_ref(), name)))
+  }
+
+  fn build(mut self, desc: SceneDesc) -> Result<Scene, String> {
+    // textures may refer to each other (checker), so resolve them in dependency order
+    let mut pending: Vec<(String, TextureDesc)> = desc.textures.into_iter().collect();
+    while !pending.is_empty() {
+      let before = pending.len();
+      let mut blocked = Vec::new();
+      for (name, tex) in pending {
+        if self.texture_ready(&tex) {
+          let texture = self.build_texture(&tex)?;
+          self.textures.insert(name, texture);
+        } else {
+          blocked.push((name, tex));
+        }
+      }
+      if blocked.len() == before {
+        let (name, tex) = &blocked[0];
+        return Err(self.error(&tex.kind.span(),
+          format!("texture `{}` refers to an unknown texture or forms a cycle", name)));
+      }
+      pending = blocked;
+    }
+
+    for (name, mat) in &desc.materials {
+      let material = self.build_material(mat)?;
+      self.materials.insert(name.clone(), material);
+    }
+
+    let mut world = HittableList::default();
+    for obj in &desc.objects {
+      world.add(self.build_object(obj)?);
+    }
+    if world.objects.is_empty() {
+      return Err("scene has no objects".to_string());
+    }
+
+    let cam = &desc.camera;
+    let mut camera = Camera::new(
+      cam.aspect_ratio.as_ref().map_or(default_aspect_ratio(), |ratio| *ratio.get_ref()),
+      cam.image_width.as_ref().map_or(default_image_width(), |width| *width.get_ref()),
+      cam.sample_per_pixel,
+      cam.max_ray_depth,
+      cam.vfov,
+      to_vec3(cam.lookfrom),
+      to_vec3(cam.lookat),
+      to_vec3(cam.vup),
+      cam.defocus_angle,
+      cam.focus_dist,
+      to_vec3(cam.background),
+    );
+    if camera.image_height() == 0 {
+      // one of them is set, the defaults make a 400 x 225 image
+      let span = cam.image_width.as_ref().map(|width| width.span()).unwrap_or_else(|| cam.aspect_ratio.as_ref().unwrap().span());
+      return Err(self.error(&span, format!("a {} pixel wide image at aspect ratio {} is less than a pixel high",
+        camera.image_width, camera.aspect_ratio)));
+    }
+    if let Some(name) = &cam.light_sampling {
+      camera.light_sampling = LightSampling::from_name(name.get_ref()).ok_or_else(|| self.error(&name.span(),
+        format!("unknown light sampling `{}` (expected bsdf, light or mis)", name.get_ref())))?;
+    }
+    if let Some(name) = &cam.mis_heuristic {
+      camera.mis_heuristic = MisHeuristic::from_name(name.get_ref()).ok_or_else(|| self.error(&name.span(),
+        format!("unknown MIS heuristic `{}` (expected balance or power)", name.get_ref())))?;
+    }
+    camera.spectral = cam.spectral;
+
+    Ok(Scene {
+      camera,
+      world: world.to_bvh(),
+    })
+  }
+
+  fn texture_ready(&self, tex: &TextureDesc) -> bool {
+    [&tex.even, &tex.odd].iter().all(|slot| match slot {
+      Some(spanned) => match spanned.get_ref() {
+        TextureRef::Name(name) => self.textures.contains_key(name),
+        TextureRef::Color(_) | TextureRef::Gray(_) => true,
+      },
+      None => true,
+    })
+  }
+
+  fn texture_ref(&self, slot: &Spanned<TextureRef>) -> Result<Texture, String> {
+    match slot.get_ref() {
+      TextureRef::Color(c) => Ok(SolidColor::new(to_vec3(*c)).to_texture()),
+      TextureRef::Gray(g) => Ok(SolidColor::new(ColorType::ones() * *g).to_texture()),
+      TextureRef::Name(name) => self.textures.get(name).cloned()
+        .ok_or_else(|| self.error(&slot.span(), format!("unknown texture `{}`", name))),
+    }
+  }
+
+  fn build_texture(&self, tex: &TextureDesc) -> Result<Texture, String> {
+    let kind = &tex.kind;
+    match kind.get_ref().as_str() {
+      "solid" => {
+        let color = self.require(tex.color, "color", kind)?;
+        Ok(SolidColor::new(to_vec3(color)).to_texture())
+      }
+      "checker" => {
+        let scale = self.require(tex.scale, "scale", kind)?;
+        let even = self.texture_ref(self.require(tex.even.as_ref(), "even", kind)?)?;
+        let odd = self.texture_ref(self.require(tex.odd.as_ref(), "odd", kind)?)?;
+        Ok(CheckerTexture::new(scale, even, odd).to_texture())
+      }
+      "image" => {
+        let path = self.require(tex.path.as_ref(), "path", kind)?;
+        if !Path::new(path.get_ref()).is_file() {
+          return Err(self.error(&path.span(), format!("image `{}` not found", path.get_ref())));
+        }
+        if tex.linear.unwrap_or(false) {
+          Ok(ImageTexture::new_linear(path.get_ref()).to_texture())
+        } else {
+          Ok(ImageTexture::new(path.get_ref()).to_texture())
+        }
+      }
+      "noise" => {
+        let scale = self.require(tex.scale, "scale", kind)?;
+        Ok(NoiseTexture::new(scale).to_texture())
+      }
+      other => Err(self.error(&kind.span(),
+        format!("unknown texture type `{}` (expected solid, checker, image or noise)", other))),
+    }
+  }
+
+  fn build_material(&self, mat: &MaterialDesc) -> Result<Material, String> {
+    let kind = &mat.kind;
+    match kind.get_ref().as_str() {
+      "lambertian" => {
+        let albedo = self.texture_ref(self.require(mat.albedo.as_ref(), "albedo", kind)?)?;
+        Ok(Lambertian::new(albedo).to_material())
+      }
+      "metal" => {
+        let albedo = self.require(mat.albedo.as_ref(), "albedo", kind)?;
+        let albedo = match albedo.get_ref() {
+          TextureRef::Color(c) => to_vec3(*c),
+          TextureRef::Gray(g) => ColorType::ones() * *g,
+          TextureRef::Name(_) => return Err(self.error(&albedo.span(), "metal albedo must be a color".to_string())),
+        };
+        Ok(Metal::new(albedo, mat.fuzz.unwrap_or(0.0)).to_material())
+      }
+      "dielectric" => {
+        let dielectric = match self.dispersion(mat)? {
+          Some(dispersion) => Dielectric::new_dispersive(dispersion),
+          None => Dielectric::new(self.require(mat.refraction_index, "refraction_index", kind)?),
+        };
+        Ok(dielectric
+          .with_absorption(self.absorption(mat)?)
+          .with_priority(mat.priority.unwrap_or(0))
+          .to_material())
+      }
+      "diffuse_light" => {
+        let emit = self.texture_ref(self.require(mat.emit.as_ref(), "emit", kind)?)?;
+        Ok(DiffuseLight::new(emit).to_material())
+      }
+      "isotropic" => {
+        let albedo = self.texture_ref(self.require(mat.albedo.as_ref(), "albedo", kind)?)?;
+        Ok(Isotropic::new(albedo).to_material())
+      }
+      "conductor" => {
+        let (eta, k) = match &mat.metal {
+          Some(metal) => conductor_ior(metal.get_ref()).ok_or_else(|| self.error(&metal.span(), format!(
+            "unknown metal `{}` (expected {})", metal.get_ref(), CONDUCTOR_NAMES.join(", "))))?,
+          None => (to_vec3(self.require(mat.eta, "eta", kind)?), to_vec3(self.require(mat.k, "k", kind)?)),
+        };
+        let roughness = self.texture_ref(self.require(mat.roughness.as_ref(), "roughness", kind)?)?;
+        let roughness_v = match &mat.roughness_v {
+          Some(slot) => self.texture_ref(slot)?,
+          None => roughness.clone(),
+        };
+        Ok(Conductor::new(self.distribution(mat)?, eta, k, roughness, roughness_v).to_material())
+      }
+      "rough_dielectric" => {
+        let refraction_index = self.require(mat.refraction_index, "refraction_index", kind)?;
+        let roughness = self.texture_ref(self.require(mat.roughness.as_ref(), "roughness", kind)?)?;
+        Ok(RoughDielectric::new(self.distribution(mat)?, refraction_index, roughness)
+          .with_absorption(self.absorption(mat)?)
+          .with_priority(mat.priority.unwrap_or(0))
+          .to_material())
+      }
+      "lambertian_with_light" => {
+        let albedo = self.texture_ref(self.require(mat.albedo.as_ref(), "albedo", kind)?)?;
+        let light = self.require(mat.light, "light", kind)?;
+        Ok(LambertianWithLight::new(albedo, to_vec3(light)).to_material())
+      }
+      other => Err(self.error(&kind.span(), format!(
+        "unknown material type `{}` (expected lambertian, metal, conductor, dielectric, rough_dielectric, diffuse_light, isotropic or lambertian_with_light)",
+        other))),
+    }
+  }
+
+  fn dispersion(&self, mat: &MaterialDesc) -> Result<Option<Dispersion>, String> {
+    let dispersion = match (&mat.dispersion, &mat.cauchy) {
+      (Some(_), Some(cauchy)) => return Err(self.error(&cauchy.span(), "give either `dispersion` or `cauchy`, not both".to_string())),
+      (Some(name), None) => Dispersion::from_name(name.get_ref()).ok_or_else(|| self.error(&name.span(), format!(
+        "unknown dispersion `{}` (expected {})", name.get_ref(), DISPERSION_NAMES.join(", "))))?,
+      (None, Some(cauchy)) => {
+        let [a, b] = *cauchy.get_ref();
+        Dispersion::Cauchy { a, b }
+      }
+      (None, None) => return Ok(None),
+    };
+    if mat.refraction_index.is_some() {
+      let span = mat.dispersion.as_ref().map_or_else(|| mat.cauchy.as_ref().unwrap().span(), |name| name.span());
+      return Err(self.error(&span, "a dispersion model gives the refraction index, leave out `refraction_index`".to_string()));
+    }
+    Ok(Some(dispersion))
+  }
+
+  fn absorption(&self, mat: &MaterialDesc) -> Result<ColorType, String> {
+    match (&mat.absorption, &mat.color_at_distance) {
+      (Some(_), Some(color)) => Err(self.error(&color.span(), "give either `absorption` or `color_at_distance`, not both".to_string())),
+      (Some(absorption), None) => {
+        // negative absorption would amplify light
+        if !absorption.get_ref().iter().all(|a| a.is_finite() && *a >= 0.0) {
+          return Err(self.error(&absorption.span(), "`absorption` must be finite and not negative".to_string()));
+        }
+        Ok(to_vec3(*absorption.get_ref()))
+      }
+      (None, Some(color)) => {
+        let distance = match &mat.at_distance {
+          Some(distance) if !(*distance.get_ref() > 0.0 && distance.get_ref().is_finite()) =>
+            return Err(self.error(&distance.span(), "`at_distance` must be a positive distance".to_string())),
+          Some(distance) => *distance.get_ref(),
+          None => 1.0,
+        };
+        Ok(absorption_at_distance(to_vec3(*color.get_ref()), distance))
+      }
+      (None, None) => Ok(ColorType::zero()),
+    }
+  }
+
+  fn distribution(&self, mat: &MaterialDesc) -> Result<MicrofacetKind, String> {
+    match &mat.distribution {
+      Some(name) => MicrofacetKind::from_name(name.get_ref()).ok_or_else(|| self.error(&name.span(),
+        format!("unknown distribution `{}` (expected ggx or beckmann)", name.get_ref()))),
+      None => Ok(MicrofacetKind::Ggx),
+    }
+  }
+
+  fn material(&self, obj: &ObjectDesc) -> Result<Material, String> {
+    let name = self.require(obj.material.as_ref(), "material", &obj.kind)?;
+    self.materials.get(name.get_ref()).cloned()
+      .ok_or_else(|| self.error(&name.span(), format!("unknown material `{}`", name.get_ref())))
+  }
+
+  fn inner_object(&self, obj: &ObjectDesc) -> Result<Object, String> {
+    let inner = self.require(obj.object.as_ref(), "object", &obj.kind)?;
+    self.build_object(inner)
+  }
+
+  fn build_object(&self, obj: &ObjectDesc) -> Result<Object, String> {
+    let kind = &obj.kind;
+    match kind.get_ref().as_str() {
+      "sphere" => {
+        let center = to_vec3(self.require(obj.center, "center", kind)?);
+        let radius = self.require(obj.radius, "radius", kind)?;
+        let mat = self.material(obj)?;
+        Ok(match obj.center_after_move {
+          Some(after) => Sphere::new_moving(center, to_vec3(after), radius, mat).to_object(),
+          None => Sphere::new_static(center, radius, mat).to_object(),
+        })
+      }
+      "quad" | "triangle" | "circle" | "ring" => {
+        let q = to_vec3(self.require(obj.q, "q", kind)?);
+        let u = to_vec3(self.require(obj.u, "u", kind)?);
+        let v = to_vec3(self.require(obj.v, "v", kind)?);
+        if u.cross(&v).near_zero() {
+          return Err(self.error(&kind.span(), format!("{} has parallel or zero `u` and `v`", kind.get_ref())));
+        }
+        let mat = self.material(obj)?;
+        Ok(match kind.get_ref().as_str() {
+          "quad" => Quad::new(q, u, v, mat).to_object(),
+          "triangle" => Triangle::new(q, u, v, mat).to_object(),
+          "circle" => Circle::new(q, u, v, mat).to_object(),
+          _ => {
+            let min_ratio = self.require(obj.min_ratio, "min_ratio", kind)?;
+            Ring::new(q, u, v, mat, min_ratio).to_object()
+          }
+        })
+      }
+      "box" => {
+        let a = to_vec3(self.require(obj.a, "a", kind)?);
+        let b = to_vec3(self.require(obj.b, "b", kind)?);
+        Ok(build_box(a, b, self.material(obj)?).to_object())
+      }
+      "constant_medium" => {
+        let density = self.require(obj.density, "density", kind)?;
+        let albedo = self.texture_ref(self.require(obj.albedo.as_ref(), "albedo", kind)?)?;
+        Ok(ConstantMedium::new(self.inner_object(obj)?, density, albedo).to_object())
+      }
+      "translate" => {
+        let offset = to_vec3(self.require(obj.offset, "offset", kind)?);
+        Ok(Translate::new(self.inner_object(obj)?, offset).to_object())
+      }
+      "rotate_y" => {
+        let angle = self.require(obj.angle, "angle", kind)?;
+        Ok(RotateY::new(self.inner_object(obj)?, angle).to_object())
+      }
+      "group" => {
+        let objects = self.require(obj.objects.as_ref(), "objects", kind)?;
+        if objects.is_empty() {
+          return Err(self.error(&kind.span(), "group has no objects".to_string()));
+        }
+        let mut group = HittableList::default();
+        for inner in objects {
+          group.add(self.build_object(inner)?);
+        }
+        Ok(group.to_bvh())
+      }
+      other => Err(self.error(&kind.span(), format!(
+        "unknown object type `{}` (expected sphere, quad, triangle, circle, ring, box, constant_medium, translate, rotate_y or group)",
+        other))),
+    }
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use crate::*;
+
+  const CAMERA: &str = "
+[camera]
+lookfrom = [0.0, 0.0, 10.0]
+lookat = [0.0, 0.0, 0.0]
+";
+
+  #[test]
+  fn test_parse_scene() {
+    let src = format!("{}{}", CAMERA, r#"
+[textures.checker]
+type = "checker"
+scale = 0.5
+even = [0.2, 0.3, 0.1]
+odd = "white"
+
+[textures.white]
+type = "solid"
+color = [0.9, 0.9, 0.9]
+
+[materials.ground]
+type = "lambertian"
+albedo = "checker"
+
+[[objects]]
+type = "translate"
+offset = [1.0, 0.0, 0.0]
+object = { type = "sphere", center = [0.0, 0.0, 0.0], radius = 1.0, material = "ground" }
+"#);
+    let scene = parse_scene(&src).unwrap();
+    assert_eq!(scene.camera.image_width, 400);
+    assert_eq!(scene.camera.light_sampling, LightSampling::Mis);
+
+    let ray = Ray::new(Point3::new(1.0, 0.0, 10.0), Vec3::new(0.0, 0.0, -1.0), 0.0);
+    let mut rec = HitRecord::default();
+    assert!(scene.world.hit(&ray, Interval::new(0.0, INFINITY), &mut rec));
+    assert!((rec.t - 9.0).abs() < 1e-9);
+  }
+
+  #[test]
+  fn test_error_points_at_line() {
+    let src = format!("{}{}", CAMERA, r#"
+[[objects]]
+type = "sphere"
+center = [0.0, 0.0, 0.0]
+radius = 1.0
+material = "missing"
+"#);
+    let err = parse_scene(&src).err().unwrap();
+    assert!(err.starts_with("10:12: unknown material `missing`"), "{}", err);
+
+    let src = format!("{}{}", CAMERA, r#"
+[[objects]]
+type = "sphere"
+centre = [0.0, 0.0, 0.0]
+"#);
+    let err = parse_scene(&src).err().unwrap();
+    assert!(err.starts_with("8:1:"), "{}", err);
+    assert!(err.contains("centre"), "{}", err);
+
+    let src = format!("{}{}", CAMERA, r#"light_sampling = "nee"
+
+[materials.white]
+type = "lambertian"
+albedo = [0.5, 0.5, 0.5]
+
+[[objects]]
+type = "sphere"
+center = [0.0, 0.0, 0.0]
+radius = 1.0
+material = "white"
+"#);
+    let err = parse_scene(&src).err().unwrap();
+    assert!(err.starts_with("5:18: unknown light sampling `nee`"), "{}", err);
+
+    let src = format!("{}{}", CAMERA, r#"
+[materials.glass]
+type = "dielectric"
+refraction_index = 1.5
+absorption = [0.1, 0.1, 0.1]
+color_at_distance = [0.9, 0.5, 0.5]
+
+[[objects]]
+type = "sphere"
+center = [0.0, 0.0, 0.0]
+radius = 1.0
+material = "glass"
+"#);
+    let err = parse_scene(&src).err().unwrap();
+    assert!(err.starts_with("10:21: give either `absorption` or `color_at_distance`"), "{}", err);
+
+    for (line, expected) in [
+      ("absorption = [0.1, -0.2, 0.1]", "9:14: `absorption` must be finite and not negative"),
+      ("color_at_distance = [0.9, 0.5, 0.5]\nat_distance = 0.0", "10:15: `at_distance` must be a positive distance"),
+      ("color_at_distance = [0.9, 0.5, 0.5]\nat_distance = -2.0", "10:15: `at_distance` must be a positive distance"),
+    ] {
+      let src = format!("{}{}{}{}", CAMERA, r#"
+[materials.glass]
+type = "dielectric"
+refraction_index = 1.5
+"#, line, r#"
+
+[[objects]]
+type = "sphere"
+center = [0.0, 0.0, 0.0]
+radius = 1.0
+material = "glass"
+"#);
+      let err = parse_scene(&src).err().unwrap();
+      assert!(err.starts_with(expected), "{}", err);
+    }
+
+    for (line, expected) in [("image_width = 1", "5:15: a 1 pixel wide image"), ("aspect_ratio = 500.0", "5:16: a 400 pixel wide image")] {
+      let src = format!("{}{}{}", CAMERA, line, r#"
+
+[materials.white]
+type = "lambertian"
+albedo = [0.5, 0.5, 0.5]
+
+[[objects]]
+type = "sphere"
+center = [0.0, 0.0, 0.0]
+radius = 1.0
+material = "white"
+"#);
+      let err = parse_scene(&src).err().unwrap();
+      assert!(err.starts_with(expected) && err.contains("less than a pixel high"), "{}", err);
+    }
+  }
+
+  #[test]
+  fn test_example_scenes() {
+    for entry in std::fs::read_dir("scenes").unwrap() {
+      let path = entry.unwrap().path();
+      if let Err(err) = load_scene(path.to_str().unwrap()) {
+        panic!("{}", err);
+      }
+    }
+  }
+}
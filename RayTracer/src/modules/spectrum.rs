@@ -0,0 +1,195 @@
+// Spectral rendering
+// In spectral mode each camera path carries three wavelengths instead of red, green and blue: a hero wavelength
+// sampled uniformly over the visible range and two more at equal offsets, wrapping around it (Wilkie et al. 2014,
+// "Hero Wavelength Spectral Sampling"). The three values of a ColorType along the path are then the radiance or
+// throughput at those wavelengths. RGB reflectances and emissions are upsampled to spectra where they enter the
+// path, and the estimate is turned into XYZ with the CIE matching functions and into linear sRGB at the film.
+// A dispersive material sends each wavelength its own way, and so does any refracting surface inside one; there the
+// path keeps the hero wavelength only, and the others are dropped (their share goes to the hero, which keeps the
+// estimate unbiased).
+
+use crate::color::{*};
+
+pub const LAMBDA_MIN: f64 = 380.0; // nm
+pub const LAMBDA_MAX: f64 = 780.0;
+const LAMBDA_D: f64 = 587.56; // helium d-line, where a glass's nominal index is quoted
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Wavelengths {
+  pub lambda: [f64; 3], // nm; lambda[0] is the hero
+}
+
+impl Wavelengths {
+  // `u`: uniform in [0, 1)
+  pub fn sample(u: f64) -> Self {
+    let at = |offset: f64| LAMBDA_MIN + ((u + offset) % 1.0) * (LAMBDA_MAX - LAMBDA_MIN);
+    Self { lambda: [at(0.0), at(1.0 / 3.0), at(2.0 / 3.0)] }
+  }
+
+  // density of each wavelength
+  pub fn pdf(&self) -> f64 {
+    1.0 / (LAMBDA_MAX - LAMBDA_MIN)
+  }
+
+  // the spectrum of an RGB reflectance or emission at these wavelengths; linear in `rgb` scaled by a positive factor,
+  // so products of upsampled values may be taken in either order
+  pub fn upsample(&self, rgb: ColorType) -> ColorType {
+    ColorType::new(smits(rgb, self.lambda[0]), smits(rgb, self.lambda[1]), smits(rgb, self.lambda[2]))
+  }
+
+  // linear sRGB of a path's estimate at these wavelengths
+  pub fn to_rgb(&self, values: ColorType) -> ColorType {
+    let mut xyz = ColorType::zero();
+    for (lambda, value) in self.lambda.iter().zip([values.x, values.y, values.z]) {
+      xyz += cie_xyz(*lambda) * value;
+    }
+    let xyz = xyz / (3.0 * self.pdf());
+    let rgb = xyz_to_linear_srgb(xyz);
+    let white = film_white();
+    ColorType::new(rgb.x / white.x, rgb.y / white.y, rgb.z / white.z)
+  }
+}
+
+// CIE 1931 colour matching functions, the piecewise Gaussian fit of Wyman, Sloan and Shirley 2013,
+// "Simple Analytic Approximations to the CIE XYZ Color Matching Functions"
+pub fn cie_xyz(lambda: f64) -> ColorType {
+  let g = |mu: f64, sigma_below: f64, sigma_above: f64| {
+    let t = (lambda - mu) / if lambda < mu { sigma_below } else { sigma_above };
+    (-0.5 * t * t).exp()
+  };
+  ColorType::new(
+    1.056 * g(599.8, 37.9, 31.0) + 0.362 * g(442.0, 16.0, 26.7) - 0.065 * g(501.1, 20.4, 26.2),
+    0.821 * g(568.8, 46.9, 40.5) + 0.286 * g(530.9, 16.3, 31.1),
+    1.217 * g(437.0, 11.8, 36.0) + 0.681 * g(459.0, 26.0, 13.8),
+  )
+}
+
+fn xyz_to_linear_srgb(xyz: ColorType) -> ColorType {
+  ColorType::new(
+    3.2404542 * xyz.x - 1.5371385 * xyz.y - 0.4985314 * xyz.z,
+    -0.9692660 * xyz.x + 1.8760108 * xyz.y + 0.0415560 * xyz.z,
+    0.0556434 * xyz.x - 0.2040259 * xyz.y + 1.0572252 * xyz.z,
+  )
+}
+
+// sRGB of the constant spectrum 1 over the visible range; dividing by it keeps white surfaces under white light white
+fn film_white() -> ColorType {
+  static WHITE: std::sync::OnceLock<ColorType> = std::sync::OnceLock::new();
+  *WHITE.get_or_init(|| {
+    let steps = 4000;
+    let step = (LAMBDA_MAX - LAMBDA_MIN) / steps as f64;
+    let mut xyz = ColorType::zero();
+    for k in 0..steps {
+      xyz += cie_xyz(LAMBDA_MIN + (k as f64 + 0.5) * step) * step;
+    }
+    xyz_to_linear_srgb(xyz)
+  })
+}
+
+// Smits 1999, "An RGB-to-Spectrum Conversion for Reflectances": white plus the spectra of the two primaries or
+// secondaries that make up the rest, tabulated in 10 bins over 380-720 nm
+const SMITS_WHITE: [f64; 10] = [1.0000, 1.0000, 0.9999, 0.9993, 0.9992, 0.9998, 1.0000, 1.0000, 1.0000, 1.0000];
+const SMITS_CYAN: [f64; 10] = [0.9710, 0.9426, 1.0007, 1.0007, 1.0007, 1.0007, 0.1564, 0.0000, 0.0000, 0.0000];
+const SMITS_MAGENTA: [f64; 10] = [1.0000, 1.0000, 0.9685, 0.2229, 0.0000, 0.0458, 0.8369, 1.0000, 1.0000, 0.9959];
+const SMITS_YELLOW: [f64; 10] = [0.0001, 0.0000, 0.1088, 0.6651, 1.0000, 1.0000, 0.9996, 0.9586, 0.9685, 0.9840];
+const SMITS_RED: [f64; 10] = [0.1012, 0.0515, 0.0000, 0.0000, 0.0000, 0.0000, 0.8325, 1.0149, 1.0149, 1.0149];
+const SMITS_GREEN: [f64; 10] = [0.0000, 0.0000, 0.0273, 0.7937, 1.0000, 0.9418, 0.1719, 0.0000, 0.0000, 0.0025];
+const SMITS_BLUE: [f64; 10] = [1.0000, 1.0000, 0.8916, 0.3323, 0.0000, 0.0000, 0.0003, 0.0369, 0.0483, 0.0496];
+
+fn smits(rgb: ColorType, lambda: f64) -> f64 {
+  // linear between the bin centres, constant beyond the first and last
+  let x = ((lambda - 380.0) / 34.0 - 0.5).clamp(0.0, 9.0);
+  let k = (x as usize).min(8);
+  let t = x - k as f64;
+  let at = |table: &[f64; 10]| table[k] * (1.0 - t) + table[k + 1] * t;
+  let (r, g, b) = (rgb.x, rgb.y, rgb.z);
+  if r <= g && r <= b {
+    r * at(&SMITS_WHITE) + if g <= b {
+      (g - r) * at(&SMITS_CYAN) + (b - g) * at(&SMITS_BLUE)
+    } else {
+      (b - r) * at(&SMITS_CYAN) + (g - b) * at(&SMITS_GREEN)
+    }
+  } else if g <= r && g <= b {
+    g * at(&SMITS_WHITE) + if r <= b {
+      (r - g) * at(&SMITS_MAGENTA) + (b - r) * at(&SMITS_BLUE)
+    } else {
+      (b - g) * at(&SMITS_MAGENTA) + (r - b) * at(&SMITS_RED)
+    }
+  } else {
+    b * at(&SMITS_WHITE) + if r <= g {
+      (r - b) * at(&SMITS_YELLOW) + (g - r) * at(&SMITS_GREEN)
+    } else {
+      (g - b) * at(&SMITS_YELLOW) + (r - g) * at(&SMITS_RED)
+    }
+  }
+}
+
+// Index of refraction as a function of wavelength, with wavelengths in micrometres in the formulas
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Dispersion {
+  Cauchy { a: f64, b: f64 }, // n = a + b / lambda^2
+  Sellmeier { b: [f64; 3], c: [f64; 3] }, // n^2 = 1 + sum b_i lambda^2 / (lambda^2 - c_i)
+}
+
+pub const DISPERSION_NAMES: [&str; 3] = ["bk7", "fused_silica", "sf11"];
+
+impl Dispersion {
+  // Sellmeier coefficients of common glasses (Schott and Malitson data)
+  pub fn from_name(name: &str) -> Option<Self> {
+    match name {
+      "bk7" => Some(Self::Sellmeier { b: [1.03961212, 0.231792344, 1.01046945], c: [0.00600069867, 0.0200179144, 103.560653] }),
+      "fused_silica" => Some(Self::Sellmeier { b: [0.6961663, 0.4079426, 0.8974794], c: [0.00467914826, 0.0135120631, 97.9340025] }),
+      "sf11" => Some(Self::Sellmeier { b: [1.73759695, 0.313747346, 1.89878101], c: [0.013188707, 0.0623068142, 155.23629] }),
+      _ => None,
+    }
+  }
+
+  // at `lambda` nm
+  pub fn ior(&self, lambda: f64) -> f64 {
+    let l2 = (lambda / 1000.0).powi(2);
+    match *self {
+      Self::Cauchy { a, b } => a + b / l2,
+      Self::Sellmeier { b, c } => (1.0 + (0..3).map(|i| b[i] * l2 / (l2 - c[i])).sum::<f64>()).sqrt(),
+    }
+  }
+
+  // the index quoted for the glass, used when rendering in RGB
+  pub fn nominal_ior(&self) -> f64 {
+    self.ior(LAMBDA_D)
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use crate::*;
+
+  // the film's colour of an upsampled RGB value, integrated over stratified wavelengths
+  fn round_trip(rgb: ColorType) -> ColorType {
+    let n = 3000;
+    (0..n).map(|k| {
+      let wavelengths = Wavelengths::sample((k as f64 + 0.5) / n as f64);
+      wavelengths.to_rgb(wavelengths.upsample(rgb))
+    }).fold(ColorType::zero(), |a, b| a + b) / n as f64
+  }
+
+  #[test]
+  fn test_spectrum() {
+    let white = round_trip(ColorType::ones() * 0.5);
+    assert!((white - ColorType::ones() * 0.5).norm() < 1e-3, "{:?}", white);
+    for rgb in [ColorType::new(0.8, 0.3, 0.1), ColorType::new(0.2, 0.6, 0.3), ColorType::new(0.1, 0.2, 0.7)] {
+      let back = round_trip(rgb);
+      assert!((back - rgb).norm() < 0.03, "{:?} -> {:?}", rgb, back);
+    }
+
+    let wavelengths = Wavelengths::sample(0.9);
+    assert!(wavelengths.lambda.iter().all(|l| (LAMBDA_MIN..LAMBDA_MAX).contains(l)));
+    assert!(wavelengths.upsample(ColorType::new(2.0, 1.0, 0.0)) == wavelengths.upsample(ColorType::new(1.0, 0.5, 0.0)) * 2.0);
+
+    // BK7: n_d = 1.5168, more at the blue end
+    let bk7 = Dispersion::from_name("bk7").unwrap();
+    assert!((bk7.nominal_ior() - 1.5168).abs() < 1e-4);
+    assert!(bk7.ior(400.0) > bk7.ior(700.0));
+    assert!((Dispersion::Cauchy { a: 1.5, b: 0.01 }.ior(500.0) - 1.54).abs() < 1e-12);
+  }
+}
@@ -1,4 +1,4 @@
-use crate::{Material, PI};
+use crate::{Material, PI, EPS, INFINITY};
 
 use crate::vec3::{*};
 use crate::ray::{*};
@@ -6,6 +6,8 @@ use crate::hittable::{*};
 use crate::interval::{*};
 use crate::material::{*};
 use crate::aabb::{*};
+use crate::sampler::{*};
+use crate::onb::{*};
 
 use std::sync::Arc;
 
@@ -13,6 +15,7 @@ pub struct Sphere {
   pub center: Point3,
   pub radius: f64,
   pub mat: Material,
+  ids: HitIds,
   is_moving: bool,
   center_vec: Vec3,
   bbox: Aabb,
@@ -31,6 +34,7 @@ impl Sphere {
     Sphere {
       center,
       radius,
+      ids: HitIds::new(&mat),
       mat,
       is_moving,
       center_vec,
@@ -62,6 +66,12 @@ impl Sphere {
 
     (u, v)
   }
+
+  // Lights are sampled over the cone of directions the sphere covers from `origin`; from inside (or on the
+  // surface, where the cone opens to a hemisphere) uniformly over its area instead.
+  fn sees_cone_from(&self, origin: Point3) -> bool {
+    (self.center - origin).norm_squared() > self.radius * self.radius * (1.0 + EPS)
+  }
 }
 
 impl Hittable for Sphere {
@@ -95,6 +105,7 @@ impl Hittable for Sphere {
     let outward_normal = (ray.at(root) - center).normalize();
     let (u, v) = Self::get_spherer_uv(outward_normal);
     *rec = HitRecord::new_from_ray_and_outward_normal(ray, outward_normal, self.mat.clone(), root, u, v);
+    rec.ids = self.ids;
 
     true
   }
@@ -104,5 +115,37 @@ impl Hittable for Sphere {
   fn bounding_box(&self) -> Aabb {
     self.bbox
   }
+
+  fn pdf_value(&self, origin: Point3, direction: Vec3) -> f64 {
+    let mut rec = HitRecord::default();
+    if !self.hit(&Ray::new(origin, direction, 0.0), Interval::new(EPS, INFINITY), &mut rec) {
+      return 0.0;
+    }
+    if self.sees_cone_from(origin) {
+      let cos_theta_max = (1.0 - self.radius * self.radius / (self.center - origin).norm_squared()).sqrt();
+      1.0 / (2.0 * PI * (1.0 - cos_theta_max))
+    } else {
+      let distance_squared = rec.t * rec.t * direction.norm_squared();
+      let cosine = (direction.dot(&rec.normal) / direction.norm()).abs();
+      distance_squared / (cosine * 4.0 * PI * self.radius * self.radius)
+    }
+  }
+
+  fn random(&self, origin: Point3, u: (f64, f64)) -> Vec3 {
+    if !self.sees_cone_from(origin) {
+      return self.center + self.radius * sample_unit_sphere(u) - origin;
+    }
+    let to_center = self.center - origin;
+    let cos_theta_max = (1.0 - self.radius * self.radius / to_center.norm_squared()).sqrt();
+    let z = 1.0 + u.1 * (cos_theta_max - 1.0);
+    let phi = 2.0 * PI * u.0;
+    let sin_theta = (1.0 - z * z).max(0.0).sqrt();
+    Onb::new(to_center).local(Vec3::new(phi.cos() * sin_theta, phi.sin() * sin_theta, z))
+  }
+
+  // a moving sphere is not sampled; its light is still found by scattering
+  fn is_light(&self) -> bool {
+    !self.is_moving && self.mat.is_emissive()
+  }
 }
 
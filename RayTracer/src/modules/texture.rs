@@ -82,13 +82,24 @@ pub struct ImageTexture {
     pub img_data: opencv::core::Mat,
     pub width: usize,
     pub height: usize,
+    pub encoding: TransferFunction, // Srgb for color images, Linear for data (normal / height maps)
 }
 
 
 pub const bilinear_coloring: bool = true;
 
 impl ImageTexture {
+    // color image, stored sRGB encoded
     pub fn new(path: &str) -> Self {
+        Self::new_with_encoding(path, TransferFunction::Srgb)
+    }
+
+    // non-color data, used as is
+    pub fn new_linear(path: &str) -> Self {
+        Self::new_with_encoding(path, TransferFunction::Linear)
+    }
+
+    pub fn new_with_encoding(path: &str, encoding: TransferFunction) -> Self {
         let img_data = imread(path, IMREAD_COLOR).expect("ImageTexture: Image reading error!");
         let width = img_data.cols() as usize;
         let height = img_data.rows() as usize;
@@ -96,9 +107,16 @@ impl ImageTexture {
             img_data,
             width,
             height,
+            encoding,
         }
     }
 
+    // decoded texel (BGR in the Mat)
+    fn texel(&self, v: i32, u: i32) -> Vector3<f64> {
+        let color: Vector3<f64> = convert_U8VecN_to_Vector3f64(self.img_data.at_2d(v, u).unwrap()) / 255.0;
+        Vector3::new(self.encoding.decode(color[0]), self.encoding.decode(color[1]), self.encoding.decode(color[2]))
+    }
+
     pub fn get_color_bilinear(&self, mut u: f64, mut v: f64) -> ColorType {
         if u < 0.0 + EPS { u = 0.0; }
         if u > 1.0 - EPS { u = 1.0; }
@@ -113,23 +131,17 @@ impl ImageTexture {
         let u2 = (u1 + 1).min((self.width - 1) as i32);
         let v1 = v_img as i32;
         let v2 = (v1 + 1).min((self.height - 1) as i32);
-        let color11: Vector3<f64> = convert_U8VecN_to_Vector3f64(self.img_data.at_2d(v1, u1).unwrap());
-        let color12: Vector3<f64> = convert_U8VecN_to_Vector3f64(self.img_data.at_2d(v1, u2).unwrap());
-        let color21: Vector3<f64> = convert_U8VecN_to_Vector3f64(self.img_data.at_2d(v2, u1).unwrap());
-        let color22: Vector3<f64> = convert_U8VecN_to_Vector3f64(self.img_data.at_2d(v2, u2).unwrap());
+        // interpolate in linear space
+        let color11: Vector3<f64> = self.texel(v1, u1);
+        let color12: Vector3<f64> = self.texel(v1, u2);
+        let color21: Vector3<f64> = self.texel(v2, u1);
+        let color22: Vector3<f64> = self.texel(v2, u2);
 
         let color1: Vector3<f64> = (v2 as f64 - v_img) * color11 + (v_img - v1 as f64) * color21;
         let color2: Vector3<f64> = (v2 as f64 - v_img) * color12 + (v_img - v1 as f64) * color22;
         let color : Vector3<f64> = (u2 as f64 - u_img) * color1 + (u_img - u1 as f64) * color2;
 
-        let color_scale = 1.0 / 255.0;
-        let gamma_color = ColorType::new(
-          color_scale * color[2] as f64, 
-          color_scale * color[1] as f64, 
-          color_scale * color[0] as f64);
-
-        // correction from image gamma space to linear space
-        gamma_to_linear_ColorType(gamma_color)
+        ColorType::new(color[2], color[1], color[0])
     }
 }
 
@@ -0,0 +1,214 @@
+// Image tiles handed out to the render workers, and the order they are handed out in.
+// The order only changes which part of the image fills in first (and cache locality); the result is the same.
+// A Tile is also the pixel rectangle of a crop window.
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Tile {
+  pub x_min: usize,
+  pub x_max: usize, // exclusive
+  pub y_min: usize,
+  pub y_max: usize, // exclusive
+}
+
+impl Tile {
+  pub fn full(width: usize, height: usize) -> Self {
+    Self { x_min: 0, x_max: width, y_min: 0, y_max: height }
+  }
+
+  pub fn width(&self) -> usize {
+    self.x_max.saturating_sub(self.x_min)
+  }
+
+  pub fn height(&self) -> usize {
+    self.y_max.saturating_sub(self.y_min)
+  }
+
+  pub fn intersect(&self, other: &Tile) -> Tile {
+    Tile {
+      x_min: self.x_min.max(other.x_min),
+      x_max: self.x_max.min(other.x_max),
+      y_min: self.y_min.max(other.y_min),
+      y_max: self.y_max.min(other.y_max),
+    }
+  }
+}
+
+// Crop window as given on the command line, before the image size is known.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CropWindow {
+  Pixels(Tile),
+  Fraction { x_min: f64, y_min: f64, x_max: f64, y_max: f64 }, // of the image width and height
+}
+
+impl CropWindow {
+  // "x0,y0,x1,y1": whole numbers are pixels, anything with a decimal point is a fraction of the frame
+  pub fn parse(text: &str) -> Result<Self, String> {
+    let fields: Vec<&str> = text.split(',').map(|field| field.trim()).collect();
+    if fields.len() != 4 {
+      return Err(format!("expected x0,y0,x1,y1, got \"{}\"", text));
+    }
+    if fields.iter().any(|field| field.contains('.')) {
+      let mut v = [0.0; 4];
+      for (x, field) in v.iter_mut().zip(&fields) {
+        *x = field.parse::<f64>().ok().filter(|x| (0.0..=1.0).contains(x))
+          .ok_or_else(|| format!("expected a fraction in [0, 1], got \"{}\"", field))?;
+      }
+      Ok(Self::Fraction { x_min: v[0], y_min: v[1], x_max: v[2], y_max: v[3] })
+    } else {
+      let mut v = [0; 4];
+      for (x, field) in v.iter_mut().zip(&fields) {
+        *x = field.parse::<usize>().map_err(|_| format!("expected a pixel coordinate, got \"{}\"", field))?;
+      }
+      Ok(Self::Pixels(Tile { x_min: v[0], y_min: v[1], x_max: v[2], y_max: v[3] }))
+    }
+  }
+
+  // pixel rectangle in a width x height image, clipped to the image
+  pub fn resolve(&self, width: usize, height: usize) -> Result<Tile, String> {
+    let tile = match *self {
+      Self::Pixels(tile) => tile,
+      Self::Fraction { x_min, y_min, x_max, y_max } => {
+        let (w, h) = (width as f64, height as f64);
+        Tile {
+          x_min: (x_min * w).round() as usize,
+          x_max: (x_max * w).round() as usize,
+          y_min: (y_min * h).round() as usize,
+          y_max: (y_max * h).round() as usize,
+        }
+      }
+    }.intersect(&Tile::full(width, height));
+    if tile.width() == 0 || tile.height() == 0 {
+      return Err(format!("the crop window {:?} leaves nothing of the {}x{} image", self, width, height));
+    }
+    Ok(tile)
+  }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TileOrder {
+  Scanline, // rows of tiles, top to bottom
+  Spiral, // rings of tiles around the centre, inside out
+  Hilbert, // along a Hilbert curve, so consecutive tiles are neighbours
+}
+
+pub const TILE_ORDER_NAMES: [&str; 3] = ["scanline", "spiral", "hilbert"];
+
+impl TileOrder {
+  pub fn from_name(name: &str) -> Option<Self> {
+    match name {
+      "scanline" => Some(Self::Scanline),
+      "spiral" => Some(Self::Spiral),
+      "hilbert" => Some(Self::Hilbert),
+      _ => None,
+    }
+  }
+}
+
+// index of (x, y) along the Hilbert curve filling an n x n grid, n a power of two
+fn hilbert_index(n: usize, mut x: usize, mut y: usize) -> usize {
+  let mut d = 0;
+  let mut s = n / 2;
+  while s > 0 {
+    let rx = (x & s > 0) as usize;
+    let ry = (y & s > 0) as usize;
+    d += s * s * ((3 * rx) ^ ry);
+    if ry == 0 { // rotate the quadrant
+      if rx == 1 {
+        x = s - 1 - (x & (s - 1));
+        y = s - 1 - (y & (s - 1));
+      }
+      std::mem::swap(&mut x, &mut y);
+    }
+    x &= s - 1;
+    y &= s - 1;
+    s /= 2;
+  }
+  d
+}
+
+// Cut `region` of the image into tile_size x tile_size tiles (smaller at the right and bottom edges).
+pub fn make_tiles(region: &Tile, tile_size: usize, order: TileOrder) -> Vec<Tile> {
+  let tile_size = tile_size.max(1);
+  let tiles_x = (region.width() + tile_size - 1) / tile_size;
+  let tiles_y = (region.height() + tile_size - 1) / tile_size;
+
+  let mut coords: Vec<(usize, usize)> = (0..tiles_y).flat_map(|ty| (0..tiles_x).map(move |tx| (tx, ty))).collect();
+  match order {
+    TileOrder::Scanline => {}
+    TileOrder::Spiral => {
+      let (cx, cy) = ((tiles_x as f64 - 1.0) / 2.0, (tiles_y as f64 - 1.0) / 2.0);
+      let key = |&(tx, ty): &(usize, usize)| {
+        let (dx, dy) = (tx as f64 - cx, ty as f64 - cy);
+        let ring = dx.abs().max(dy.abs()).ceil();
+        (ring, dy.atan2(dx)) // ring first, then clockwise (y points down) within the ring
+      };
+      coords.sort_by(|a, b| key(a).partial_cmp(&key(b)).unwrap());
+    }
+    TileOrder::Hilbert => {
+      let n = tiles_x.max(tiles_y).next_power_of_two();
+      coords.sort_by_key(|&(tx, ty)| hilbert_index(n, tx, ty));
+    }
+  }
+
+  coords.into_iter().map(|(tx, ty)| Tile {
+    x_min: region.x_min + tx * tile_size,
+    x_max: (region.x_min + (tx + 1) * tile_size).min(region.x_max),
+    y_min: region.y_min + ty * tile_size,
+    y_max: (region.y_min + (ty + 1) * tile_size).min(region.y_max),
+  }).collect()
+}
+
+
+#[cfg(test)]
+mod tests {
+  use crate::*;
+
+  #[test]
+  fn test_tiles_cover_image() {
+    for order in [TileOrder::Scanline, TileOrder::Spiral, TileOrder::Hilbert] {
+      let tiles = make_tiles(&Tile::full(70, 45), 16, order);
+      assert_eq!(tiles.len(), 5 * 3);
+      let mut covered = vec![0; 70 * 45];
+      for tile in &tiles {
+        for j in tile.y_min..tile.y_max {
+          for i in tile.x_min..tile.x_max {
+            covered[j * 70 + i] += 1;
+          }
+        }
+      }
+      assert!(covered.iter().all(|&c| c == 1));
+    }
+
+    let spiral = make_tiles(&Tile::full(48, 48), 16, TileOrder::Spiral);
+    assert_eq!((spiral[0].x_min, spiral[0].y_min), (16, 16)); // centre first
+
+    let hilbert = make_tiles(&Tile::full(64, 64), 16, TileOrder::Hilbert);
+    for pair in hilbert.windows(2) { // every step moves to an adjacent tile
+      let dx = (pair[0].x_min as i64 - pair[1].x_min as i64).abs();
+      let dy = (pair[0].y_min as i64 - pair[1].y_min as i64).abs();
+      assert_eq!(dx + dy, 16);
+    }
+  }
+
+  #[test]
+  fn test_crop_window() {
+    let crop = CropWindow::parse("10, 20,110,70").unwrap();
+    assert_eq!(crop.resolve(200, 100).unwrap(), Tile { x_min: 10, x_max: 110, y_min: 20, y_max: 70 });
+    assert_eq!(crop.resolve(50, 100).unwrap(), Tile { x_min: 10, x_max: 50, y_min: 20, y_max: 70 }); // clipped
+    assert!(crop.resolve(5, 100).is_err());
+
+    let crop = CropWindow::parse("0.5,0,1.0,0.25").unwrap();
+    assert_eq!(crop.resolve(200, 100).unwrap(), Tile { x_min: 100, x_max: 200, y_min: 0, y_max: 25 });
+
+    assert!(CropWindow::parse("1,2,3").is_err());
+    assert!(CropWindow::parse("0.5,0,1.5,1").is_err());
+    assert!(CropWindow::parse("-1,0,10,10").is_err());
+
+    let tiles = make_tiles(&Tile { x_min: 10, x_max: 50, y_min: 5, y_max: 20 }, 16, TileOrder::Scanline);
+    assert_eq!(tiles, vec![
+      Tile { x_min: 10, x_max: 26, y_min: 5, y_max: 20 },
+      Tile { x_min: 26, x_max: 42, y_min: 5, y_max: 20 },
+      Tile { x_min: 42, x_max: 50, y_min: 5, y_max: 20 },
+    ]);
+  }
+}
@@ -0,0 +1,156 @@
+// Tone mapping: linear radiance -> display range [0, 1] before the transfer function and quantization.
+// Only the 8/16-bit outputs go through this; .hdr/.exr keep the raw radiance.
+
+use crate::color::{*};
+use crate::framebuffer::{*};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ToneMapOperator {
+  Clamp, // the original behaviour: anything above 1 is white
+  Reinhard, // L / (1 + L), on luminance to keep the hue
+  ReinhardExtended { white: Option<f64> }, // white: smallest luminance mapped to 1 (default: the brightest pixel)
+  Aces, // Narkowicz's fit of the ACES filmic curve, per channel
+}
+
+pub const TONE_MAP_NAMES: [&str; 4] = ["clamp", "reinhard", "reinhard-extended", "aces"];
+
+impl ToneMapOperator {
+  pub fn from_name(name: &str, white: Option<f64>) -> Option<Self> {
+    match name {
+      "clamp" => Some(Self::Clamp),
+      "reinhard" => Some(Self::Reinhard),
+      "reinhard-extended" => Some(Self::ReinhardExtended { white }),
+      "aces" => Some(Self::Aces),
+      _ => None,
+    }
+  }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ToneMapping {
+  pub operator: ToneMapOperator,
+  pub exposure: f64, // in stops, applied on top of the automatic exposure
+  pub auto_exposure: bool, // scale the log-average luminance to middle grey
+}
+
+const MIDDLE_GREY: f64 = 0.18;
+
+// exp(mean(log(delta + L))) over all pixels (Reinhard et al. 2002)
+pub fn log_average_luminance(img: &FrameBuffer) -> f64 {
+  let delta = 1e-4;
+  let mut sum = 0.0;
+  for j in 0..img.height {
+    for i in 0..img.width {
+      sum += (delta + luminance(img.get(i, j)).max(0.0)).ln();
+    }
+  }
+  (sum / (img.width * img.height).max(1) as f64).exp()
+}
+
+fn scale_luminance(color: ColorType, l_in: f64, l_out: f64) -> ColorType {
+  if l_in <= 0.0 { ColorType::zero() } else { color * (l_out / l_in) }
+}
+
+fn aces(x: f64) -> f64 {
+  let x = x.max(0.0);
+  ((x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14)).min(1.0) // the fit overshoots 1 slightly
+}
+
+impl ToneMapping {
+  pub fn default() -> Self {
+    Self {
+      operator: ToneMapOperator::Clamp,
+      exposure: 0.0,
+      auto_exposure: false,
+    }
+  }
+
+  // linear multiplier applied to the radiance before the operator
+  pub fn exposure_scale(&self, img: &FrameBuffer) -> f64 {
+    let mut scale = (2.0 as f64).powf(self.exposure);
+    if self.auto_exposure {
+      scale *= MIDDLE_GREY / log_average_luminance(img);
+    }
+    scale
+  }
+
+  pub fn map_color(&self, color: ColorType, white: f64) -> ColorType {
+    match self.operator {
+      ToneMapOperator::Clamp => color,
+      ToneMapOperator::Reinhard => {
+        let l = luminance(color);
+        scale_luminance(color, l, l / (1.0 + l))
+      }
+      ToneMapOperator::ReinhardExtended { .. } => {
+        let l = luminance(color);
+        scale_luminance(color, l, l * (1.0 + l / (white * white)) / (1.0 + l))
+      }
+      ToneMapOperator::Aces => ColorType::new(aces(color.x), aces(color.y), aces(color.z)),
+    }
+  }
+
+  // returns a display-referred copy of `img` (still linear; the transfer function is applied when quantizing)
+  pub fn apply(&self, img: &FrameBuffer) -> FrameBuffer {
+    let scale = self.exposure_scale(img);
+    let white = match self.operator {
+      ToneMapOperator::ReinhardExtended { white: Some(white) } => white,
+      _ => {
+        let mut max_l: f64 = 0.0;
+        for j in 0..img.height {
+          for i in 0..img.width {
+            max_l = max_l.max(luminance(img.get(i, j) * scale));
+          }
+        }
+        max_l.max(1e-4)
+      }
+    };
+
+    let mut display = FrameBuffer::new(img.width, img.height);
+    for j in 0..img.height {
+      for i in 0..img.width {
+        display.add_sample(i, j, self.map_color(img.get(i, j) * scale, white));
+      }
+    }
+    display
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use crate::*;
+
+  #[test]
+  fn test_operators() {
+    let grey = ColorType::ones();
+    let mut tm = ToneMapping::default();
+    assert_eq!(tm.map_color(grey * 4.0, 1.0), grey * 4.0);
+
+    tm.operator = ToneMapOperator::Reinhard;
+    assert!((tm.map_color(grey, 1.0) - grey * 0.5).near_zero());
+
+    tm.operator = ToneMapOperator::ReinhardExtended { white: None };
+    assert!((tm.map_color(grey * 8.0, 8.0) - grey).near_zero()); // white maps to 1
+
+    tm.operator = ToneMapOperator::Aces;
+    let mut last = 0.0;
+    for k in 1..100 {
+      let v = tm.map_color(grey * (k as f64 * 0.5), 1.0).x;
+      assert!(v >= last && v <= 1.0);
+      last = v;
+    }
+  }
+
+  #[test]
+  fn test_auto_exposure() {
+    let mut img = FrameBuffer::new(4, 4);
+    for j in 0..4 {
+      for i in 0..4 {
+        img.add_sample(i, j, ColorType::ones() * 3.0);
+      }
+    }
+    let tm = ToneMapping { operator: ToneMapOperator::Clamp, exposure: 1.0, auto_exposure: true };
+    let display = tm.apply(&img);
+    assert!((display.get(1, 2).x - 0.36).abs() < 1e-3); // middle grey, one stop up
+  }
+}
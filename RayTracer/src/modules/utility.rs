@@ -4,7 +4,9 @@ use indicatif::{ProgressBar, ProgressStyle};
 use std::fs::File;
 use std::env;
 use std::io;
-use rand::Rng;
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+use std::cell::RefCell;
 use nalgebra::Vector3;
 use opencv::core::VecN;
 
@@ -12,15 +14,27 @@ pub fn degrees_to_radians(degrees: f64) -> f64 {
   degrees * PI / 180.0
 }
 
+thread_local! {
+    // set by seed_rng(); threads that were never seeded fall back to thread_rng()
+    static SEEDED_RNG: RefCell<Option<StdRng>> = RefCell::new(None);
+}
+
+// Make rand_01/rand_range reproducible on the calling thread (used for scene construction, and by
+// Camera::render_sub before every sample).
+pub fn seed_rng(seed: u64) {
+    SEEDED_RNG.with(|rng| *rng.borrow_mut() = Some(StdRng::seed_from_u64(seed)));
+}
+
 pub fn rand_01() -> f64{
-    let mut rng = rand::thread_rng();
-    rng.gen_range(0.0..1.0)
+    rand_range(0.0, 1.0)
 }
 
 // [min, max)
 pub fn rand_range(min: f64, max: f64) -> f64{
-    let mut rng = rand::thread_rng();
-    rng.gen_range(min..max)
+    SEEDED_RNG.with(|rng| match rng.borrow_mut().as_mut() {
+        Some(rng) => rng.gen_range(min..max),
+        None => rand::thread_rng().gen_range(min..max),
+    })
 }
 
 pub fn rand_range_int(min: i32, max: i32) -> i32 {